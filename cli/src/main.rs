@@ -0,0 +1,337 @@
+mod options;
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::Arc;
+use anyhow::{anyhow, bail, Context};
+use clap::{Parser, Subcommand};
+use common::filter::{FieldSource, Filter};
+use common::layout::LayoutRegistry;
+use common::mongodb_collection::krx_msg::json_reader::KrxMsgJsonReader;
+use common::packet::packet_extractor::{matches_header, PacketExtractor};
+use common::packet::splitter::PcapSplitter;
+use common::validation::Validator;
+use common::KrxMsg;
+use dw::eda::EdaCollector;
+use dw::payload_field::{LoadFromCsv, PayloadField};
+use dw::payload_parser::{parse_data, FieldLayout, ParsedValue};
+use options::Options;
+
+/// KRX capture workflows: filter, export, inspect and split captures or KrxMsg JSON dumps
+#[derive(Parser, Debug)]
+#[command(name = "krx", version)]
+struct Cli {
+    /// JSON file with defaults for the options below
+    #[arg(long, global = true)]
+    config: Option<String>,
+    #[command(flatten)]
+    options: Options,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Writes the packets carrying matching messages to --output as nanosecond pcap
+    Filter {
+        /// Scans a classic pcap with this many threads
+        #[arg(long)]
+        threads: Option<usize>,
+        /// The capture is sorted by timestamp, so --start/--end are sought instead of scanning the whole file
+        #[arg(long)]
+        sorted: bool,
+    },
+    /// Writes the matching messages to --output as JSON documents, one per line (mongoimport, KrxMsgJsonReader)
+    ExtractJson,
+    /// Counts, rates, payload lengths and field fill: CSV tables and report.json in the --output directory,
+    /// JSON on stdout without one. Field fill covers the trcodes of --layouts and --spec.
+    Stats {
+        /// TRCODE=FILE, spec CSV of a trcode (repeatable)
+        #[arg(long)]
+        spec: Vec<String>,
+    },
+    /// Prints the matching messages
+    Dump {
+        #[arg(long)]
+        limit: Option<usize>,
+    },
+    /// Splits a capture by the --output template, with {trcode}, {family} and {instcode} placeholders
+    Split {
+        #[arg(long)]
+        max_open_files: Option<usize>,
+    },
+    /// Prints the spec fields of the messages of one trcode
+    Decode {
+        /// Spec CSV (EUC-KR, optionally compressed), the trcode's layout in --layouts by default
+        #[arg(long)]
+        spec: Option<String>,
+        #[arg(long)]
+        trcode: String,
+        #[arg(long)]
+        limit: Option<usize>,
+    },
+}
+
+type KrxMsgs = Box<dyn Iterator<Item = io::Result<KrxMsg>>>;
+
+fn main() -> anyhow::Result<()> {
+    run(Cli::parse(), &mut io::stdout().lock())
+}
+
+fn run(cli: Cli, out: &mut dyn Write) -> anyhow::Result<()> {
+    let mut options = match cli.config {
+        Some(ref config) => cli.options.or(Options::load(config)?),
+        None => cli.options,
+    };
+    let mut registry = match options.layouts {
+        Some(ref dir) => LayoutRegistry::load_dir(dir).with_context(|| format!("Failed to load layouts from {}", dir))?,
+        None => LayoutRegistry::new(),
+    };
+
+    match cli.command {
+        Command::Filter { threads, sorted } => {
+            if options.is_json_input() {
+                bail!("filter reads captures, use extract-json for JSON dumps");
+            }
+            if options.quarantine.is_some() {
+                bail!("filter writes whole packets and does not validate, --quarantine takes extract-json, stats, dump or decode");
+            }
+            let mut extractor = extractor(&options, &registry, options.output()?, filter(&options, field_source(&registry))?)?;
+            if sorted {
+                extractor = extractor.with_sorted_input();
+            }
+            let summary = match threads {
+                Some(threads) => extractor.filter_packets_parallel(threads)?,
+                None => extractor.filter_packets_with_header()?,
+            };
+            writeln!(out, "{}", serde_json::to_string_pretty(&summary)?)?;
+        },
+        Command::ExtractJson => {
+            let output = options.output()?;
+            let mut writer = BufWriter::new(File::create(output).with_context(|| format!("Failed to create {}", output))?);
+            let mut written = 0u64;
+            for msg in krx_msgs(&options, &registry, filter(&options, field_source(&registry))?)? {
+                serde_json::to_writer(&mut writer, &msg?.to_extended_json()?)?;
+                writer.write_all(b"\n")?;
+                written += 1;
+            }
+            writer.flush()?;
+            writeln!(out, "{} messages written to {}", written, output)?;
+        },
+        Command::Stats { spec } => {
+            for spec in spec.iter() {
+                let (trcode, path) = spec
+                    .split_once('=')
+                    .ok_or_else(|| anyhow!("--spec takes TRCODE=FILE, got {}", spec))?;
+                registry.insert(trcode, load_spec(path)?);
+            }
+            let collector = EdaCollector::new().with_layouts(&registry);
+            let report = collector.collect(krx_msgs(&options, &registry, filter(&options, field_source(&registry))?)?)?;
+            match options.output {
+                Some(ref dir) => {
+                    report.write_csv(dir)?;
+                    report.write_json(Path::new(dir).join("report.json"))?;
+                },
+                None => writeln!(out, "{}", serde_json::to_string_pretty(&report)?)?,
+            }
+        },
+        Command::Dump { limit } => {
+            for msg in krx_msgs(&options, &registry, filter(&options, field_source(&registry))?)?.take(limit.unwrap_or(usize::MAX)) {
+                writeln!(out, "{}", msg?)?;
+            }
+        },
+        Command::Split { max_open_files } => {
+            if options.is_json_input() || options.filter.is_some() || options.time_window()?.is_some() || options.quarantine.is_some() {
+                bail!("split takes a capture and --trcodes only");
+            }
+            let mut splitter = PcapSplitter::new(options.input()?.to_string(), options.output()?.to_string(), options.trcodes.clone());
+            if let Some(max_open_files) = max_open_files {
+                splitter = splitter.with_max_open_files(max_open_files);
+            }
+            if !registry.is_empty() {
+                splitter = splitter.with_layouts(&registry);
+            }
+            writeln!(out, "{}", serde_json::to_string_pretty(&splitter.split()?)?)?;
+        },
+        Command::Decode { spec, trcode, limit } => {
+            let fields = match spec {
+                Some(ref spec) => load_spec(spec)?,
+                None => registry
+                    .get(trcode.as_bytes())
+                    .map(|layout| layout.fields().to_vec())
+                    .ok_or_else(|| anyhow!("No spec for {}, give --spec or --layouts", trcode))?,
+            };
+            let layout = Arc::new(FieldLayout::new(&trcode, fields.clone()));
+            if options.trcodes.is_none() {
+                options.trcodes = Some(vec![trcode.clone()]);
+            }
+            let msgs = krx_msgs(&options, &registry, filter(&options, Some(layout))?)?
+                .filter(|msg| msg.as_ref().map_or(true, |msg| msg.trcode() == Some(trcode.as_str())))
+                .take(limit.unwrap_or(usize::MAX));
+            for msg in msgs {
+                let msg = msg?;
+                writeln!(out, "{}", msg)?;
+                for field in fields.iter() {
+                    let value = match msg.payload.get(field.start_point as usize..field.cumulative_length as usize) {
+                        Some(data) => match parse_data(data, &field.data_type) {
+                            ParsedValue::Double(v) => v.to_string(),
+                            ParsedValue::Integer(v) => v.to_string(),
+                            ParsedValue::Text(v) => v.trim().to_string(),
+                        },
+                        None => "(short payload)".to_string(),
+                    };
+                    writeln!(out, "  {:<40} {}", field.item_name, value)?;
+                }
+            }
+        },
+    }
+    Ok(())
+}
+
+fn load_spec(path: &str) -> anyhow::Result<Vec<PayloadField>> {
+    PayloadField::load_from_csv(path).with_context(|| format!("Failed to load spec {}", path))
+}
+
+fn field_source(registry: &LayoutRegistry) -> Option<Arc<dyn FieldSource>> {
+    (!registry.is_empty()).then(|| Arc::new(FieldLayout::from_registry(registry.clone())) as Arc<dyn FieldSource>)
+}
+
+fn filter(options: &Options, fields: Option<Arc<dyn FieldSource>>) -> anyhow::Result<Option<Filter>> {
+    let Some(ref expression) = options.filter else {
+        return Ok(None);
+    };
+    let filter = Filter::parse(expression)?;
+    match fields {
+        Some(fields) => Ok(Some(filter.with_fields(fields)?)),
+        None if !filter.field_names().is_empty() => bail!("field(\"...\") in --filter needs a spec (--layouts, or --spec of decode and stats)"),
+        None => Ok(Some(filter)),
+    }
+}
+
+/// Offsets and message lengths of the trcodes in the registry come from their specs
+fn extractor(options: &Options, registry: &LayoutRegistry, output: &str, filter: Option<Filter>) -> anyhow::Result<PacketExtractor> {
+    let mut extractor = PacketExtractor::new(options.input()?.to_string(), output.to_string(), options.trcodes.clone());
+    if !registry.is_empty() {
+        extractor = extractor.with_layouts(registry);
+    }
+    if let Some(time_window) = options.time_window()? {
+        extractor = extractor.with_time_window(time_window);
+    }
+    if let Some(filter) = filter {
+        extractor = extractor.with_filter(filter);
+    }
+    Ok(extractor)
+}
+
+/// Messages of the input passing the header filter, the time window and the filter expression.
+/// A JSON dump is windowed on `packet_timestamp`.
+fn krx_msgs(options: &Options, registry: &LayoutRegistry, filter: Option<Filter>) -> anyhow::Result<KrxMsgs> {
+    if !options.is_json_input() {
+        let date = options.date()?;
+        let mut extractor = extractor(options, registry, "", filter)?;
+        if let Some(ref quarantine) = options.quarantine {
+            extractor = extractor.with_validator(Validator::new(registry.clone())).with_quarantine(quarantine.clone());
+        }
+        return Ok(Box::new(extractor.krx_msgs(date)?));
+    }
+    if options.quarantine.is_some() {
+        bail!("--quarantine takes a capture");
+    }
+
+    let input = options.input()?;
+    let mut reader = KrxMsgJsonReader::from_file(input).with_context(|| format!("Failed to open {}", input))?;
+    if let Some(filter) = filter {
+        reader = reader.with_filter(filter);
+    }
+    let header_filter = options.trcodes.clone();
+    let time_window = options.time_window()?;
+    Ok(Box::new(reader.filter(move |msg| match msg {
+        Ok(msg) => {
+            matches_header(&header_filter, &msg.payload)
+                && time_window.is_none_or(|window| msg.packet_timestamp.is_some_and(|timestamp| window.contains(timestamp)))
+        },
+        Err(_) => true,
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::UnixNano;
+    use dw::feed_generator::{b606f_payload, FeedGenerator, PayloadBuilder};
+
+    const SPEC: &str = "../spec/data/BF606F_new.7z";
+
+    fn krx(args: &[&str]) -> anyhow::Result<String> {
+        let mut out = Vec::new();
+        run(Cli::try_parse_from(std::iter::once("krx").chain(args.iter().copied()))?, &mut out)?;
+        Ok(String::from_utf8(out)?)
+    }
+
+    #[test]
+    fn test_subcommands() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir().join("krx_cli_test");
+        std::fs::create_dir_all(&dir)?;
+        let pcap = dir.join("in.pcap");
+        let fields = PayloadField::load_from_csv(SPEC)?;
+        let mut generator = FeedGenerator::create(&pcap)?;
+        for i in 0..10 {
+            let payload = b606f_payload(&fields, i, &format!("KR416{}N30007", i % 2), 100.0 + i as f64)?.build();
+            // one message per second from 10:20:00 KST
+            generator.write(1_727_400_000_000_000_000 + i as UnixNano * 1_000_000_000, &payload);
+        }
+        generator.finish()?;
+
+        let (pcap, json) = (pcap.to_str().unwrap(), dir.join("out.json"));
+        let config = dir.join("config.json");
+        std::fs::write(&config, format!(r#"{{"input": {:?}, "date": 20240927, "trcodes": ["B606F"]}}"#, pcap))?;
+        let config = config.to_str().unwrap();
+
+        krx(&["extract-json", "--config", config, "-o", json.to_str().unwrap(), "--start", "10:20:02", "--end", "10:20:08"])?;
+        let msgs = KrxMsgJsonReader::from_file(&json)?.collect::<io::Result<Vec<_>>>()?;
+        assert_eq!(msgs.iter().map(|msg| msg.distidx).collect::<Vec<_>>(), (2..8).map(Some).collect::<Vec<_>>());
+
+        let report: serde_json::Value = serde_json::from_str(&krx(&["stats", "-i", json.to_str().unwrap(), "--filter", r#"instcode ^= "KR4161""#])?)?;
+        assert_eq!(report["messages"], 3);
+        assert_eq!(report["per_second"]["peak"]["count"], 1);
+
+        let decoded = krx(&["decode", "--config", config, "--spec", SPEC, "--trcode", "B606F", "--limit", "2", "--filter", r#"field("Ask Level 1 price") >= 105"#])?;
+        assert!(decoded.contains("KR4161N30007") && decoded.contains("105") && decoded.contains("106"));
+        assert_eq!(decoded.matches("ISIN Code").count(), 2);
+
+        assert!(krx(&["dump", "--config", config, "--filter", r#"field("Ask Level 1 price") > 1"#]).is_err());
+        let layouts = dir.join("layouts");
+        std::fs::create_dir_all(&layouts)?;
+        std::fs::copy(SPEC, layouts.join("B606F.7z"))?;
+        let layouts = layouts.to_str().unwrap();
+        let dumped = krx(&["dump", "--config", config, "--layouts", layouts, "--filter", r#"field("Ask Level 1 price") > 107"#])?;
+        assert_eq!(dumped.matches("trcode: B606F").count(), 2);
+        let decoded = krx(&["decode", "--config", config, "--layouts", layouts, "--trcode", "B606F", "--limit", "1"])?;
+        assert!(decoded.contains("KR4160N30007"));
+
+        // a message cut short and one with a letter in a price go to the quarantine
+        let bad = dir.join("bad.pcap");
+        let mut generator = FeedGenerator::create(&bad)?;
+        let payload = PayloadBuilder::new(&fields).trcode("B606F")?.build();
+        generator.write(1_727_400_000_000_000_000, &payload);
+        generator.write(1_727_400_000_000_000_001, &[&payload[..300], &[0xff]].concat());
+        let mut letter = payload.clone();
+        letter[50] = b'X';
+        generator.write(1_727_400_000_000_000_002, &letter);
+        generator.finish()?;
+        let quarantine = dir.join("quarantine.json");
+        let args = ["-i", bad.to_str().unwrap(), "--date", "20240927", "-o", json.to_str().unwrap()];
+        krx(&[&["extract-json", "--layouts", layouts, "--quarantine", quarantine.to_str().unwrap()], &args[..]].concat())?;
+        assert_eq!(KrxMsgJsonReader::from_file(&json)?.count(), 1);
+        let quarantined = std::fs::read_to_string(&quarantine)?;
+        assert!(quarantined.contains("Message length 301 (expected 324)"));
+        assert!(quarantined.contains("Ask Level 1 price is not numeric"));
+        assert!(krx(&["dump", "-i", json.to_str().unwrap(), "--quarantine", quarantine.to_str().unwrap()]).is_err());
+        assert!(krx(&[&["filter", "--quarantine", quarantine.to_str().unwrap()], &args[..]].concat()).is_err());
+
+        assert!(krx(&["dump", "-i", pcap]).is_err());
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
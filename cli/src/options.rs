@@ -0,0 +1,130 @@
+use std::path::Path;
+use anyhow::{anyhow, bail, Context};
+use clap::Args;
+use serde::Deserialize;
+use common::packet::time_window::{kst_to_unix_nano, TimeWindow};
+use common::UnixNano;
+
+/// Options shared by the subcommands. Each one can also come from the `--config` JSON file,
+/// e.g., `{"input": "D:/DATA/koscom_udp_2024-09-27.pcap", "date": 20240927, "trcodes": ["B606F", "A301K"], "start": "08:45"}`,
+/// and the command line wins over the file.
+#[derive(Args, Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Options {
+    /// pcap / pcapng (optionally compressed) or a KrxMsg JSON dump (*.json, *.json.gz, ...)
+    #[arg(short, long, global = true)]
+    pub input: Option<String>,
+    /// Output file, directory or template depending on the subcommand
+    #[arg(short, long, global = true)]
+    pub output: Option<String>,
+    /// Header filter, e.g., B606F,A301K
+    #[arg(long, global = true, value_delimiter = ',')]
+    pub trcodes: Option<Vec<String>>,
+    /// Filter expression, e.g., 'instcode ^= "KR4165" and distidx in 100..200'
+    #[arg(long, global = true)]
+    pub filter: Option<String>,
+    /// yyyymmdd, stamped on the messages read from a capture and used for KST times
+    #[arg(long, global = true)]
+    pub date: Option<i32>,
+    /// Start of the time window (inclusive), KST HH:MM[:SS[.fraction]] on --date or UnixNano
+    #[arg(long, global = true)]
+    pub start: Option<String>,
+    /// End of the time window (exclusive), same format as --start
+    #[arg(long, global = true)]
+    pub end: Option<String>,
+    /// Directory of spec CSVs named after their trcode (e.g., B606F.csv), for instcode/distidx offsets
+    /// and field("...") in --filter
+    #[arg(long, global = true)]
+    pub layouts: Option<String>,
+    /// Messages failing validation (spec length, End Keyword, field characters, trcode) are written to
+    /// this JSON lines file instead of being analyzed. Commands reading messages only, not filter or split
+    #[arg(long, global = true)]
+    pub quarantine: Option<String>,
+}
+
+impl Options {
+    pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let file = std::fs::File::open(path).with_context(|| format!("Failed to open config {}", path.display()))?;
+        serde_json::from_reader(std::io::BufReader::new(file)).with_context(|| format!("Invalid config {}", path.display()))
+    }
+
+    /// Fills what is not given on the command line from `config`
+    pub fn or(self, config: Options) -> Self {
+        Options {
+            input: self.input.or(config.input),
+            output: self.output.or(config.output),
+            trcodes: self.trcodes.or(config.trcodes),
+            filter: self.filter.or(config.filter),
+            date: self.date.or(config.date),
+            start: self.start.or(config.start),
+            end: self.end.or(config.end),
+            layouts: self.layouts.or(config.layouts),
+            quarantine: self.quarantine.or(config.quarantine),
+        }
+    }
+
+    pub fn input(&self) -> anyhow::Result<&str> {
+        self.input.as_deref().ok_or_else(|| anyhow!("--input is required"))
+    }
+
+    pub fn output(&self) -> anyhow::Result<&str> {
+        self.output.as_deref().ok_or_else(|| anyhow!("--output is required"))
+    }
+
+    pub fn date(&self) -> anyhow::Result<i32> {
+        self.date.ok_or_else(|| anyhow!("--date (yyyymmdd) is required"))
+    }
+
+    /// `mongoexport` dumps are told apart from captures by name
+    pub fn is_json_input(&self) -> bool {
+        self.input.as_deref().is_some_and(|input| input.contains(".json"))
+    }
+
+    pub fn time_window(&self) -> anyhow::Result<Option<TimeWindow>> {
+        if self.start.is_none() && self.end.is_none() {
+            return Ok(None);
+        }
+        let start = self.start.as_deref().map(|time| self.time(time)).transpose()?;
+        let end = self.end.as_deref().map(|time| self.time(time)).transpose()?;
+        Ok(Some(TimeWindow::new(start, end)))
+    }
+
+    fn time(&self, time: &str) -> anyhow::Result<UnixNano> {
+        if !time.is_empty() && time.bytes().all(|b| b.is_ascii_digit()) {
+            return Ok(time.parse()?);
+        }
+        if self.date.is_none() {
+            bail!("--date is required for the KST time {}", time);
+        }
+        kst_to_unix_nano(self.date()?, time).map_err(|e| anyhow!(e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_and_time_window() -> anyhow::Result<()> {
+        let config: Options = serde_json::from_str(r#"{"input": "a.pcap", "date": 20240927, "trcodes": ["B606F"], "start": "10:20"}"#)?;
+        let options = Options {
+            input: Some("b.pcap".to_string()),
+            end: Some("1727400060000000000".to_string()),
+            ..Options::default()
+        }
+        .or(config);
+        assert_eq!(options.input()?, "b.pcap");
+        assert_eq!(options.trcodes, Some(vec!["B606F".to_string()]));
+        assert_eq!(
+            options.time_window()?,
+            Some(TimeWindow::new(Some(1_727_400_000_000_000_000), Some(1_727_400_060_000_000_000)))
+        );
+        assert!(options.output().is_err());
+
+        let options = Options { start: Some("10:20".to_string()), ..Options::default() };
+        assert!(options.time_window().is_err());
+        assert!(serde_json::from_str::<Options>(r#"{"inptu": "a.pcap"}"#).is_err());
+        Ok(())
+    }
+}
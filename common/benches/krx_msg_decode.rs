@@ -0,0 +1,60 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use common::{KrxMsg, KrxMsgRef};
+
+const NUM_MESSAGES: usize = 100_000;
+
+/// B606F and A301K messages in turn, a quarter of them B606F
+fn payloads() -> Vec<Vec<u8>> {
+    (0..NUM_MESSAGES)
+        .map(|i| {
+            let trcode = if i % 4 == 0 { "B606F" } else { "A301K" };
+            let mut payload = format!("{}{:08}G1  KR4165N30007", trcode, i).into_bytes();
+            payload.resize(323, b' ');
+            payload.push(0xff);
+            payload
+        })
+        .collect()
+}
+
+fn krx_msg_decode(c: &mut Criterion) {
+    let payloads = payloads();
+
+    let mut group = c.benchmark_group("Decode 100k messages");
+    group.bench_function("KrxMsg::new_from_payload", |b| b.iter(|| {
+        for payload in payloads.iter() {
+            let msg = KrxMsg::new_from_payload(20240927, payload, Some(0), None).unwrap();
+            black_box((&msg.trcode, &msg.instcode, msg.distidx));
+        }
+    }));
+    group.bench_function("KrxMsgRef, trcode, instcode and distidx", |b| b.iter(|| {
+        for payload in payloads.iter() {
+            let msg = KrxMsgRef::new(20240927, payload, Some(0), None);
+            black_box((msg.trcode(), msg.instcode(), msg.distidx()));
+        }
+    }));
+    // the usual scan: most messages are dropped on the trcode, the rest are kept
+    group.bench_function("KrxMsgRef, owned only for B606F", |b| b.iter(|| {
+        let mut kept = Vec::new();
+        for payload in payloads.iter() {
+            let msg = KrxMsgRef::new(20240927, payload, Some(0), None);
+            if msg.trcode() == Some("B606F") {
+                kept.push(msg.to_krx_msg());
+            }
+        }
+        black_box(kept)
+    }));
+    group.bench_function("KrxMsg::new_from_payload, then B606F kept", |b| b.iter(|| {
+        let mut kept = Vec::new();
+        for payload in payloads.iter() {
+            let msg = KrxMsg::new_from_payload(20240927, payload, Some(0), None).unwrap();
+            if msg.trcode() == Some("B606F") {
+                kept.push(msg);
+            }
+        }
+        black_box(kept)
+    }));
+    group.finish();
+}
+
+criterion_group!(benches, krx_msg_decode);
+criterion_main!(benches);
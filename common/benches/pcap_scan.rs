@@ -0,0 +1,82 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use pcap::{Capture, Linktype, PacketHeader, Precision};
+use common::packet::decoder::decode_packet;
+use common::packet::packet_extractor::{matches_header, PacketExtractor};
+
+const NUM_PACKETS: usize = 200_000;
+
+fn udp_frame(payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0u8; 12];
+    frame.extend_from_slice(&[0x08, 0x00, 0x45, 0x00]);
+    frame.extend_from_slice(&((20 + 8 + payload.len()) as u16).to_be_bytes());
+    frame.extend_from_slice(&[0, 0, 0, 0, 64, 17, 0, 0, 10, 0, 0, 1, 233, 37, 54, 1]);
+    frame.extend_from_slice(&20000u16.to_be_bytes());
+    frame.extend_from_slice(&20001u16.to_be_bytes());
+    frame.extend_from_slice(&((8 + payload.len()) as u16).to_be_bytes());
+    frame.extend_from_slice(&[0, 0]);
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// B606F and A301K messages in turn, a quarter of them B606F
+fn write_capture(path: &std::path::Path) {
+    let cap = Capture::dead_with_precision(Linktype::ETHERNET, Precision::Nano).unwrap();
+    let mut savefile = cap.savefile(path).unwrap();
+    for i in 0..NUM_PACKETS {
+        let trcode = if i % 4 == 0 { "B606F" } else { "A301K" };
+        let mut payload = format!("{}{:08}G1  KR4165N30007", trcode, i).into_bytes();
+        payload.resize(323, b' ');
+        payload.push(0xff);
+        let frame = udp_frame(&payload);
+        let header = PacketHeader {
+            ts: libc::timeval { tv_sec: 1_727_400_000 + (i / 10_000) as i64, tv_usec: (i % 10_000) as i64 },
+            caplen: frame.len() as u32,
+            len: frame.len() as u32,
+        };
+        savefile.write(&pcap::Packet::new(&header, &frame));
+    }
+    savefile.flush().unwrap();
+}
+
+/// The loop `filter_packets_with_header` used before it read captures itself
+fn libpcap_filter(input: &str, output: &str, header_filter: &Option<Vec<String>>) {
+    let mut cap = Capture::from_file(input).unwrap();
+    let output_cap = Capture::dead_with_precision(cap.get_datalink(), Precision::Nano).unwrap();
+    let mut savefile = output_cap.savefile(output).unwrap();
+    let linktype = cap.get_datalink();
+    while let Ok(packet) = cap.next_packet() {
+        if let Ok(decoded) = decode_packet(linktype, packet.data) {
+            if matches_header(header_filter, decoded.payload) {
+                savefile.write(&packet);
+            }
+        }
+    }
+}
+
+fn pcap_scan(c: &mut Criterion) {
+    let dir = std::env::temp_dir();
+    let input = dir.join("pcap_scan_bench.pcap");
+    let output = dir.join("pcap_scan_bench_out.pcap");
+    write_capture(&input);
+    let input = input.to_str().unwrap().to_string();
+    let output = output.to_str().unwrap().to_string();
+    let header_filter = Some(vec!["B606F".to_string()]);
+    let extractor = PacketExtractor::new(input.clone(), output.clone(), header_filter.clone());
+
+    let mut group = c.benchmark_group("Filter 200k packets");
+    group.sample_size(10);
+    group.bench_function("libpcap next_packet", |b| b.iter(|| libpcap_filter(&input, &output, &header_filter)));
+    group.bench_function("CaptureReader, 1 thread", |b| b.iter(|| extractor.filter_packets_with_header()));
+    for num_threads in [2, 4, 8] {
+        group.bench_function(format!("mmap, {} threads", num_threads), |b| {
+            b.iter(|| extractor.filter_packets_parallel(num_threads))
+        });
+    }
+    group.finish();
+
+    let _ = std::fs::remove_file(&input);
+    let _ = std::fs::remove_file(&output);
+}
+
+criterion_group!(benches, pcap_scan);
+criterion_main!(benches);
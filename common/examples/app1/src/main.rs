@@ -21,7 +21,8 @@ fn main() -> anyhow::Result<()> {
         header_filter,
     );
 
-    packet_extractor.filter_packets_with_header();
+    let summary = packet_extractor.filter_packets_with_header()?;
+    println!("{:?}", summary);
 
     Ok(())
 }
@@ -0,0 +1,230 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+use std::sync::mpsc::{sync_channel, Receiver};
+use std::thread;
+use flate2::read::MultiGzDecoder;
+use sevenz_rust::{Password, SevenZReader};
+use spec::SEVENZ_MAGIC;
+use xz2::read::XzDecoder;
+
+const BUF_CAPACITY: usize = 1 << 20;
+
+/// 7z is decoded on its own thread and handed over in chunks of this size,
+/// at most `SEVENZ_CHANNEL_DEPTH` of them in flight
+const SEVENZ_CHUNK_LEN: usize = 1 << 16;
+const SEVENZ_CHANNEL_DEPTH: usize = 16;
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+const XZ_MAGIC: &[u8] = &[0xfd, b'7', b'z', b'X', b'Z', 0x00];
+const MAGIC_LEN: usize = 6;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+    Xz,
+    /// the first file of the archive is read
+    SevenZ,
+}
+
+impl Compression {
+    pub fn from_magic(bytes: &[u8]) -> Option<Self> {
+        [
+            (GZIP_MAGIC, Compression::Gzip),
+            (ZSTD_MAGIC, Compression::Zstd),
+            (XZ_MAGIC, Compression::Xz),
+            (SEVENZ_MAGIC, Compression::SevenZ),
+        ]
+        .into_iter()
+        .find(|(magic, _)| bytes.starts_with(magic))
+        .map(|(_, compression)| compression)
+    }
+
+    pub fn from_extension<P: AsRef<Path>>(path: P) -> Option<Self> {
+        let extension = path.as_ref().extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "gz" | "gzip" => Some(Compression::Gzip),
+            "zst" | "zstd" => Some(Compression::Zstd),
+            "xz" => Some(Compression::Xz),
+            "7z" => Some(Compression::SevenZ),
+            _ => None,
+        }
+    }
+
+    /// Magic bytes take precedence, the extension decides for files without a known magic
+    /// (so that a damaged `.gz` fails in the decoder rather than being read as plain data)
+    pub fn detect<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut magic = Vec::with_capacity(MAGIC_LEN);
+        File::open(path.as_ref())?.take(MAGIC_LEN as u64).read_to_end(&mut magic)?;
+        Ok(Compression::from_magic(&magic)
+            .or_else(|| Compression::from_extension(path.as_ref()))
+            .unwrap_or(Compression::None))
+    }
+}
+
+/// Opens `path` for reading, decompressing gzip, zstd, xz or 7z on the fly.
+/// Decompression is streaming, memory does not grow with the size of the file.
+pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Box<dyn BufRead + Send>> {
+    let path = path.as_ref();
+    let compression = Compression::detect(path)?;
+    if compression == Compression::SevenZ {
+        return Ok(Box::new(BufReader::with_capacity(BUF_CAPACITY, SevenZStream::open(path)?)));
+    }
+
+    let file = BufReader::with_capacity(BUF_CAPACITY, File::open(path)?);
+    Ok(match compression {
+        Compression::None => Box::new(file),
+        Compression::Gzip => Box::new(BufReader::with_capacity(BUF_CAPACITY, MultiGzDecoder::new(file))),
+        Compression::Zstd => Box::new(BufReader::with_capacity(BUF_CAPACITY, zstd::Decoder::with_buffer(file)?)),
+        Compression::Xz => Box::new(BufReader::with_capacity(BUF_CAPACITY, XzDecoder::new_multi_decoder(file))),
+        Compression::SevenZ => unreachable!(),
+    })
+}
+
+/// Decompresses data already in memory (e.g., embedded with `include_bytes!`), detected by its magic bytes.
+/// Data without a known magic is returned as it is.
+pub fn decompress(bytes: &[u8]) -> io::Result<Vec<u8>> {
+    let mut decompressed = Vec::new();
+    match Compression::from_magic(bytes).unwrap_or(Compression::None) {
+        Compression::None => decompressed.extend_from_slice(bytes),
+        Compression::Gzip => {
+            MultiGzDecoder::new(bytes).read_to_end(&mut decompressed)?;
+        },
+        Compression::Zstd => {
+            zstd::Decoder::new(bytes)?.read_to_end(&mut decompressed)?;
+        },
+        Compression::Xz => {
+            XzDecoder::new_multi_decoder(bytes).read_to_end(&mut decompressed)?;
+        },
+        Compression::SevenZ => decompressed = spec::un7z(bytes)?,
+    }
+    Ok(decompressed)
+}
+
+/// The first file of a 7z archive. sevenz-rust only decodes through a callback, so the callback
+/// runs on a thread and sends the data over a bounded channel.
+struct SevenZStream {
+    receiver: Receiver<io::Result<Vec<u8>>>,
+    chunk: Vec<u8>,
+    pos: usize,
+}
+
+impl SevenZStream {
+    fn open(path: &Path) -> io::Result<Self> {
+        let mut archive = SevenZReader::open(path, Password::empty()).map_err(sevenz_error)?;
+        let (sender, receiver) = sync_channel(SEVENZ_CHANNEL_DEPTH);
+        thread::spawn(move || {
+            let mut done = false;
+            let result = archive.for_each_entries(|entry, reader| {
+                if done || entry.is_directory() {
+                    return Ok(!done);
+                }
+                done = true;
+                let mut buf = vec![0u8; SEVENZ_CHUNK_LEN];
+                loop {
+                    let n = reader.read(&mut buf)?;
+                    // a closed channel means the reader was dropped
+                    if n == 0 || sender.send(Ok(buf[..n].to_vec())).is_err() {
+                        return Ok(false);
+                    }
+                }
+            });
+            let error = match result {
+                Err(e) => Some(sevenz_error(e)),
+                Ok(()) if !done => Some(io::Error::new(io::ErrorKind::InvalidData, "7z archive has no file")),
+                Ok(()) => None,
+            };
+            if let Some(error) = error {
+                let _ = sender.send(Err(error));
+            }
+        });
+        Ok(SevenZStream {
+            receiver,
+            chunk: Vec::new(),
+            pos: 0,
+        })
+    }
+}
+
+impl Read for SevenZStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.chunk.len() {
+            match self.receiver.recv() {
+                Ok(chunk) => {
+                    self.chunk = chunk?;
+                    self.pos = 0;
+                },
+                // the decoding thread finished
+                Err(_) => return Ok(0),
+            }
+        }
+        let n = buf.len().min(self.chunk.len() - self.pos);
+        buf[..n].copy_from_slice(&self.chunk[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+fn sevenz_error(error: sevenz_rust::Error) -> io::Error {
+    match error {
+        sevenz_rust::Error::Io(e, _) => e,
+        e => io::Error::new(io::ErrorKind::InvalidData, e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn contents() -> Vec<u8> {
+        (0..200_000u32).flat_map(|i| format!("B606F{:08}\n", i).into_bytes()).collect()
+    }
+
+    fn read_all(path: &Path) -> io::Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        open(path)?.read_to_end(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    #[test]
+    fn test_open_compressed() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir();
+        let contents = contents();
+
+        let plain = dir.join("compression_test.txt");
+        std::fs::write(&plain, &contents)?;
+
+        let gzip = dir.join("compression_test.txt.gz");
+        let mut encoder = flate2::write::GzEncoder::new(File::create(&gzip)?, flate2::Compression::default());
+        encoder.write_all(&contents)?;
+        encoder.finish()?;
+
+        // no extension: detected by magic bytes
+        let zstd = dir.join("compression_test_zstd");
+        zstd::stream::copy_encode(&contents[..], File::create(&zstd)?, 0)?;
+
+        let xz = dir.join("compression_test.txt.xz");
+        let mut encoder = xz2::write::XzEncoder::new(File::create(&xz)?, 6);
+        encoder.write_all(&contents)?;
+        encoder.finish()?;
+
+        let sevenz = dir.join("compression_test.7z");
+        sevenz_rust::compress_to_path(&plain, &sevenz).map_err(sevenz_error)?;
+
+        assert_eq!(Compression::detect(&plain)?, Compression::None);
+        assert_eq!(Compression::detect(&gzip)?, Compression::Gzip);
+        assert_eq!(Compression::detect(&zstd)?, Compression::Zstd);
+        assert_eq!(Compression::detect(&xz)?, Compression::Xz);
+        assert_eq!(Compression::detect(&sevenz)?, Compression::SevenZ);
+        for path in [&plain, &gzip, &zstd, &xz, &sevenz] {
+            assert_eq!(read_all(path)?, contents, "{}", path.display());
+            assert_eq!(decompress(&std::fs::read(path)?)?, contents, "{}", path.display());
+            std::fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
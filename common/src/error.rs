@@ -2,6 +2,14 @@
 pub enum Error {
     LengthMismatch,
     TimestampOrderMismatch,
+    /// the input could not be opened, e.g., missing file or unknown capture format
+    OpenInput { path: String, reason: String },
+    /// reading stopped before the end of the input, e.g., a truncated or corrupt record
+    ReadInput { path: String, reason: String },
+    CreateOutput { path: String, reason: String },
+    WriteOutput { path: String, reason: String },
+    /// worker threads could not be started
+    ThreadPool(String),
 }
 
 impl std::fmt::Display for Error {
@@ -9,8 +17,13 @@ impl std::fmt::Display for Error {
         match self {
             Error::LengthMismatch => write!(f, "Length mismatch"),
             Error::TimestampOrderMismatch => write!(f, "Timestamp order mismatch"),
+            Error::OpenInput { path, reason } => write!(f, "Cannot open input file {}: {}", path, reason),
+            Error::ReadInput { path, reason } => write!(f, "Cannot read input file {}: {}", path, reason),
+            Error::CreateOutput { path, reason } => write!(f, "Cannot create output file {}: {}", path, reason),
+            Error::WriteOutput { path, reason } => write!(f, "Cannot write output file {}: {}", path, reason),
+            Error::ThreadPool(reason) => write!(f, "Cannot start worker threads: {}", reason),
         }
     }
 }
 
-impl std::error::Error for Error {}
\ No newline at end of file
+impl std::error::Error for Error {}
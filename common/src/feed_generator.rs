@@ -0,0 +1,290 @@
+// Synthetic KRX feed: payloads laid out by `PayloadField`, wrapped in Ethernet/IPv4/UDP and written as pcap
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::path::Path;
+use encoding_rs::EUC_KR;
+use pcap::{Capture, Linktype, PacketHeader, Precision, Savefile};
+use crate::layout::PayloadField;
+use crate::UnixNano;
+
+const END_KEYWORD: u8 = 0xff;
+const TRCODE_LEN: usize = 5;
+
+const ETHERNET_HEADER_LEN: usize = 14;
+const IPV4_HEADER_LEN: usize = 20;
+const UDP_HEADER_LEN: usize = 8;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldValue {
+    Int(i64),
+    Double(f64),
+    Text(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GeneratorError {
+    UnknownField(String),
+    /// the value does not fit in the field
+    TooLong { field: String, length: usize, value: String },
+    /// e.g., a `Text` for an Int field
+    TypeMismatch { field: String, data_type: String },
+    InvalidTrcode(String),
+}
+
+impl std::fmt::Display for GeneratorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            GeneratorError::UnknownField(field) => write!(f, "Unknown field: {}", field),
+            GeneratorError::TooLong { field, length, value } => {
+                write!(f, "{} does not fit in {} ({} bytes)", value, field, length)
+            },
+            GeneratorError::TypeMismatch { field, data_type } => {
+                write!(f, "Value does not match {} ({})", field, data_type)
+            },
+            GeneratorError::InvalidTrcode(trcode) => write!(f, "Invalid trcode: {}", trcode),
+        }
+    }
+}
+
+impl std::error::Error for GeneratorError {}
+
+/// Builds one payload of a layout. Fields are named by `item_name` (e.g., "Ask Level 1 price").
+/// Unset fields are blank (String) or zero (Int, Double), the End Keyword field gets 0xFF.
+/// Int and Double are zero padded to the field length with a leading '-' if negative, Double
+/// keeps the decimals it needs (or the layout's decimal places) so that `parse_data` reads it back.
+pub struct PayloadBuilder<'a> {
+    fields: &'a [PayloadField],
+    index: HashMap<&'a str, usize>,
+    payload: Vec<u8>,
+}
+
+impl<'a> PayloadBuilder<'a> {
+    pub fn new(fields: &'a [PayloadField]) -> Self {
+        let length = fields.iter().map(|field| field.cumulative_length as usize).max().unwrap_or(0);
+        let mut payload = vec![b' '; length];
+        for field in fields.iter() {
+            let range = field.start_point as usize..field.cumulative_length as usize;
+            match field.data_type.as_str() {
+                "Int" | "Double" => payload[range].fill(b'0'),
+                _ if field.item_name == "End Keyword" => payload[range].fill(END_KEYWORD),
+                _ => {},
+            }
+        }
+        PayloadBuilder {
+            fields,
+            index: fields.iter().enumerate().map(|(i, field)| (field.item_name.as_str(), i)).collect(),
+            payload,
+        }
+    }
+
+    /// Data Category and Information Category together, e.g., B606F
+    pub fn trcode(mut self, trcode: &str) -> Result<Self, GeneratorError> {
+        if trcode.len() != TRCODE_LEN || !trcode.is_ascii() || self.payload.len() < TRCODE_LEN {
+            return Err(GeneratorError::InvalidTrcode(trcode.to_string()));
+        }
+        self.payload[..TRCODE_LEN].copy_from_slice(trcode.as_bytes());
+        Ok(self)
+    }
+
+    pub fn set(mut self, item_name: &str, value: FieldValue) -> Result<Self, GeneratorError> {
+        let field = self
+            .index
+            .get(item_name)
+            .map(|&i| &self.fields[i])
+            .ok_or_else(|| GeneratorError::UnknownField(item_name.to_string()))?;
+        let length = field.length as usize;
+        let decimals = field.sub_section.trim().parse::<usize>().unwrap_or(0);
+        let bytes = match (field.data_type.as_str(), &value) {
+            ("Int", FieldValue::Int(v)) => pad_number(v.to_string(), length),
+            ("Double", FieldValue::Double(v)) if decimals > 0 => pad_number(format!("{:.*}", decimals, v), length),
+            ("Double", FieldValue::Double(v)) => pad_number(v.to_string(), length),
+            ("Double", FieldValue::Int(v)) => pad_number(v.to_string(), length),
+            ("String", FieldValue::Text(v)) => {
+                let (encoded, _, _) = EUC_KR.encode(v);
+                let mut bytes = encoded.into_owned();
+                if bytes.len() <= length {
+                    bytes.resize(length, b' ');
+                }
+                bytes
+            },
+            _ => {
+                return Err(GeneratorError::TypeMismatch {
+                    field: item_name.to_string(),
+                    data_type: field.data_type.clone(),
+                });
+            },
+        };
+        if bytes.len() != length {
+            return Err(GeneratorError::TooLong {
+                field: item_name.to_string(),
+                length,
+                value: format!("{:?}", value),
+            });
+        }
+        let start = field.start_point as usize;
+        self.payload[start..start + length].copy_from_slice(&bytes);
+        Ok(self)
+    }
+
+    pub fn build(self) -> Vec<u8> {
+        self.payload
+    }
+}
+
+/// The B606F quote the tests build over and over, on the BF606F spec (e.g., `LayoutRegistry::krx()`).
+/// More fields can be set on the returned builder before `build`.
+/// # Arguments
+/// * `distidx` - Message sequence number
+/// * `isin` - ISIN Code, "" leaves it blank
+/// * `ask_price` - Ask Level 1 price
+pub fn b606f_payload<'a>(
+    fields: &'a [PayloadField],
+    distidx: i64,
+    isin: &str,
+    ask_price: f64,
+) -> Result<PayloadBuilder<'a>, GeneratorError> {
+    PayloadBuilder::new(fields)
+        .trcode("B606F")?
+        .set("Message sequence number", FieldValue::Int(distidx))?
+        .set("ISIN Code", FieldValue::Text(isin.to_string()))?
+        .set("Ask Level 1 price", FieldValue::Double(ask_price))
+}
+
+/// "-12" in 5 bytes => "-0012", longer than `length` is left as is for the caller to reject
+fn pad_number(text: String, length: usize) -> Vec<u8> {
+    let (sign, digits) = match text.strip_prefix('-') {
+        Some(digits) => ("-", digits),
+        None => ("", text.as_str()),
+    };
+    let width = length.saturating_sub(sign.len());
+    format!("{}{:0>width$}", sign, digits, width = width).into_bytes()
+}
+
+/// Writes UDP datagrams to a nanosecond pcap (Ethernet linktype), as a feed channel would carry them.
+/// # Arguments
+/// * `source`, `group`, `port` - addresses of the generated packets, 10.0.0.1 => 233.37.54.1:20001 by default
+pub struct FeedGenerator {
+    savefile: Savefile,
+    source: Ipv4Addr,
+    group: Ipv4Addr,
+    source_port: u16,
+    port: u16,
+    ip_id: u16,
+}
+
+impl FeedGenerator {
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self, pcap::Error> {
+        let capture = Capture::dead_with_precision(Linktype::ETHERNET, Precision::Nano)?;
+        Ok(FeedGenerator {
+            savefile: capture.savefile(path)?,
+            source: Ipv4Addr::new(10, 0, 0, 1),
+            group: Ipv4Addr::new(233, 37, 54, 1),
+            source_port: 20000,
+            port: 20001,
+            ip_id: 0,
+        })
+    }
+
+    pub fn with_channel(mut self, source: Ipv4Addr, group: Ipv4Addr, port: u16) -> Self {
+        self.source = source;
+        self.group = group;
+        self.port = port;
+        self
+    }
+
+    /// One packet carrying `payload` (one message, or several back to back)
+    pub fn write(&mut self, timestamp: UnixNano, payload: &[u8]) {
+        let frame = self.udp_frame(payload);
+        let header = PacketHeader {
+            ts: libc::timeval {
+                tv_sec: (timestamp / 1_000_000_000) as libc::time_t,
+                // nanoseconds in a nanosecond savefile
+                tv_usec: (timestamp % 1_000_000_000) as libc::suseconds_t,
+            },
+            caplen: frame.len() as u32,
+            len: frame.len() as u32,
+        };
+        self.savefile.write(&pcap::Packet::new(&header, &frame));
+    }
+
+    pub fn finish(mut self) -> Result<(), pcap::Error> {
+        self.savefile.flush()
+    }
+
+    fn udp_frame(&mut self, payload: &[u8]) -> Vec<u8> {
+        let mut frame = Vec::with_capacity(ETHERNET_HEADER_LEN + IPV4_HEADER_LEN + UDP_HEADER_LEN + payload.len());
+        // multicast MAC of the group, locally administered source
+        let group = self.group.octets();
+        frame.extend_from_slice(&[0x01, 0x00, 0x5e, group[1] & 0x7f, group[2], group[3]]);
+        frame.extend_from_slice(&[0x02, 0x00, 0x00, 0x00, 0x00, 0x01]);
+        frame.extend_from_slice(&[0x08, 0x00]);
+
+        let ip_start = frame.len();
+        let total_len = (IPV4_HEADER_LEN + UDP_HEADER_LEN + payload.len()) as u16;
+        frame.extend_from_slice(&[0x45, 0x00]);
+        frame.extend_from_slice(&total_len.to_be_bytes());
+        frame.extend_from_slice(&self.ip_id.to_be_bytes());
+        frame.extend_from_slice(&[0x40, 0x00, 32, 17, 0, 0]);
+        frame.extend_from_slice(&self.source.octets());
+        frame.extend_from_slice(&group);
+        let checksum = ipv4_checksum(&frame[ip_start..]);
+        frame[ip_start + 10..ip_start + 12].copy_from_slice(&checksum.to_be_bytes());
+        self.ip_id = self.ip_id.wrapping_add(1);
+
+        frame.extend_from_slice(&self.source_port.to_be_bytes());
+        frame.extend_from_slice(&self.port.to_be_bytes());
+        frame.extend_from_slice(&((UDP_HEADER_LEN + payload.len()) as u16).to_be_bytes());
+        // no UDP checksum, allowed over IPv4
+        frame.extend_from_slice(&[0, 0]);
+        frame.extend_from_slice(payload);
+        frame
+    }
+}
+
+fn ipv4_checksum(header: &[u8]) -> u16 {
+    let mut sum: u32 = header
+        .chunks(2)
+        .map(|pair| u16::from_be_bytes([pair[0], *pair.get(1).unwrap_or(&0)]) as u32)
+        .sum();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::LayoutRegistry;
+    use crate::packet::krx_msg_iter::KrxMsgIter;
+
+    #[test]
+    fn test_generated_pcap_is_read_back() -> anyhow::Result<()> {
+        let registry = LayoutRegistry::krx()?;
+        let fields = registry.get(b"B606F").unwrap().fields();
+        let path = std::env::temp_dir().join("feed_generator_test.pcap");
+        let mut generator = FeedGenerator::create(&path)?;
+        for i in 0..10 {
+            let payload = b606f_payload(fields, i, "KR4165N30007", 100.0)?.build();
+            generator.write(1_727_400_000_000_000_000 + i as UnixNano * 1_000, &payload);
+        }
+        generator.finish()?;
+
+        let msgs = KrxMsgIter::from_file(path.to_str().unwrap(), 20240927, Some(vec!["B606F".to_string()]))?
+            .collect::<std::io::Result<Vec<_>>>()?;
+        std::fs::remove_file(&path)?;
+        assert_eq!(msgs.len(), 10);
+        assert_eq!(msgs[3].distidx, Some(3));
+        assert_eq!(msgs[3].instcode.as_deref(), Some("KR4165N30007"));
+        assert_eq!(msgs[3].packet_timestamp, Some(1_727_400_000_000_003_000));
+        Ok(())
+    }
+
+    #[test]
+    fn test_ipv4_checksum() {
+        // header with its checksum verifies to 0
+        let header = [0x45, 0x00, 0x00, 0x73, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11, 0xb8, 0x61, 0xc0, 0xa8, 0x00, 0x01, 0xc0, 0xa8, 0x00, 0xc7];
+        assert_eq!(ipv4_checksum(&header), 0);
+    }
+}
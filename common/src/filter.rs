@@ -0,0 +1,705 @@
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use crate::layout::LayoutRegistry;
+use crate::mongodb_collection::krx_msg::range_helper::{krx_message_dist_index_range, krx_messages_instcode_range};
+use crate::packet::time_window::{kst_time_of_day, time_of_day};
+use crate::{KrxMsg, TrCode, UnixNano};
+
+
+/// A literal of a filter expression, or a decoded payload field
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Int(i64),
+    Double(f64),
+    Text(String),
+}
+
+/// Decoded payload fields for `field("...")` predicates, e.g., the spec layout of a trcode
+pub trait FieldSource: fmt::Debug + Send + Sync {
+    /// Checked for every `field("...")` when the source is attached to a filter
+    fn has_field(&self, name: &str) -> bool;
+
+    /// `None` if the payload does not carry the field (e.g., another trcode).
+    /// Text is expected without its padding.
+    fn field(&self, payload: &[u8], name: &str) -> Option<Value>;
+}
+
+/// # Arguments
+/// * `position` - byte offset in the expression
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilterError {
+    pub position: usize,
+    pub message: String,
+}
+
+impl FilterError {
+    fn new(position: usize, message: impl Into<String>) -> Self {
+        FilterError { position, message: message.into() }
+    }
+}
+
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} (at {})", self.message, self.position)
+    }
+}
+
+impl std::error::Error for FilterError {}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Subject {
+    Trcode,
+    Family,
+    Market,
+    Instcode,
+    Distidx,
+    /// capture time, UnixNano
+    Time,
+    /// capture time, ns since midnight KST (a time literal, e.g., "09:00")
+    KstTime,
+    Len,
+    Field(String),
+}
+
+impl Subject {
+    fn is_text(&self) -> bool {
+        matches!(self, Subject::Trcode | Subject::Family | Subject::Market | Subject::Instcode)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    /// text starting with the literal
+    Prefix,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Or(Vec<Expr>),
+    And(Vec<Expr>),
+    Not(Box<Expr>),
+    Compare(Subject, Op, Value),
+    In(Subject, Vec<Value>),
+    /// [start, end)
+    Range(Subject, Value, Value),
+}
+
+/// Filter on KRX messages, parsed from an expression such as
+/// `family == "B6" and market == "F" and instcode ^= "KR4165" and distidx in 100..200`.
+///
+/// Subjects
+/// * `trcode` (B606F), `family` (B6), `market` (F, the last byte of the trcode), `instcode`
+/// * `distidx`, `len` (payload length)
+/// * `time` - capture time, either UnixNano or KST wall-clock time of day ("09:00", "15:30:00.5")
+/// * `field("Ask Level 1 price")` - a decoded payload field, needs `with_fields`
+///
+/// Operators are `==`, `!=`, `<`, `<=`, `>`, `>=`, `^=` (starts with), `in [a, b, ...]` and
+/// `in start..end` (end exclusive), combined with `and`/`&&`, `or`/`||`, `not`/`!` and parentheses.
+/// A predicate on something the message does not have (no instcode, a field of another trcode,
+/// a value of another type) is false.
+#[derive(Clone)]
+pub struct Filter {
+    expression: String,
+    expr: Expr,
+    fields: Option<Arc<dyn FieldSource>>,
+}
+
+impl fmt::Debug for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Filter")
+            .field("expression", &self.expression)
+            .field("fields", &self.fields)
+            .finish()
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.expression)
+    }
+}
+
+impl FromStr for Filter {
+    type Err = FilterError;
+
+    fn from_str(expression: &str) -> Result<Self, Self::Err> {
+        Filter::parse(expression)
+    }
+}
+
+impl Filter {
+    pub fn parse(expression: &str) -> Result<Self, FilterError> {
+        let tokens = tokenize(expression)?;
+        let mut parser = Parser { tokens, position: 0, end: expression.len() };
+        let expr = parser.or()?;
+        if let Some((position, _)) = parser.tokens.get(parser.position) {
+            return Err(FilterError::new(*position, "Unexpected token"));
+        }
+        Ok(Filter {
+            expression: expression.to_string(),
+            expr,
+            fields: None,
+        })
+    }
+
+    /// Attaches the decoder of `field("...")`, every field name in the expression must be known to it
+    pub fn with_fields(mut self, fields: Arc<dyn FieldSource>) -> Result<Self, FilterError> {
+        if let Some(name) = self.field_names().into_iter().find(|name| !fields.has_field(name)) {
+            let position = self.expression.find(name.as_str()).unwrap_or(0);
+            return Err(FilterError::new(position, format!("Unknown field: {}", name)));
+        }
+        self.fields = Some(fields);
+        Ok(self)
+    }
+
+    /// Names used in `field("...")`, in order of appearance
+    pub fn field_names(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        collect_field_names(&self.expr, &mut names);
+        names
+    }
+
+    /// A single message (not a whole datagram) and its capture time
+    pub fn matches(&self, payload: &[u8], timestamp: Option<UnixNano>) -> bool {
+        self.eval(&self.expr, &Target::Payload(payload, timestamp, None))
+    }
+
+    /// `matches` with the instcode and distidx offsets of the trcodes in the registry taken from their specs
+    pub fn matches_with_layouts(&self, payload: &[u8], timestamp: Option<UnixNano>, layouts: &LayoutRegistry) -> bool {
+        self.eval(&self.expr, &Target::Payload(payload, timestamp, Some(layouts)))
+    }
+
+    /// Uses the trcode, instcode and distidx stored on the message, and `packet_timestamp` as the capture time
+    pub fn matches_msg(&self, msg: &KrxMsg) -> bool {
+        self.eval(&self.expr, &Target::Msg(msg))
+    }
+
+    fn eval(&self, expr: &Expr, target: &Target) -> bool {
+        match expr {
+            Expr::Or(exprs) => exprs.iter().any(|expr| self.eval(expr, target)),
+            Expr::And(exprs) => exprs.iter().all(|expr| self.eval(expr, target)),
+            Expr::Not(expr) => !self.eval(expr, target),
+            Expr::Compare(subject, op, value) => self.with_operand(subject, target, |operand| compare(&operand, *op, value)),
+            Expr::In(subject, values) => self.with_operand(subject, target, |operand| {
+                values.iter().any(|value| compare(&operand, Op::Eq, value))
+            }),
+            Expr::Range(subject, start, end) => self.with_operand(subject, target, |operand| {
+                compare(&operand, Op::Ge, start) && compare(&operand, Op::Lt, end)
+            }),
+        }
+    }
+
+    fn with_operand(&self, subject: &Subject, target: &Target, f: impl FnOnce(Operand) -> bool) -> bool {
+        let operand = match subject {
+            Subject::Trcode => target.trcode().map(Operand::Text),
+            Subject::Family => target.trcode().and_then(|trcode| trcode.get(..2)).map(Operand::Text),
+            Subject::Market => target.trcode().and_then(|trcode| trcode.get(trcode.len().saturating_sub(1)..)).map(Operand::Text),
+            Subject::Instcode => target.instcode().map(Operand::Text),
+            Subject::Distidx => target.distidx().map(Operand::Int),
+            Subject::Time => target.timestamp().map(|timestamp| Operand::Int(timestamp as i64)),
+            Subject::KstTime => target.timestamp().map(|timestamp| Operand::Int(kst_time_of_day(timestamp) as i64)),
+            Subject::Len => Some(Operand::Int(target.payload().len() as i64)),
+            Subject::Field(name) => {
+                let value = match self.fields.as_ref().and_then(|fields| fields.field(target.payload(), name)) {
+                    Some(value) => value,
+                    None => return false,
+                };
+                return f(Operand::from(&value));
+            },
+        };
+        operand.is_some_and(f)
+    }
+}
+
+/// Returns true if the message passes the filter, or if there is no filter.
+/// Offsets come from `layouts` if given, see `Filter::matches_with_layouts`.
+pub fn matches_filter(
+    filter: &Option<Filter>,
+    payload: &[u8],
+    timestamp: Option<UnixNano>,
+    layouts: Option<&LayoutRegistry>,
+) -> bool {
+    match filter {
+        Some(ref filter) => filter.eval(&filter.expr, &Target::Payload(payload, timestamp, layouts)),
+        None => true,
+    }
+}
+
+fn collect_field_names(expr: &Expr, names: &mut Vec<String>) {
+    match expr {
+        Expr::Or(exprs) | Expr::And(exprs) => exprs.iter().for_each(|expr| collect_field_names(expr, names)),
+        Expr::Not(expr) => collect_field_names(expr, names),
+        Expr::Compare(Subject::Field(name), ..) | Expr::In(Subject::Field(name), _) | Expr::Range(Subject::Field(name), ..)
+            if !names.contains(name) =>
+        {
+            names.push(name.clone());
+        },
+        _ => {},
+    }
+}
+
+/// What a filter is evaluated on, looked up only when a predicate needs it
+enum Target<'a> {
+    Payload(&'a [u8], Option<UnixNano>, Option<&'a LayoutRegistry>),
+    Msg(&'a KrxMsg),
+}
+
+impl Target<'_> {
+    fn payload(&self) -> &[u8] {
+        match self {
+            Target::Payload(payload, _, _) => payload,
+            Target::Msg(msg) => &msg.payload,
+        }
+    }
+
+    fn trcode(&self) -> Option<&str> {
+        match self {
+            Target::Payload(payload, _, _) => TrCode::str_from_bytes(payload),
+            Target::Msg(msg) => msg.trcode(),
+        }
+    }
+
+    fn instcode(&self) -> Option<&str> {
+        match self {
+            Target::Payload(payload, _, layouts) => layouts
+                .map_or_else(|| krx_messages_instcode_range(payload), |layouts| layouts.instcode_range(payload))
+                .and_then(|range| payload.get(range))
+                .and_then(|instcode| std::str::from_utf8(instcode).ok())
+                .map(|instcode| instcode.trim())
+                .filter(|instcode| !instcode.is_empty()),
+            Target::Msg(msg) => msg.instcode.as_deref(),
+        }
+    }
+
+    fn distidx(&self) -> Option<i64> {
+        match self {
+            Target::Payload(payload, _, layouts) => layouts
+                .map_or_else(|| krx_message_dist_index_range(payload), |layouts| layouts.distidx_range(payload))
+                .and_then(|range| payload.get(range))
+                .and_then(|distidx| std::str::from_utf8(distidx).ok())
+                .and_then(|distidx| distidx.trim().parse().ok()),
+            Target::Msg(msg) => msg.distidx.map(|distidx| distidx as i64),
+        }
+    }
+
+    fn timestamp(&self) -> Option<UnixNano> {
+        match self {
+            Target::Payload(_, timestamp, _) => *timestamp,
+            Target::Msg(msg) => msg.packet_timestamp.or(msg.timestamp),
+        }
+    }
+}
+
+enum Operand<'a> {
+    Int(i64),
+    Double(f64),
+    Text(&'a str),
+}
+
+impl<'a> From<&'a Value> for Operand<'a> {
+    fn from(value: &'a Value) -> Self {
+        match value {
+            Value::Int(v) => Operand::Int(*v),
+            Value::Double(v) => Operand::Double(*v),
+            Value::Text(v) => Operand::Text(v.as_str()),
+        }
+    }
+}
+
+fn compare(operand: &Operand, op: Op, value: &Value) -> bool {
+    use std::cmp::Ordering;
+    let ordering = match (operand, value) {
+        (Operand::Text(a), Value::Text(b)) => {
+            if op == Op::Prefix {
+                return a.starts_with(b.as_str());
+            }
+            a.cmp(&b.as_str())
+        },
+        (Operand::Int(a), Value::Int(b)) => a.cmp(b),
+        (Operand::Int(a), Value::Double(b)) => (*a as f64).partial_cmp(b).unwrap_or(Ordering::Less),
+        (Operand::Double(a), Value::Int(b)) => a.partial_cmp(&(*b as f64)).unwrap_or(Ordering::Less),
+        (Operand::Double(a), Value::Double(b)) => a.partial_cmp(b).unwrap_or(Ordering::Less),
+        _ => return false,
+    };
+    match op {
+        Op::Eq => ordering == Ordering::Equal,
+        Op::Ne => ordering != Ordering::Equal,
+        Op::Lt => ordering == Ordering::Less,
+        Op::Le => ordering != Ordering::Greater,
+        Op::Gt => ordering == Ordering::Greater,
+        Op::Ge => ordering != Ordering::Less,
+        Op::Prefix => false,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Literal(Value),
+    Op(Op),
+    And,
+    Or,
+    Not,
+    In,
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    Comma,
+    DotDot,
+}
+
+fn tokenize(expression: &str) -> Result<Vec<(usize, Token)>, FilterError> {
+    let bytes = expression.as_bytes();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let start = i;
+        let b = bytes[i];
+        let two = bytes.get(i..i + 2).unwrap_or(&[]);
+        let token = match b {
+            b' ' | b'\t' | b'\r' | b'\n' => {
+                i += 1;
+                continue;
+            },
+            b'(' => Token::LParen,
+            b')' => Token::RParen,
+            b'[' => Token::LBracket,
+            b']' => Token::RBracket,
+            b',' => Token::Comma,
+            _ if two == b".." => Token::DotDot,
+            _ if two == b"==" => Token::Op(Op::Eq),
+            _ if two == b"!=" => Token::Op(Op::Ne),
+            _ if two == b"<=" => Token::Op(Op::Le),
+            _ if two == b">=" => Token::Op(Op::Ge),
+            _ if two == b"^=" => Token::Op(Op::Prefix),
+            _ if two == b"&&" => Token::And,
+            _ if two == b"||" => Token::Or,
+            b'<' => Token::Op(Op::Lt),
+            b'>' => Token::Op(Op::Gt),
+            b'!' => Token::Not,
+            b'"' | b'\'' => {
+                let end = expression[i + 1..]
+                    .find(b as char)
+                    .ok_or_else(|| FilterError::new(start, "Unterminated string"))?;
+                let text = expression[i + 1..i + 1 + end].to_string();
+                i += end + 2;
+                tokens.push((start, Token::Literal(Value::Text(text))));
+                continue;
+            },
+            b'-' | b'0'..=b'9' => {
+                i += 1;
+                while i < bytes.len() && bytes[i].is_ascii_digit() {
+                    i += 1;
+                }
+                // a fraction, but not the `..` of a range
+                let is_double = bytes.get(i) == Some(&b'.') && bytes.get(i + 1).is_some_and(|b| b.is_ascii_digit());
+                if is_double {
+                    i += 1;
+                    while i < bytes.len() && bytes[i].is_ascii_digit() {
+                        i += 1;
+                    }
+                }
+                let number = &expression[start..i];
+                let value = if is_double {
+                    number.parse().map(Value::Double).ok()
+                } else {
+                    number.parse().map(Value::Int).ok()
+                };
+                let value = value.ok_or_else(|| FilterError::new(start, format!("Invalid number: {}", number)))?;
+                tokens.push((start, Token::Literal(value)));
+                continue;
+            },
+            _ if b.is_ascii_alphabetic() || b == b'_' => {
+                while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
+                    i += 1;
+                }
+                let token = match &expression[start..i] {
+                    "and" => Token::And,
+                    "or" => Token::Or,
+                    "not" => Token::Not,
+                    "in" => Token::In,
+                    ident => Token::Ident(ident.to_string()),
+                };
+                tokens.push((start, token));
+                continue;
+            },
+            _ => return Err(FilterError::new(start, format!("Unexpected character: {}", &expression[start..].chars().next().unwrap_or(' ')))),
+        };
+        i += match token {
+            Token::Op(Op::Lt) | Token::Op(Op::Gt) | Token::Not => 1,
+            Token::LParen | Token::RParen | Token::LBracket | Token::RBracket | Token::Comma => 1,
+            _ => 2,
+        };
+        tokens.push((start, token));
+    }
+    Ok(tokens)
+}
+
+/// or := and (("or" | "||") and)*
+/// and := unary (("and" | "&&") unary)*
+/// unary := ("not" | "!") unary | "(" or ")" | predicate
+/// predicate := subject op literal | subject "in" "[" literal ("," literal)* "]" | subject "in" literal ".." literal
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    position: usize,
+    /// length of the expression, reported for a missing token at the end
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|(_, token)| token)
+    }
+
+    fn offset(&self) -> usize {
+        self.tokens.get(self.position).map_or(self.end, |(offset, _)| *offset)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).map(|(_, token)| token.clone());
+        self.position += 1;
+        token
+    }
+
+    fn expect(&mut self, expected: Token, what: &str) -> Result<(), FilterError> {
+        let offset = self.offset();
+        match self.next() {
+            Some(token) if token == expected => Ok(()),
+            _ => Err(FilterError::new(offset, format!("Expected {}", what))),
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr, FilterError> {
+        let mut exprs = vec![self.and()?];
+        while self.peek() == Some(&Token::Or) {
+            self.position += 1;
+            exprs.push(self.and()?);
+        }
+        Ok(if exprs.len() == 1 { exprs.pop().unwrap() } else { Expr::Or(exprs) })
+    }
+
+    fn and(&mut self) -> Result<Expr, FilterError> {
+        let mut exprs = vec![self.unary()?];
+        while self.peek() == Some(&Token::And) {
+            self.position += 1;
+            exprs.push(self.unary()?);
+        }
+        Ok(if exprs.len() == 1 { exprs.pop().unwrap() } else { Expr::And(exprs) })
+    }
+
+    fn unary(&mut self) -> Result<Expr, FilterError> {
+        match self.peek() {
+            Some(Token::Not) => {
+                self.position += 1;
+                Ok(Expr::Not(Box::new(self.unary()?)))
+            },
+            Some(Token::LParen) => {
+                self.position += 1;
+                let expr = self.or()?;
+                self.expect(Token::RParen, "')'")?;
+                Ok(expr)
+            },
+            _ => self.predicate(),
+        }
+    }
+
+    fn predicate(&mut self) -> Result<Expr, FilterError> {
+        let offset = self.offset();
+        let subject = self.subject()?;
+        match self.next() {
+            Some(Token::Op(op)) => {
+                let value = self.literal()?;
+                let (subject, mut values) = check_literals(subject, op, vec![value], offset)?;
+                Ok(Expr::Compare(subject, op, values.pop().unwrap()))
+            },
+            Some(Token::In) if self.peek() == Some(&Token::LBracket) => {
+                self.position += 1;
+                let mut values = vec![self.literal()?];
+                while self.peek() == Some(&Token::Comma) {
+                    self.position += 1;
+                    values.push(self.literal()?);
+                }
+                self.expect(Token::RBracket, "']'")?;
+                let (subject, values) = check_literals(subject, Op::Eq, values, offset)?;
+                Ok(Expr::In(subject, values))
+            },
+            Some(Token::In) => {
+                let start = self.literal()?;
+                self.expect(Token::DotDot, "'..'")?;
+                let end = self.literal()?;
+                let (subject, mut values) = check_literals(subject, Op::Lt, vec![start, end], offset)?;
+                let end = values.pop().unwrap();
+                Ok(Expr::Range(subject, values.pop().unwrap(), end))
+            },
+            _ => Err(FilterError::new(offset, "Expected an operator or 'in' after the subject")),
+        }
+    }
+
+    fn subject(&mut self) -> Result<Subject, FilterError> {
+        let offset = self.offset();
+        let name = match self.next() {
+            Some(Token::Ident(name)) => name,
+            _ => return Err(FilterError::new(offset, "Expected a subject")),
+        };
+        match name.as_str() {
+            "trcode" => Ok(Subject::Trcode),
+            "family" => Ok(Subject::Family),
+            "market" => Ok(Subject::Market),
+            "instcode" => Ok(Subject::Instcode),
+            "distidx" => Ok(Subject::Distidx),
+            "time" => Ok(Subject::Time),
+            "len" => Ok(Subject::Len),
+            "field" => {
+                self.expect(Token::LParen, "'(' after field")?;
+                let offset = self.offset();
+                let name = match self.next() {
+                    Some(Token::Literal(Value::Text(name))) => name,
+                    _ => return Err(FilterError::new(offset, "Expected a field name in quotes")),
+                };
+                self.expect(Token::RParen, "')'")?;
+                Ok(Subject::Field(name))
+            },
+            _ => Err(FilterError::new(offset, format!("Unknown subject: {}", name))),
+        }
+    }
+
+    fn literal(&mut self) -> Result<Value, FilterError> {
+        let offset = self.offset();
+        match self.next() {
+            Some(Token::Literal(value)) => Ok(value),
+            _ => Err(FilterError::new(offset, "Expected a number or a quoted string")),
+        }
+    }
+}
+
+/// Rejects literals a subject can never equal, and turns time-of-day literals into ns since midnight
+fn check_literals(subject: Subject, op: Op, values: Vec<Value>, offset: usize) -> Result<(Subject, Vec<Value>), FilterError> {
+    if subject.is_text() {
+        if values.iter().any(|value| !matches!(value, Value::Text(_))) {
+            return Err(FilterError::new(offset, "Expected a quoted string"));
+        }
+        return Ok((subject, values));
+    }
+    if op == Op::Prefix && !matches!(subject, Subject::Field(_)) {
+        return Err(FilterError::new(offset, "'^=' needs a text subject"));
+    }
+    match subject {
+        Subject::Time if values.iter().all(|value| matches!(value, Value::Text(_))) => {
+            let values = values
+                .iter()
+                .map(|value| match value {
+                    Value::Text(time) => time_of_day(time).map(|nanos| Value::Int(nanos as i64)),
+                    _ => unreachable!(),
+                })
+                .collect::<Result<Vec<Value>, String>>()
+                .map_err(|e| FilterError::new(offset, e))?;
+            Ok((Subject::KstTime, values))
+        },
+        Subject::Distidx | Subject::Len | Subject::Time => {
+            if values.iter().any(|value| !matches!(value, Value::Int(_))) {
+                return Err(FilterError::new(offset, "Expected an integer"));
+            }
+            Ok((subject, values))
+        },
+        _ => Ok((subject, values)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::test_frames::b6_message;
+
+    const T0: UnixNano = 1_727_400_000_000_000_000;
+
+    #[derive(Debug)]
+    struct AskPrice;
+
+    impl FieldSource for AskPrice {
+        fn has_field(&self, name: &str) -> bool {
+            name == "Ask Level 1 price"
+        }
+
+        fn field(&self, payload: &[u8], name: &str) -> Option<Value> {
+            if name != "Ask Level 1 price" || !payload.starts_with(b"B606F") {
+                return None;
+            }
+            Some(Value::Double(std::str::from_utf8(&payload[29..35]).ok()?.trim().parse().ok()?))
+        }
+    }
+
+    #[test]
+    fn test_parse_and_match() -> anyhow::Result<()> {
+        let payload = b6_message(150);
+        let matches = |expression: &str| Filter::parse(expression).unwrap().matches(&payload, Some(T0));
+
+        assert!(matches(r#"family == "B6" and market == "F" and instcode ^= "KR4165""#));
+        assert!(matches("distidx in 100..200 && len == 324"));
+        assert!(!matches("distidx in 100..150"));
+        assert!(matches(r#"trcode in ["A301K", "B606F"] or distidx > 1000"#));
+        assert!(matches(r#"not (trcode == "A301K" || distidx < 100)"#));
+        assert!(!matches(r#"!(instcode ^= "KR41")"#));
+        // 2024-09-27 10:20:00 KST
+        assert!(matches(r#"time in "10:00".."10:30""#));
+        assert!(matches(&format!("time >= {}", T0)));
+        assert!(!matches(r#"time < "09:00:00.5""#));
+
+        // A301K carries no instcode
+        let a3 = b"A301K00000002\xff";
+        let filter: Filter = r#"instcode ^= "KR""#.parse()?;
+        assert!(!filter.matches(a3, None));
+        assert!(Filter::parse(r#"not instcode ^= "KR""#)?.matches(a3, None));
+
+        let msg = KrxMsg::new_from_payload(20240927, &payload, Some(T0), None).unwrap();
+        assert!(Filter::parse(r#"instcode == "KR4165N30007" and distidx == 150 and time == "10:20""#)?.matches_msg(&msg));
+
+        // not a trcode: the same answer for the payload and for the message made from it
+        let mut blank = payload.clone();
+        blank[2] = b' ';
+        let blank_msg = KrxMsg::new_from_payload(20240927, &blank, Some(T0), None).unwrap();
+        for expression in [r#"family == "B6""#, r#"market == "F""#, r#"not trcode == "B6 6F""#] {
+            let filter = Filter::parse(expression)?;
+            assert_eq!(filter.matches(&blank, Some(T0)), filter.matches_msg(&blank_msg), "{}", expression);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_fields() -> anyhow::Result<()> {
+        let mut payload = b6_message(1);
+        payload[29..35].copy_from_slice(b"101.25");
+        let filter = Filter::parse(r#"field("Ask Level 1 price") >= 101 and field('Ask Level 1 price') < 101.5"#)?;
+        assert_eq!(filter.field_names(), vec!["Ask Level 1 price".to_string()]);
+        // no source attached, field predicates are false
+        assert!(!filter.matches(&payload, None));
+
+        let filter = filter.with_fields(Arc::new(AskPrice))?;
+        assert!(filter.matches(&payload, None));
+        assert!(!filter.matches(b"A301K00000002\xff", None));
+
+        let error = Filter::parse(r#"field("Bid") > 1"#)?.with_fields(Arc::new(AskPrice)).unwrap_err();
+        assert_eq!(error.message, "Unknown field: Bid");
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let error = |expression: &str| Filter::parse(expression).unwrap_err();
+        assert_eq!(error("distidx == \"1\"").message, "Expected an integer");
+        assert_eq!(error("trcode == 1").message, "Expected a quoted string");
+        assert_eq!(error("price > 1").message, "Unknown subject: price");
+        assert_eq!(error("trcode == \"B606F").position, 10);
+        assert_eq!(error("(distidx > 1").position, 12);
+        assert_eq!(error("distidx > 1 distidx").position, 12);
+        assert!(Filter::parse("distidx ^= 1").is_err());
+        assert!(Filter::parse(r#"time > "25:00""#).is_err());
+    }
+}
@@ -0,0 +1,235 @@
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::ops::Range;
+use std::path::Path;
+use crate::compression;
+use crate::mongodb_collection::krx_msg::range_helper::{krx_message_dist_index_range, krx_messages_instcode_range};
+use crate::mongodb_collection::krx_msg::trcode::TrCode;
+use crate::packet::framing::MessageLengths;
+
+/// Item names of the spec fields `KrxMsg` takes its instcode and distidx from
+pub const INSTCODE_FIELD: &str = "ISIN Code";
+pub const DISTIDX_FIELD: &str = "Message sequence number";
+
+/// The spec rows and the bundled specs live in the `spec` crate, which build scripts can depend on
+pub use spec::{PayloadField, KRX_SPECS};
+
+/// `PayloadField::load_from_csv`, reading through `compression`
+pub trait LoadFromCsv: Sized {
+    fn load_from_csv(file_path: &str) -> io::Result<Vec<Self>>;
+}
+
+impl LoadFromCsv for PayloadField {
+    // Jay
+    // Box<dyn Error> is not thread safe.
+    // Moreover, Box<dyn Trait> is slow. General practice is specify the error type in lib and use anyhow in the application
+    // gzip, zstd, xz and 7z files (e.g., spec/data/BF606F_new.7z) are decompressed while reading
+    fn load_from_csv(file_path: &str) -> io::Result<Vec<PayloadField>> {
+        // 파일을 바이트로 읽기
+        let mut bytes = Vec::new();
+        compression::open(file_path)?.read_to_end(&mut bytes)?;
+        PayloadField::from_csv_bytes(&bytes)
+    }
+}
+
+/// Fields of one trcode, in spec order
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Layout {
+    fields: Vec<PayloadField>,
+}
+
+impl Layout {
+    pub fn new(fields: Vec<PayloadField>) -> Self {
+        Layout { fields }
+    }
+
+    pub fn fields(&self) -> &[PayloadField] {
+        &self.fields
+    }
+
+    pub fn field(&self, item_name: &str) -> Option<&PayloadField> {
+        self.fields.iter().find(|field| field.item_name == item_name)
+    }
+
+    pub fn range(&self, item_name: &str) -> Option<Range<usize>> {
+        self.field(item_name).map(PayloadField::range)
+    }
+
+    pub fn instcode_range(&self) -> Option<Range<usize>> {
+        self.range(INSTCODE_FIELD)
+    }
+
+    pub fn distidx_range(&self) -> Option<Range<usize>> {
+        self.range(DISTIDX_FIELD)
+    }
+
+    /// Bytes up to and including the End Keyword
+    pub fn message_length(&self) -> usize {
+        self.fields.iter().map(|field| field.cumulative_length as usize).max().unwrap_or(0)
+    }
+}
+
+/// Spec layouts keyed by trcode, so that offsets come from the spec CSVs instead of code.
+/// It is handed to what reads messages, e.g., `KrxMsgIter::with_layouts` or `KrxMsgRef::with_layouts`,
+/// which then take instcode and distidx offsets and message lengths from it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LayoutRegistry {
+    layouts: HashMap<String, Layout>,
+}
+
+impl LayoutRegistry {
+    pub fn new() -> Self {
+        LayoutRegistry::default()
+    }
+
+    pub fn insert(&mut self, trcode: &str, fields: Vec<PayloadField>) {
+        self.layouts.insert(trcode.to_string(), Layout::new(fields));
+    }
+
+    pub fn with_layout(mut self, trcode: &str, fields: Vec<PayloadField>) -> Self {
+        self.insert(trcode, fields);
+        self
+    }
+
+    /// Every spec CSV in `dir` named after its trcode, e.g., B606F.csv or B606F_20241028.7z
+    /// (the name up to the first '_' or '.'). Files not named after a trcode are skipped.
+    pub fn load_dir<P: AsRef<Path>>(dir: P) -> io::Result<Self> {
+        let mut registry = LayoutRegistry::new();
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            let trcode = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.split(['_', '.']).next())
+                .filter(|stem| stem.parse::<TrCode>().is_ok());
+            match trcode {
+                Some(trcode) if path.is_file() => {
+                    let fields = PayloadField::load_from_csv(&path.to_string_lossy())?;
+                    registry.insert(trcode, fields);
+                },
+                _ => {
+                    let path = path.display().to_string();
+                    flashlog::flash_warn!("LAYOUT"; "Skipped a file not named after a trcode"; path = path);
+                },
+            }
+        }
+        Ok(registry)
+    }
+
+    /// Specs compiled into the binary, e.g., `[("B606F", include_bytes!("../data/B606F.7z").as_slice())]`.
+    /// They may be compressed like the files `load_dir` reads.
+    pub fn from_embedded(specs: &[(&str, &[u8])]) -> io::Result<Self> {
+        let mut registry = LayoutRegistry::new();
+        for (trcode, bytes) in specs {
+            let fields = PayloadField::from_csv_bytes(&compression::decompress(bytes)?)?;
+            registry.insert(trcode, fields);
+        }
+        Ok(registry)
+    }
+
+    /// `KRX_SPECS`
+    pub fn krx() -> io::Result<Self> {
+        LayoutRegistry::from_embedded(KRX_SPECS)
+    }
+
+    pub fn get(&self, trcode: &[u8]) -> Option<&Layout> {
+        let trcode = std::str::from_utf8(trcode).ok()?;
+        self.layouts.get(trcode)
+    }
+
+    /// Layout of the trcode the payload starts with
+    pub fn layout_of(&self, payload: &[u8]) -> Option<&Layout> {
+        self.get(payload.get(..5)?)
+    }
+
+    pub fn trcodes(&self) -> impl Iterator<Item = &str> {
+        self.layouts.keys().map(String::as_str)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Layout)> {
+        self.layouts.iter().map(|(trcode, layout)| (trcode.as_str(), layout))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.layouts.is_empty()
+    }
+
+    /// `KRX_MESSAGE_LENGTHS`, with the lengths of the trcodes in the registry taken from their specs
+    pub fn message_lengths(&self) -> MessageLengths {
+        let mut lengths = MessageLengths::default();
+        for (trcode, layout) in self.layouts.iter() {
+            lengths.insert(trcode, layout.message_length());
+        }
+        lengths
+    }
+
+    /// Range of the ISIN Code in the spec of the payload's trcode, `krx_messages_instcode_range` without one
+    pub fn instcode_range(&self, payload: &[u8]) -> Option<Range<usize>> {
+        match self.layout_of(payload) {
+            Some(layout) => layout.instcode_range(),
+            None => krx_messages_instcode_range(payload),
+        }
+    }
+
+    /// Range of the Message sequence number in the spec of the payload's trcode,
+    /// `krx_message_dist_index_range` without one
+    pub fn distidx_range(&self, payload: &[u8]) -> Option<Range<usize>> {
+        match self.layout_of(payload) {
+            Some(layout) => layout.distidx_range(),
+            None => krx_message_dist_index_range(payload),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoding_rs::EUC_KR;
+    use crate::{KrxMsg, KrxMsgRef};
+
+    /// Z999F (not a KRX trcode): distidx at 5..10 and instcode at 10..22
+    const SPEC: &str = "한글명,Item Name,Sub,Data Type,Length,Cumulative,Start
+데이터구분값,Data Category,0,String,2,2,0
+정보구분값,Information Category,0,String,3,5,2
+정보분배일련번호,Message sequence number,0,Int,5,10,5
+종목코드,ISIN Code,0,String,12,22,10
+정보분배메세지종료키워드,End Keyword,0,String,1,23,22
+";
+
+    fn spec() -> Vec<u8> {
+        EUC_KR.encode(SPEC).0.into_owned()
+    }
+
+    #[test]
+    fn test_layout_registry() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir().join("layout_registry_test");
+        std::fs::create_dir_all(&dir)?;
+        std::fs::write(dir.join("Z999F_v1.csv"), spec())?;
+        std::fs::write(dir.join("README.txt"), "not a spec")?;
+        let registry = LayoutRegistry::load_dir(&dir)?;
+        std::fs::remove_dir_all(&dir)?;
+        assert_eq!(registry.trcodes().collect::<Vec<_>>(), vec!["Z999F"]);
+
+        let mut compressed = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        std::io::Write::write_all(&mut compressed, &spec())?;
+        let embedded = LayoutRegistry::from_embedded(&[("Z999F", compressed.finish()?.as_slice())])?;
+
+        for registry in [&registry, &embedded] {
+            let layout = registry.layout_of(b"Z999F00042KR4165N30007\xff").unwrap();
+            assert_eq!((layout.distidx_range(), layout.instcode_range()), (Some(5..10), Some(10..22)));
+            assert_eq!(layout.range("Data Category"), Some(0..2));
+            assert_eq!(layout.field(INSTCODE_FIELD).unwrap().korean_name, "종목코드");
+            assert_eq!(registry.message_lengths().get(b"Z999F"), Some(23));
+        }
+
+        let payload = b"Z999F00042KR4165N30007\xff";
+        let before = KrxMsg::new_from_payload(20240927, payload, None, None)?;
+        assert_eq!((before.distidx, before.instcode), (None, None));
+        let after = KrxMsgRef::new(20240927, payload, None, None).with_layouts(&registry).to_krx_msg();
+        assert_eq!((after.distidx, after.instcode.as_deref()), (Some(42), Some("KR4165N30007")));
+        // trcodes without a layout keep the built-in offsets
+        let b6 = crate::packet::test_frames::b6_message(7);
+        assert_eq!((registry.distidx_range(&b6), registry.instcode_range(&b6)), (Some(5..13), Some(17..29)));
+        Ok(())
+    }
+}
@@ -1,7 +1,12 @@
 pub mod types;
 pub mod error;
 pub mod packet;
+pub mod compression;
+pub mod filter;
+pub mod layout;
+pub mod validation;
 pub mod mongodb_collection;
+pub mod feed_generator;
 
 pub use error::Error;
 pub use types::timeseries::HftTimeseries;
@@ -12,4 +17,6 @@ pub use types::{
 };
 
 pub use mongodb_collection::krx_msg::KrxMsg;
+pub use mongodb_collection::krx_msg::krx_msg_ref::KrxMsgRef;
+pub use mongodb_collection::krx_msg::trcode::TrCode;
 pub use mongodb_collection::kr_benchmark_bond::KrBenchmarkBond;
\ No newline at end of file
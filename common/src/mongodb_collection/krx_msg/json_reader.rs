@@ -0,0 +1,155 @@
+use std::io::{self, BufRead, Read};
+use std::path::Path;
+use struson::reader::{JsonReader, JsonStreamReader, ReaderSettings, ValueType};
+use crate::compression;
+use crate::filter::Filter;
+use crate::KrxMsg;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Layout {
+    NotStarted,
+    /// `mongoexport --jsonArray`
+    Array,
+    /// one document after another (`mongoexport` default)
+    Documents,
+    Finished,
+}
+
+/// Streams `KrxMsg` out of a JSON dump one document at a time, so memory does not grow with the dump.
+/// Both a JSON array and concatenated documents are accepted.
+pub struct KrxMsgJsonReader<R: Read> {
+    reader: JsonStreamReader<R>,
+    layout: Layout,
+    filter: Option<Filter>,
+}
+
+impl KrxMsgJsonReader<Box<dyn BufRead + Send>> {
+    /// gzip, zstd, xz and 7z dumps are decompressed while reading
+    pub fn from_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(KrxMsgJsonReader::new(compression::open(path)?))
+    }
+}
+
+impl<R: Read> KrxMsgJsonReader<R> {
+    pub fn new(reader: R) -> Self {
+        let settings = ReaderSettings {
+            allow_multiple_top_level: true,
+            ..ReaderSettings::default()
+        };
+        KrxMsgJsonReader {
+            reader: JsonStreamReader::new_custom(reader, settings),
+            layout: Layout::NotStarted,
+            filter: None,
+        }
+    }
+
+    /// Skips documents failing the filter expression, see `Filter::matches_msg`
+    pub fn with_filter(mut self, filter: Filter) -> Self {
+        self.filter = Some(filter);
+        self
+    }
+
+    fn read_next(&mut self) -> Result<Option<KrxMsg>, Box<dyn std::error::Error + Send + Sync>> {
+        match self.layout {
+            Layout::NotStarted => {
+                if self.reader.peek()? == ValueType::Array {
+                    self.reader.begin_array()?;
+                    self.layout = Layout::Array;
+                } else {
+                    self.layout = Layout::Documents;
+                    return Ok(Some(self.reader.deserialize_next()?));
+                }
+            },
+            Layout::Finished => return Ok(None),
+            _ => {},
+        }
+
+        if self.reader.has_next()? {
+            return Ok(Some(self.reader.deserialize_next()?));
+        }
+        if self.layout == Layout::Array {
+            self.reader.end_array()?;
+        }
+        self.layout = Layout::Finished;
+        Ok(None)
+    }
+}
+
+impl<R: Read> Iterator for KrxMsgJsonReader<R> {
+    type Item = io::Result<KrxMsg>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.read_next() {
+                Ok(Some(krx_msg)) if self.filter.as_ref().is_some_and(|filter| !filter.matches_msg(&krx_msg)) => continue,
+                Ok(krx_msg) => return krx_msg.map(Ok),
+                Err(e) => {
+                    // the stream position is unknown after an error
+                    self.layout = Layout::Finished;
+                    return Some(Err(io::Error::new(io::ErrorKind::InvalidData, e)));
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    /// what `mongoexport` writes: payload as `{"$binary": ...}`
+    fn extended_json(krx_msgs: &[KrxMsg]) -> Vec<serde_json::Value> {
+        krx_msgs
+            .iter()
+            .map(|m| m.to_extended_json().unwrap())
+            .collect()
+    }
+
+    fn krx_msgs() -> Vec<KrxMsg> {
+        (1..=3)
+            .map(|i| {
+                let mut payload = format!("B606F{:08}G1  KR4165N30007", i).into_bytes();
+                payload.push(0xff);
+                KrxMsg::new_from_payload(20240927, &payload, Some(i), None).unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_array_and_documents() -> anyhow::Result<()> {
+        let krx_msgs = krx_msgs();
+        let documents = extended_json(&krx_msgs);
+        let array = serde_json::to_string(&documents)?;
+        let documents: String = documents.iter().map(|m| m.to_string() + "\n").collect();
+
+        for json in [array, documents] {
+            let read = KrxMsgJsonReader::new(json.as_bytes()).collect::<io::Result<Vec<_>>>()?;
+            assert_eq!(read.len(), 3);
+            for (read, krx_msg) in read.iter().zip(krx_msgs.iter()) {
+                assert_eq!(read.distidx, krx_msg.distidx);
+                assert_eq!(read.packet_timestamp, krx_msg.packet_timestamp);
+                assert_eq!(read.payload, krx_msg.payload);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_compressed_file() -> anyhow::Result<()> {
+        let path = std::env::temp_dir().join("krx_msg_json_reader_test.json.gz");
+        let mut encoder = flate2::write::GzEncoder::new(std::fs::File::create(&path)?, flate2::Compression::default());
+        serde_json::to_writer(&mut encoder, &extended_json(&krx_msgs()))?;
+        encoder.finish()?.flush()?;
+
+        let read = KrxMsgJsonReader::from_file(&path)?.collect::<io::Result<Vec<_>>>()?;
+        assert_eq!(read.iter().map(|m| m.distidx).collect::<Vec<_>>(), vec![Some(1), Some(2), Some(3)]);
+
+        let read = KrxMsgJsonReader::from_file(&path)?
+            .with_filter(Filter::parse("distidx != 2 and time > 1")?)
+            .collect::<io::Result<Vec<_>>>()?;
+        std::fs::remove_file(&path)?;
+        assert_eq!(read.iter().map(|m| m.distidx).collect::<Vec<_>>(), vec![Some(3)]);
+        Ok(())
+    }
+}
@@ -0,0 +1,150 @@
+use std::str;
+use crate::mongodb_collection::krx_msg::range_helper::{
+    krx_messages_instcode_range,
+    krx_message_dist_index_range,
+};
+use crate::mongodb_collection::krx_msg::trcode::TrCode;
+use crate::layout::LayoutRegistry;
+use crate::{KrxMsg, UnixNano};
+
+/// `KrxMsg` borrowing its payload (e.g., from the capture buffer): nothing is copied and
+/// trcode, instcode and distidx are parsed from the payload only when asked for.
+/// `to_krx_msg` makes the owned message when it has to outlive the payload.
+/// # Arguments
+/// * `date` - yyyymmdd
+/// * `subidx` - position of the message in its packet when a packet carries several messages
+/// * `packet_timestamp` - UnixNano (the time when the packet is received)
+/// * `timestamp` - UnixNano (the time when the message is received on the processor)
+/// * `layouts` - specs instcode and distidx offsets are taken from, the built-in table without one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KrxMsgRef<'a> {
+    pub date: i32,
+    pub subidx: Option<i32>,
+    pub packet_timestamp: Option<UnixNano>,
+    pub timestamp: Option<UnixNano>,
+    pub payload: &'a [u8],
+    pub layouts: Option<&'a LayoutRegistry>,
+}
+
+impl<'a> KrxMsgRef<'a> {
+    pub fn new(
+        date: i32,
+        payload: &'a [u8],
+        packet_timestamp: Option<UnixNano>,
+        timestamp: Option<UnixNano>,
+    ) -> Self {
+        KrxMsgRef { date, subidx: None, packet_timestamp, timestamp, payload, layouts: None }
+    }
+
+    pub fn with_subidx(mut self, subidx: i32) -> Self {
+        self.subidx = Some(subidx);
+        self
+    }
+
+    /// Takes instcode and distidx offsets of the trcodes in the registry from their specs
+    pub fn with_layouts(mut self, layouts: &'a LayoutRegistry) -> Self {
+        self.layouts = Some(layouts);
+        self
+    }
+
+    /// None unless the payload starts with a trcode, see `TrCode::from_bytes`
+    pub fn trcode(&self) -> Option<&'a str> {
+        TrCode::str_from_bytes(self.payload)
+    }
+
+    pub fn parsed_trcode(&self) -> Option<TrCode> {
+        TrCode::from_bytes(self.payload)
+    }
+
+    pub fn instcode(&self) -> Option<&'a str> {
+        self.instcode_bytes().and_then(|bytes| str::from_utf8(bytes).ok())
+    }
+
+    /// None for a trcode without one, or if the bytes are not a number (quite a few messages are blank there)
+    pub fn distidx(&self) -> Option<i32> {
+        let range = match self.layouts {
+            Some(layouts) => layouts.distidx_range(self.payload),
+            None => krx_message_dist_index_range(self.payload),
+        }?;
+        str::from_utf8(self.payload.get(range)?).ok()?.parse().ok()
+    }
+
+    /// Copies the payload and parses trcode, instcode and distidx, as `KrxMsg::new_from_payload`
+    pub fn to_krx_msg(&self) -> KrxMsg {
+        let trcode = self.parsed_trcode();
+        if trcode.is_none() {
+            let pay_clone = self.payload.to_vec();
+            flashlog::flash_info!("DECODE";"Failed to decode trcode"; payload = pay_clone);
+        }
+        let instcode = self.instcode_bytes().and_then(|bytes| match str::from_utf8(bytes) {
+            Ok(instcode) => Some(instcode.to_string()),
+            Err(_) => {
+                let pay_clone = self.payload.to_vec();
+                flashlog::flash_info!("DECODE";"Failed to decode instcode"; payload = pay_clone);
+                None
+            },
+        });
+
+        KrxMsg {
+            date: self.date,
+            trcode,
+            distidx: self.distidx(),
+            subidx: self.subidx,
+            instcode,
+            packet_timestamp: self.packet_timestamp,
+            timestamp: self.timestamp,
+            payload: self.payload.to_vec(),
+        }
+    }
+
+    fn instcode_bytes(&self) -> Option<&'a [u8]> {
+        let range = match self.layouts {
+            Some(layouts) => layouts.instcode_range(self.payload),
+            None => krx_messages_instcode_range(self.payload),
+        }?;
+        self.payload.get(range)
+    }
+}
+
+impl From<KrxMsgRef<'_>> for KrxMsg {
+    fn from(msg: KrxMsgRef<'_>) -> Self {
+        msg.to_krx_msg()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_krx_msg_ref() {
+        let mut payload = b"B606F00000042G1  KR4165N30007".to_vec();
+        payload.resize(323, b' ');
+        payload.push(0xff);
+        let msg_ref = KrxMsgRef::new(20240927, &payload, Some(7), None).with_subidx(1);
+        assert_eq!(msg_ref.trcode(), Some("B606F"));
+        assert_eq!(msg_ref.instcode(), Some("KR4165N30007"));
+        assert_eq!(msg_ref.distidx(), Some(42));
+
+        let msg = KrxMsg::from(msg_ref);
+        assert_eq!((msg.trcode(), msg.instcode.as_deref(), msg.distidx), (Some("B606F"), Some("KR4165N30007"), Some(42)));
+        assert_eq!((msg.subidx, msg.packet_timestamp, msg.payload.as_slice()), (Some(1), Some(7), payload.as_slice()));
+        assert_eq!(msg.as_msg_ref(), msg_ref);
+
+        // too short for the instcode, blank distidx
+        let short = KrxMsgRef::new(20240927, b"B606F        G1  KR41", None, None);
+        assert_eq!((short.instcode(), short.distidx()), (None, None));
+        let undecoded = KrxMsgRef::new(20240927, b"B6", None, None).to_krx_msg();
+        assert_eq!(undecoded.trcode, None);
+        // the same rule for the borrowed and the owned message
+        let blank = KrxMsgRef::new(20240927, b"B6 6F00000042", None, None);
+        assert_eq!((blank.trcode(), blank.to_krx_msg().trcode()), (None, None));
+        // stored as the string, "" when it did not decode
+        let document = mongodb::bson::to_document(&msg).unwrap();
+        assert_eq!(document.get_str("trcode"), Ok("B606F"));
+        assert_eq!(mongodb::bson::from_document::<KrxMsg>(document).unwrap().trcode, msg.trcode);
+        let document = mongodb::bson::to_document(&undecoded).unwrap();
+        assert_eq!(document.get_str("trcode"), Ok(""));
+        assert_eq!(mongodb::bson::from_document::<KrxMsg>(document).unwrap().trcode, None);
+    }
+}
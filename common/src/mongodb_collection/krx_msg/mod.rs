@@ -1,20 +1,23 @@
 pub mod range_helper;
+pub mod json_reader;
+pub mod trcode;
+pub mod krx_msg_ref;
 
 use mongodb::bson::{Binary, spec::BinarySubtype};
 use std::{fmt, str};
 use serde::{Deserialize, Serialize};
 use encoding_rs::EUC_KR;
 use crate::UnixNano;
-use crate::mongodb_collection::krx_msg::range_helper::{
-    krx_messages_instcode_range,
-    krx_message_dist_index_range,
-};
+use crate::mongodb_collection::krx_msg::trcode::{optional_trcode, TrCode};
+use crate::mongodb_collection::krx_msg::krx_msg_ref::KrxMsgRef;
 
 /// # Arguments
 /// * `date` - yyyymmdd
-/// * `trcode` - 5 bytes (first two bytes are data type, last three bytes are asset code, e.g., B606F)
+/// * `trcode` - 5 bytes (first two bytes are data type, last three bytes are asset code, e.g., B606F),
+///   None if the payload does not start with one. Stored as the string, "" for None.
 /// * `instcode` - 12 bytes (e.g., KR4165N30007)
 /// * `dist_index` - distribution index, the order of the message regarding the same trcode.
+/// * `subidx` - position of the message in its packet when a packet carries several messages
 /// * `packet_timestamp` - UnixNano (the time when the packet is received)
 /// * `timestamp` - UnixNano (the time when the message is received on the processor)
 /// * `payload` - binary data
@@ -23,8 +26,11 @@ use crate::mongodb_collection::krx_msg::range_helper::{
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct KrxMsg {
     pub date: i32,
-    pub trcode: String,
+    #[serde(with = "optional_trcode")]
+    pub trcode: Option<TrCode>,
     pub distidx: Option<i32>,
+    #[serde(default)]
+    pub subidx: Option<i32>,
     pub instcode: Option<String>,
     pub packet_timestamp: Option<UnixNano>,
     pub timestamp: Option<UnixNano>,
@@ -51,7 +57,7 @@ impl fmt::Display for KrxMsg {
             f,
             "KrxMsg {{\n  date: {}\n  trcode: {}\n  distidx: {}\n instcode: {}\n  packet_timestamp: {}\n  timestamp: {}\n  payload: {} ({} bytes)\n}}",
             date_str,
-            self.trcode,
+            self.trcode().unwrap_or("None"),
             self.distidx.map_or("None".to_string(), |idx| idx.to_string()),
             self.instcode.as_deref().unwrap_or("None"),
             self.packet_timestamp.map_or("None".to_string(), |ts| ts.to_string()),
@@ -69,70 +75,29 @@ impl KrxMsg {
         packet_timestamp: Option<UnixNano>,
         timestamp: Option<UnixNano>,
     ) -> Result<Self, std::string::FromUtf8Error> {
-        let trcode = if let Ok(trcode) = String::from_utf8(payload[..5].to_vec()) {
-            trcode
-        } else {
-            let pay_clone = payload.to_vec();
-            flashlog::flash_info!("DECODE";"Failed to decode trcode"; payload = pay_clone);
-            "".to_string()
-        };
-        let instcode_range = krx_messages_instcode_range(payload);
-        let instcode = match instcode_range {
-            Some(range) => {
-                if payload.len() < range.end {
-                    None
-                } else {
-                    match String::from_utf8(payload[range.start..range.end].to_vec()) {
-                        Ok(instcode) => Some(instcode),
-                        Err(_) => {
-                            let pay_clone = payload.to_vec();
-                            flashlog::flash_info!("DECODE";"Failed to decode instcode"; payload = pay_clone);
-                            None
-                        }
-                    }
-                }
-            },
-            None => None,
-        };
+        Ok(KrxMsgRef::new(date, payload, packet_timestamp, timestamp).to_krx_msg())
+    }
+
+    /// Borrows the message as a `KrxMsgRef`, e.g., for code taking either
+    pub fn as_msg_ref(&self) -> KrxMsgRef<'_> {
+        KrxMsgRef {
+            date: self.date,
+            subidx: self.subidx,
+            packet_timestamp: self.packet_timestamp,
+            timestamp: self.timestamp,
+            payload: &self.payload,
+            layouts: None,
+        }
+    }
 
-        let distidx_range = krx_message_dist_index_range(payload);
-        let distidx: Option<i32> = match distidx_range {
-            Some(range) => {
-                if payload.len() < range.end {
-                    None
-                } else {
-                    let clipped = &payload[range.start..range.end];
-                    let res = match String::from_utf8_lossy(clipped).parse::<i32>() {
-                        Ok(distidx) => Some(distidx),
-                        Err(_) => {
-                            None
-                            /* there are quite a few messages that are not numbers
-                            // if all whitespace, return None
-                            if clipped.iter().all(|&c| c == 0x20) {
-                                None
-                            } else {
-                                let pay_clone = encoding_rs::EUC_KR.decode(payload).0.to_string();
-                                flashlog::flash_info!("DECODE";"{}: Failed to parse distidx", e; payload = pay_clone);
-                                None
-                            }
-                            */
-                        }
-                    };
-                    res
-                }
-            },
-            None => None,
-        };
+    /// `trcode` as a str. Both this and `KrxMsgRef::trcode` take a trcode only through `TrCode::from_bytes`
+    pub fn trcode(&self) -> Option<&str> {
+        self.trcode.as_ref().map(TrCode::as_str)
+    }
 
-        Ok(Self {
-            date,
-            trcode,
-            distidx,
-            instcode,
-            packet_timestamp,
-            timestamp,
-            payload: payload.to_vec(),
-        })
+    /// What `mongoexport` writes (payload as `{"$binary": ...}`), readable by `KrxMsgJsonReader` and `mongoimport`
+    pub fn to_extended_json(&self) -> Result<serde_json::Value, mongodb::bson::ser::Error> {
+        Ok(mongodb::bson::to_bson(self)?.into_relaxed_extjson())
     }
 }
 mod binary_serde {
@@ -160,97 +125,81 @@ mod binary_serde {
 
 #[cfg(test)]
 mod test {
-    //i want data that payload in ../data/multiasset_db.krx_msg.json include "KR4167"
     use super::*;
+    use crate::feed_generator::b606f_payload;
+    use crate::layout::LayoutRegistry;
     use struson::reader::{JsonStreamReader, JsonReader};
 
-    #[test]
-    fn test_3yr_ktbf() -> anyhow::Result<()> {
-        // read from multiasset_db.krx_msg.json
-        let file_name = "data/krx_msg.json";
-        let file_path = format!("{}", file_name);
-        let file = std::fs::File::open(file_path).unwrap();
-        let reader = std::io::BufReader::new(file);
-        /* non-streaming 
-        let krx_msgs: Vec<KrxMsg> = serde_json::from_reader(reader).unwrap();
-        for krx_msg in krx_msgs {
-            println!("{}", krx_msg);
-        }
-        */
-        // streaming
-        let mut stream_reader = JsonStreamReader::new(reader);
-        stream_reader.begin_array()?;
-        
-        while stream_reader.has_next()? {
-            let krx_msg: KrxMsg = stream_reader.deserialize_next()?;
-            if krx_msg.instcode.is_some() && krx_msg.instcode.as_ref().unwrap().contains("KR4165") {
-                println!("{}", krx_msg);
-            }
+    const INSTCODES: [&str; 3] = ["KR4165N30007", "KR4167N30005", "KR4170N30002"];
+    const MSGS_PER_INSTCODE: usize = 5;
+
+    /// A mongoexport-style dump (a JSON array) of B606F quotes, `MSGS_PER_INSTCODE` for each of `INSTCODES`
+    fn write_fixture(path: &std::path::Path) -> anyhow::Result<()> {
+        let registry = LayoutRegistry::krx()?;
+        let fields = registry.get(b"B606F").unwrap().fields();
+        let mut krx_msgs = Vec::new();
+        for i in 0..INSTCODES.len() * MSGS_PER_INSTCODE {
+            let payload = b606f_payload(fields, i as i64, INSTCODES[i % INSTCODES.len()], 100.0)?.build();
+            let packet_timestamp = 1_727_400_000_000_000_000 + i as UnixNano * 1_000;
+            krx_msgs.push(KrxMsg::new_from_payload(20240927, &payload, Some(packet_timestamp), None)?.to_extended_json()?);
         }
-
-        stream_reader.end_array()?;
-        
+        std::fs::write(path, serde_json::to_vec(&krx_msgs)?)?;
         Ok(())
-
     }
 
-    #[test]
-    fn test_10yr_ktbf() -> anyhow::Result<()> {
-        // read from multiasset_db.krx_msg.json
-        let file_name = "data/krx_msg.json";
-        let file_path = format!("{}", file_name);
-        let file = std::fs::File::open(file_path).unwrap();
+    /// Streams the dump and returns the messages whose instcode contains `instcode`
+    fn read_fixture(path: &std::path::Path, instcode: &str) -> anyhow::Result<Vec<KrxMsg>> {
+        let file = std::fs::File::open(path)?;
         let reader = std::io::BufReader::new(file);
-        /* non-streaming 
-        let krx_msgs: Vec<KrxMsg> = serde_json::from_reader(reader).unwrap();
-        for krx_msg in krx_msgs {
-            println!("{}", krx_msg);
-        }
-        */
-        // streaming
         let mut stream_reader = JsonStreamReader::new(reader);
         stream_reader.begin_array()?;
-        
+
+        let mut found = Vec::new();
         while stream_reader.has_next()? {
             let krx_msg: KrxMsg = stream_reader.deserialize_next()?;
-            if krx_msg.instcode.is_some() && krx_msg.instcode.as_ref().unwrap().contains("KR4167") {
+            if krx_msg.instcode.as_ref().is_some_and(|code| code.contains(instcode)) {
                 println!("{}", krx_msg);
+                found.push(krx_msg);
             }
         }
 
         stream_reader.end_array()?;
-        
+        Ok(found)
+    }
+
+    #[test]
+    fn test_3yr_ktbf() -> anyhow::Result<()> {
+        let path = std::env::temp_dir().join("krx_msg_test_3yr.json");
+        write_fixture(&path)?;
+        let found = read_fixture(&path, "KR4165")?;
+        std::fs::remove_file(&path)?;
+        assert_eq!(found.len(), MSGS_PER_INSTCODE);
+        assert_eq!(found[1].distidx, Some(3));
+        assert_eq!(found[1].trcode(), Some("B606F"));
         Ok(())
+    }
 
+    #[test]
+    fn test_10yr_ktbf() -> anyhow::Result<()> {
+        let path = std::env::temp_dir().join("krx_msg_test_10yr.json");
+        write_fixture(&path)?;
+        let found = read_fixture(&path, "KR4167")?;
+        std::fs::remove_file(&path)?;
+        assert_eq!(found.len(), MSGS_PER_INSTCODE);
+        assert_eq!(found[0].instcode.as_deref(), Some("KR4167N30005"));
+        assert_eq!(found[0].packet_timestamp, Some(1_727_400_000_000_001_000));
+        Ok(())
     }
 
     #[test]
     fn test_30yr_ktbf() -> anyhow::Result<()> {
-        // read from multiasset_db.krx_msg.json
-        let file_name = "data/krx_msg.json";
-        let file_path = format!("{}", file_name);
-        let file = std::fs::File::open(file_path).unwrap();
-        let reader = std::io::BufReader::new(file);
-        /* non-streaming 
-        let krx_msgs: Vec<KrxMsg> = serde_json::from_reader(reader).unwrap();
-        for krx_msg in krx_msgs {
-            println!("{}", krx_msg);
-        }
-        */
-        // streaming
-        let mut stream_reader = JsonStreamReader::new(reader);
-        stream_reader.begin_array()?;
-        
-        while stream_reader.has_next()? {
-            let krx_msg: KrxMsg = stream_reader.deserialize_next()?;
-            if krx_msg.instcode.is_some() && krx_msg.instcode.as_ref().unwrap().contains("KR4170") {
-                println!("{}", krx_msg);
-            }
-        }
-
-        stream_reader.end_array()?;
-        
+        let path = std::env::temp_dir().join("krx_msg_test_30yr.json");
+        write_fixture(&path)?;
+        let found = read_fixture(&path, "KR4170")?;
+        std::fs::remove_file(&path)?;
+        assert_eq!(found.len(), MSGS_PER_INSTCODE);
+        assert_eq!(found[4].distidx, Some(14));
+        assert_eq!(found[4].payload.len(), 324);
         Ok(())
-
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,349 @@
+use std::ops::RangeInclusive;
+use std::{fmt, str};
+use std::str::FromStr;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Market segment, the last byte of a trcode
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Market {
+    /// S, KOSPI stocks
+    Stk,
+    /// Q, KOSDAQ
+    Ksq,
+    /// X, KONEX
+    Knx,
+    /// B, general bonds
+    Bnd,
+    /// K, KTS (government bonds)
+    Kts,
+    /// M, small-lot bonds
+    Smb,
+    /// R, repo
+    Rpo,
+    /// F, derivatives
+    Drv,
+    /// G, commodities
+    Cmd,
+    /// E, emission allowances
+    Ets,
+}
+
+impl Market {
+    const ALL: [Market; 10] = [
+        Market::Stk, Market::Ksq, Market::Knx, Market::Bnd, Market::Kts,
+        Market::Smb, Market::Rpo, Market::Drv, Market::Cmd, Market::Ets,
+    ];
+
+    pub fn from_code(code: u8) -> Option<Market> {
+        Market::ALL.into_iter().find(|market| market.code() == code)
+    }
+
+    /// The byte in the trcode, e.g., b'F' for `Drv`
+    pub fn code(&self) -> u8 {
+        match self {
+            Market::Stk => b'S',
+            Market::Ksq => b'Q',
+            Market::Knx => b'X',
+            Market::Bnd => b'B',
+            Market::Kts => b'K',
+            Market::Smb => b'M',
+            Market::Rpo => b'R',
+            Market::Drv => b'F',
+            Market::Cmd => b'G',
+            Market::Ets => b'E',
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Market::Stk => "STK",
+            Market::Ksq => "KSQ",
+            Market::Knx => "KNX",
+            Market::Bnd => "BND",
+            Market::Kts => "KTS",
+            Market::Smb => "SMB",
+            Market::Rpo => "RPO",
+            Market::Drv => "DRV",
+            Market::Cmd => "CMD",
+            Market::Ets => "ETS",
+        }
+    }
+}
+
+impl fmt::Display for Market {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Market {
+    type Err = String;
+
+    /// "DRV" or the trcode byte "F"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Market::ALL
+            .into_iter()
+            .find(|market| market.as_str() == s || s.as_bytes() == [market.code()])
+            .ok_or_else(|| format!("Unknown market: {}", s))
+    }
+}
+
+/// Message family, the first two bytes of a trcode
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Family {
+    /// inst info excluding ELW/ETN
+    A0,
+    /// ELW/ETN info
+    A1,
+    /// trade
+    A3,
+    /// market close
+    A6,
+    /// quote
+    B6,
+    /// quote with MM/LP together
+    B7,
+    /// investor statistics after market close
+    C1,
+    /// market open
+    C4,
+    /// quote and trade
+    G7,
+    /// derivative investors
+    H1,
+    /// open interest
+    H2,
+    /// underlying bond info of KTBF
+    H6,
+    /// remaining orders
+    OA,
+    Other([u8; 2]),
+}
+
+impl Family {
+    pub fn from_bytes(bytes: [u8; 2]) -> Family {
+        match &bytes {
+            b"A0" => Family::A0,
+            b"A1" => Family::A1,
+            b"A3" => Family::A3,
+            b"A6" => Family::A6,
+            b"B6" => Family::B6,
+            b"B7" => Family::B7,
+            b"C1" => Family::C1,
+            b"C4" => Family::C4,
+            b"G7" => Family::G7,
+            b"H1" => Family::H1,
+            b"H2" => Family::H2,
+            b"H6" => Family::H6,
+            b"OA" => Family::OA,
+            _ => Family::Other(bytes),
+        }
+    }
+}
+
+/// Board/product numbers of a market and the families sent for them.
+/// Messages of these trcodes carry the distribution index at 5..13.
+struct Product {
+    market: Market,
+    numbers: RangeInclusive<u8>,
+    families: &'static [Family],
+}
+
+const PRODUCTS: &[Product] = &[
+    // (증권A) STK : A001S, A301S
+    Product { market: Market::Stk, numbers: 1..=1, families: &[Family::A0, Family::A3] },
+    // (증권C) STK : A002S-A004S, A302S-A304S
+    Product { market: Market::Stk, numbers: 2..=4, families: &[Family::A0, Family::A3] },
+    // (증권B) KSQ : A001Q, A301Q
+    Product { market: Market::Ksq, numbers: 1..=1, families: &[Family::A0, Family::A3] },
+    // (증권B) KNX : A001X, A301X
+    Product { market: Market::Knx, numbers: 1..=1, families: &[Family::A0, Family::A3] },
+    // (채권A) BND : A001B, A301B, B601B, G701B
+    Product { market: Market::Bnd, numbers: 1..=1, families: &[Family::A0, Family::A3, Family::B6, Family::G7] },
+    // (채권A) KTS : A301K, B601K, G701K
+    Product { market: Market::Kts, numbers: 1..=1, families: &[Family::A3, Family::B6, Family::G7] },
+    // (채권A) SMB : A301M, B601M, G701M
+    Product { market: Market::Smb, numbers: 1..=1, families: &[Family::A3, Family::B6, Family::G7] },
+    // (채권A) RPO : A001R, B601R, G701R
+    Product { market: Market::Rpo, numbers: 1..=1, families: &[Family::A0, Family::B6, Family::G7] },
+    // (파생A) DRV : 01-13, 15, 16, (파생B) DRV : 14, e.g., A001F, A306F, B606F, G714F
+    Product { market: Market::Drv, numbers: 1..=16, families: &[Family::A0, Family::A3, Family::B6, Family::G7] },
+    // (일반A) CMD : A001G, A301G, B601G, G701G
+    Product { market: Market::Cmd, numbers: 1..=1, families: &[Family::A0, Family::A3, Family::B6, Family::G7] },
+    // (일반A) ETS : A001E, A301E, B601E, G701E
+    Product { market: Market::Ets, numbers: 1..=1, families: &[Family::A0, Family::A3, Family::B6, Family::G7] },
+];
+
+/// 5 byte KRX transaction code: family (2 bytes), board/product number (2 digits) and market (1 byte),
+/// e.g., B606F = quote (B6) of product 06 in derivatives (F).
+/// Any 5 ASCII alphanumeric bytes parse, so codes like B6054 or J9077 are kept as they are.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TrCode([u8; 5]);
+
+impl TrCode {
+    /// The first 5 bytes of a payload
+    pub fn from_bytes(bytes: &[u8]) -> Option<TrCode> {
+        let code: [u8; 5] = bytes.get(..5)?.try_into().ok()?;
+        code.iter().all(u8::is_ascii_alphanumeric).then_some(TrCode(code))
+    }
+
+    /// The first 5 bytes of a payload as a str, under the same rule as `from_bytes`
+    pub fn str_from_bytes(bytes: &[u8]) -> Option<&str> {
+        TrCode::from_bytes(bytes)?;
+        str::from_utf8(&bytes[..5]).ok()
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 5] {
+        &self.0
+    }
+
+    pub fn as_str(&self) -> &str {
+        // checked to be ASCII on construction
+        str::from_utf8(&self.0).unwrap_or_default()
+    }
+
+    pub fn family(&self) -> Family {
+        Family::from_bytes([self.0[0], self.0[1]])
+    }
+
+    /// Board/product number, None if the bytes are not digits
+    pub fn number(&self) -> Option<u8> {
+        let (tens, ones) = (self.0[2], self.0[3]);
+        (tens.is_ascii_digit() && ones.is_ascii_digit()).then(|| (tens - b'0') * 10 + ones - b'0')
+    }
+
+    pub fn market(&self) -> Option<Market> {
+        Market::from_code(self.0[4])
+    }
+
+    /// B6, B7 and G7
+    pub fn is_quote(&self) -> bool {
+        matches!(self.family(), Family::B6 | Family::B7 | Family::G7)
+    }
+
+    /// A3 and G7
+    pub fn is_trade(&self) -> bool {
+        matches!(self.family(), Family::A3 | Family::G7)
+    }
+
+    /// Found in the product table, i.e., an A0, A3, B6 or G7 code of a product being distributed
+    pub fn is_known_product(&self) -> bool {
+        let (family, number, market) = match (self.number(), self.market()) {
+            (Some(number), Some(market)) => (self.family(), number, market),
+            _ => return false,
+        };
+        PRODUCTS.iter().any(|product| {
+            product.market == market && product.numbers.contains(&number) && product.families.contains(&family)
+        })
+    }
+}
+
+impl fmt::Display for TrCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for TrCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "TrCode({})", self.as_str())
+    }
+}
+
+impl FromStr for TrCode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match TrCode::from_bytes(s.as_bytes()) {
+            Some(trcode) if s.len() == 5 => Ok(trcode),
+            _ => Err(format!("Invalid trcode: {}", s)),
+        }
+    }
+}
+
+impl Serialize for TrCode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for TrCode {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// `Option<TrCode>` as its string, "" for None (a payload whose trcode did not decode),
+/// e.g., `#[serde(with = "optional_trcode")]` on `KrxMsg::trcode`.
+/// Older dumps stored whatever the first 5 bytes were, a value that is not a trcode reads as None.
+pub mod optional_trcode {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(trcode: &Option<TrCode>, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(trcode.as_ref().map_or("", TrCode::as_str))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<TrCode>, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        let trcode = s.parse().ok();
+        if trcode.is_none() && !s.is_empty() {
+            flashlog::flash_info!("DECODE"; "Stored trcode is not a trcode, read as None"; trcode = s);
+        }
+        Ok(trcode)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trcode() -> anyhow::Result<()> {
+        let trcode: TrCode = "B606F".parse().map_err(anyhow::Error::msg)?;
+        assert_eq!((trcode.family(), trcode.number(), trcode.market()), (Family::B6, Some(6), Some(Market::Drv)));
+        assert!(trcode.is_quote() && !trcode.is_trade() && trcode.is_known_product());
+        assert_eq!(trcode.to_string(), "B606F");
+        assert_eq!(serde_json::to_string(&trcode)?, r#""B606F""#);
+        assert_eq!(serde_json::from_str::<TrCode>(r#""G701K""#)?.market(), Some(Market::Kts));
+        assert!(serde_json::from_str::<TrCode>(r#""B60""#).is_err());
+        #[derive(Deserialize)]
+        struct Stored(#[serde(with = "optional_trcode")] Option<TrCode>);
+        for stored in [r#""""#, r#""B60""#, r#""B6 6F""#, r#""B6-6F""#] {
+            assert_eq!(serde_json::from_str::<Stored>(stored)?.0, None, "{}", stored);
+        }
+        assert_eq!(serde_json::from_str::<Stored>(r#""B606F""#)?.0, Some(trcode));
+        assert_eq!(TrCode::from_bytes(b"A301K00000002\xff").map(|trcode| trcode.to_string()), Some("A301K".to_string()));
+        assert!("B606F0".parse::<TrCode>().is_err() && "B6 6F".parse::<TrCode>().is_err());
+        assert_eq!("DRV".parse::<Market>(), Ok(Market::Drv));
+        assert_eq!("F".parse::<Market>(), Ok(Market::Drv));
+
+        let other: TrCode = "J9077".parse().map_err(anyhow::Error::msg)?;
+        assert_eq!((other.family(), other.market()), (Family::Other(*b"J9"), None));
+        assert!(!other.is_known_product());
+        Ok(())
+    }
+
+    #[test]
+    fn test_product_table() {
+        // the trcode lists the product table replaced
+        let known = [
+            "A001S", "A002S", "A003S", "A004S", "A001Q", "A001X", "A001B", "A001R", "A001F", "A002F", "A003F",
+            "A004F", "A005F", "A006F", "A007F", "A008F", "A009F", "A010F", "A011F", "A012F", "A013F", "A015F",
+            "A016F", "A014F", "A001G", "A001E",
+            "B601B", "B601K", "B601M", "B601R", "B601F", "B602F", "B603F", "B604F", "B605F", "B606F", "B607F",
+            "B608F", "B609F", "B610F", "B611F", "B612F", "B613F", "B614F", "B615F", "B616F", "B601G", "B601E",
+            "G701B", "G701K", "G701M", "G701R", "G701F", "G702F", "G703F", "G704F", "G705F", "G706F", "G707F",
+            "G708F", "G709F", "G710F", "G711F", "G712F", "G713F", "G714F", "G715F", "G716F", "G701G", "G701E",
+            "A301S", "A302S", "A303S", "A304S", "A301Q", "A301X", "A301B", "A301M", "A301K", "A301F", "A302F",
+            "A303F", "A304F", "A305F", "A306F", "A307F", "A308F", "A309F", "A310F", "A311F", "A312F", "A313F",
+            "A314F", "A315F", "A316F", "A301G", "A301E",
+        ];
+        for trcode in known {
+            assert!(trcode.parse::<TrCode>().unwrap().is_known_product(), "{}", trcode);
+        }
+        for trcode in ["A001K", "A001M", "A301R", "B601S", "G701Q", "B617F", "A005S", "B602K", "H201F", "B6054"] {
+            assert!(!trcode.parse::<TrCode>().unwrap().is_known_product(), "{}", trcode);
+        }
+    }
+}
@@ -0,0 +1,347 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::iter::Peekable;
+use serde::{Deserialize, Serialize};
+use crate::mongodb_collection::krx_msg::trcode::optional_trcode;
+use crate::{KrxMsg, TrCode, UnixNano};
+
+/// how long a message waits for its copy on the other line
+pub const DEFAULT_ARBITRATION_WINDOW: UnixNano = 3_000_000_000;
+
+/// Upper bounds (ns) of the skew buckets, skew = B arrival - A arrival
+pub const DEFAULT_SKEW_BOUNDS: [i64; 13] = [
+    -100_000_000, -10_000_000, -1_000_000, -100_000, -10_000, -1_000,
+    0,
+    1_000, 10_000, 100_000, 1_000_000, 10_000_000, 100_000_000,
+];
+
+/// Redundant feed line
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Line {
+    A,
+    B,
+}
+
+/// Identity of a message across lines: trcode and distidx, or a hash of the payload when the
+/// message has no distidx
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum MessageKey {
+    Distidx { trcode: Option<TrCode>, distidx: i32 },
+    Payload(u64),
+}
+
+impl MessageKey {
+    pub fn of(msg: &KrxMsg) -> Self {
+        match msg.distidx {
+            Some(distidx) => MessageKey::Distidx {
+                trcode: msg.trcode,
+                distidx,
+            },
+            None => {
+                let mut hasher = DefaultHasher::new();
+                msg.payload.hash(&mut hasher);
+                MessageKey::Payload(hasher.finish())
+            },
+        }
+    }
+}
+
+/// Distribution of the A/B arrival skew (B - A, in ns; positive when A is ahead).
+/// `counts[i]` counts skews below `bounds[i]` (and not below `bounds[i - 1]`), the last count is
+/// for skews at or above the last bound.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SkewHistogram {
+    pub bounds: Vec<i64>,
+    pub counts: Vec<u64>,
+    pub count: u64,
+    pub min: Option<i64>,
+    pub max: Option<i64>,
+    pub sum: i128,
+}
+
+impl SkewHistogram {
+    pub fn new(bounds: Vec<i64>) -> Self {
+        SkewHistogram {
+            counts: vec![0; bounds.len() + 1],
+            bounds,
+            count: 0,
+            min: None,
+            max: None,
+            sum: 0,
+        }
+    }
+
+    pub fn record(&mut self, skew: i64) {
+        let bucket = self.bounds.partition_point(|&bound| bound <= skew);
+        self.counts[bucket] += 1;
+        self.count += 1;
+        self.min = Some(self.min.map_or(skew, |min| min.min(skew)));
+        self.max = Some(self.max.map_or(skew, |max| max.max(skew)));
+        self.sum += skew as i128;
+    }
+
+    pub fn mean(&self) -> Option<f64> {
+        (self.count > 0).then(|| self.sum as f64 / self.count as f64)
+    }
+}
+
+impl Default for SkewHistogram {
+    fn default() -> Self {
+        SkewHistogram::new(DEFAULT_SKEW_BOUNDS.to_vec())
+    }
+}
+
+/// A message whose copy never showed up on the other line
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SingleLineMessage {
+    pub line: Line,
+    #[serde(with = "optional_trcode")]
+    pub trcode: Option<TrCode>,
+    pub distidx: Option<i32>,
+    pub packet_timestamp: UnixNano,
+}
+
+/// # Arguments
+/// * `messages` - messages passed on (first copies)
+/// * `duplicates` - copies dropped because the other line delivered first
+/// * `a_wins`, `b_wins` - matched messages whose first copy came from A / B (A wins ties)
+/// * `same_line_duplicates` - repeated keys on the line that delivered the first copy, dropped
+/// * `skew` - arrival skew of matched messages
+/// * `single_line` - messages seen on one line only
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ArbitrationReport {
+    pub messages: u64,
+    pub duplicates: u64,
+    pub a_wins: u64,
+    pub b_wins: u64,
+    pub same_line_duplicates: u64,
+    pub skew: SkewHistogram,
+    pub single_line: Vec<SingleLineMessage>,
+}
+
+#[derive(Debug)]
+struct FirstCopy {
+    line: Line,
+    timestamp: UnixNano,
+    trcode: Option<TrCode>,
+    distidx: Option<i32>,
+}
+
+/// Merges the A and B lines of a feed by `packet_timestamp` and keeps the first copy of every message.
+/// A message waits `window` for its copy; a copy arriving later is passed on as a new message, and
+/// the first one is reported as single-line.
+/// Each line has to be in timestamp order, as `KrxMsgIter` yields it.
+pub struct LineArbiter<A, B>
+where
+    A: Iterator<Item = io::Result<KrxMsg>>,
+    B: Iterator<Item = io::Result<KrxMsg>>,
+{
+    a: Peekable<A>,
+    b: Peekable<B>,
+    window: UnixNano,
+    pending: HashMap<MessageKey, FirstCopy>,
+    // first copies in arrival order, for expiry
+    arrivals: VecDeque<(UnixNano, MessageKey)>,
+    report: ArbitrationReport,
+}
+
+impl<A, B> LineArbiter<A, B>
+where
+    A: Iterator<Item = io::Result<KrxMsg>>,
+    B: Iterator<Item = io::Result<KrxMsg>>,
+{
+    pub fn new(a: A, b: B) -> Self {
+        LineArbiter {
+            a: a.peekable(),
+            b: b.peekable(),
+            window: DEFAULT_ARBITRATION_WINDOW,
+            pending: HashMap::new(),
+            arrivals: VecDeque::new(),
+            report: ArbitrationReport::default(),
+        }
+    }
+
+    pub fn with_window(mut self, window: UnixNano) -> Self {
+        self.window = window;
+        self
+    }
+
+    pub fn with_skew_bounds(mut self, bounds: Vec<i64>) -> Self {
+        self.report.skew = SkewHistogram::new(bounds);
+        self
+    }
+
+    /// Complete once the iterator is exhausted
+    pub fn report(&self) -> &ArbitrationReport {
+        &self.report
+    }
+
+    pub fn into_report(self) -> ArbitrationReport {
+        self.report
+    }
+
+    /// Next message of either line in timestamp order, A first on ties
+    fn next_of_lines(&mut self) -> Option<io::Result<(Line, KrxMsg)>> {
+        let line = match (self.a.peek(), self.b.peek()) {
+            (None, None) => return None,
+            (Some(_), None) | (Some(Err(_)), _) => Line::A,
+            (None, Some(_)) | (_, Some(Err(_))) => Line::B,
+            (Some(Ok(a)), Some(Ok(b))) => {
+                if arrival(b) < arrival(a) { Line::B } else { Line::A }
+            },
+        };
+        let msg = match line {
+            Line::A => self.a.next(),
+            Line::B => self.b.next(),
+        }?;
+        Some(msg.map(|msg| (line, msg)))
+    }
+
+    fn expire(&mut self, now: Option<UnixNano>) {
+        while let Some((timestamp, _)) = self.arrivals.front() {
+            if now.is_some_and(|now| now.saturating_sub(*timestamp) <= self.window) {
+                break;
+            }
+            let (timestamp, key) = self.arrivals.pop_front().unwrap();
+            // matched or replaced entries are gone or carry another timestamp
+            if self.pending.get(&key).is_some_and(|first| first.timestamp == timestamp) {
+                let first = self.pending.remove(&key).unwrap();
+                self.report.single_line.push(SingleLineMessage {
+                    line: first.line,
+                    trcode: first.trcode,
+                    distidx: first.distidx,
+                    packet_timestamp: first.timestamp,
+                });
+            }
+        }
+    }
+}
+
+impl<A, B> Iterator for LineArbiter<A, B>
+where
+    A: Iterator<Item = io::Result<KrxMsg>>,
+    B: Iterator<Item = io::Result<KrxMsg>>,
+{
+    type Item = io::Result<(Line, KrxMsg)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (line, msg) = match self.next_of_lines() {
+                Some(Ok(next)) => next,
+                Some(Err(e)) => return Some(Err(e)),
+                None => {
+                    self.expire(None);
+                    return None;
+                },
+            };
+            let timestamp = arrival(&msg);
+            self.expire(Some(timestamp));
+
+            let key = MessageKey::of(&msg);
+            match self.pending.get(&key) {
+                Some(first) if first.line == line => {
+                    self.report.same_line_duplicates += 1;
+                },
+                Some(first) => {
+                    let skew = match first.line {
+                        Line::A => timestamp as i64 - first.timestamp as i64,
+                        Line::B => first.timestamp as i64 - timestamp as i64,
+                    };
+                    self.report.skew.record(skew);
+                    match first.line {
+                        Line::A => self.report.a_wins += 1,
+                        Line::B => self.report.b_wins += 1,
+                    }
+                    self.report.duplicates += 1;
+                    self.pending.remove(&key);
+                },
+                None => {
+                    self.pending.insert(key.clone(), FirstCopy {
+                        line,
+                        timestamp,
+                        trcode: msg.trcode,
+                        distidx: msg.distidx,
+                    });
+                    self.arrivals.push_back((timestamp, key));
+                    self.report.messages += 1;
+                    return Some(Ok((line, msg)));
+                },
+            }
+        }
+    }
+}
+
+fn arrival(msg: &KrxMsg) -> UnixNano {
+    msg.packet_timestamp.or(msg.timestamp).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::test_frames::b6_message;
+
+    fn msg(distidx: u32, timestamp: UnixNano) -> io::Result<KrxMsg> {
+        Ok(KrxMsg::new_from_payload(20240927, &b6_message(distidx), Some(timestamp), None).unwrap())
+    }
+
+    fn no_distidx(text: &str, timestamp: UnixNano) -> io::Result<KrxMsg> {
+        Ok(KrxMsg::new_from_payload(20240927, text.as_bytes(), Some(timestamp), None).unwrap())
+    }
+
+    #[test]
+    fn test_first_copy_wins() -> anyhow::Result<()> {
+        let a = vec![
+            msg(1, 1_000),
+            msg(2, 3_000),
+            no_distidx("A301S heartbeat", 4_000),
+            msg(3, 10_000),
+            msg(4, 20_000_000_000),
+        ];
+        let b = vec![
+            msg(1, 1_500),
+            msg(2, 2_000),
+            no_distidx("A301S heartbeat", 4_200),
+            msg(5, 11_000),
+        ];
+        let mut arbiter = LineArbiter::new(a.into_iter(), b.into_iter()).with_window(1_000_000_000);
+        let merged: Vec<(Line, Option<i32>)> = arbiter
+            .by_ref()
+            .map(|next| next.map(|(line, msg)| (line, msg.distidx)))
+            .collect::<io::Result<_>>()?;
+        assert_eq!(merged, vec![
+            (Line::A, Some(1)),
+            (Line::B, Some(2)),
+            (Line::A, None),
+            (Line::A, Some(3)),
+            (Line::B, Some(5)),
+            (Line::A, Some(4)),
+        ]);
+
+        let report = arbiter.into_report();
+        assert_eq!((report.messages, report.duplicates), (6, 3));
+        assert_eq!((report.a_wins, report.b_wins), (2, 1));
+        assert_eq!(report.skew.count, 3);
+        assert_eq!((report.skew.min, report.skew.max), (Some(-1_000), Some(500)));
+        // -1us, then 200ns and 500ns
+        assert_eq!(report.skew.counts[6], 1);
+        assert_eq!(report.skew.counts[7], 2);
+
+        let single: Vec<(Line, Option<i32>)> = report.single_line.iter().map(|m| (m.line, m.distidx)).collect();
+        assert_eq!(single, vec![(Line::A, Some(3)), (Line::B, Some(5)), (Line::A, Some(4))]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_late_copy_is_a_new_message() -> anyhow::Result<()> {
+        let a = vec![msg(1, 0)];
+        let b = vec![msg(1, 2_000)];
+        let mut arbiter = LineArbiter::new(a.into_iter(), b.into_iter()).with_window(1_000);
+        assert_eq!(arbiter.by_ref().count(), 2);
+        let report = arbiter.report();
+        assert_eq!(report.duplicates, 0);
+        assert_eq!(report.single_line.len(), 2);
+        Ok(())
+    }
+}
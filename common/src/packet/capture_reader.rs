@@ -0,0 +1,588 @@
+use std::io::{self, BufRead, Read, Write};
+use std::path::Path;
+use pcap::Linktype;
+use crate::compression;
+use crate::UnixNano;
+
+pub const PCAP_MAGIC_MICRO: u32 = 0xa1b2_c3d4;
+pub const PCAP_MAGIC_NANO: u32 = 0xa1b2_3c4d;
+pub const PCAP_FILE_HEADER_LEN: usize = 24;
+pub const PCAP_RECORD_HEADER_LEN: usize = 16;
+const PCAPNG_SHB: u32 = 0x0a0d_0d0a;
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1a2b_3c4d;
+
+const PCAPNG_IDB: u32 = 0x0000_0001;
+const PCAPNG_OPB: u32 = 0x0000_0002;
+const PCAPNG_SPB: u32 = 0x0000_0003;
+const PCAPNG_EPB: u32 = 0x0000_0006;
+
+const OPT_END_OF_OPT: u16 = 0;
+const OPT_IF_TSRESOL: u16 = 9;
+const OPT_IF_TSOFFSET: u16 = 14;
+
+/// upper bound on a single record/block, anything larger is treated as a corrupt file
+pub(crate) const MAX_BLOCK_LEN: usize = 16 * 1024 * 1024;
+
+/// snaplen in the header `write_pcap_header` writes
+const WRITE_SNAPLEN: u32 = 0x0004_0000;
+
+/// (timestamp, original length, captured bytes) of a frame held back for writing,
+/// e.g., a fragment until its datagram is complete
+pub type OwnedFrame = (UnixNano, u32, Vec<u8>);
+
+/// File header of a little-endian nanosecond pcap (version 2.4), followed by `write_pcap_record`s
+pub fn write_pcap_header(output: &mut dyn Write, linktype: Linktype) -> io::Result<()> {
+    for word in [PCAP_MAGIC_NANO, 0x0004_0002, 0, 0, WRITE_SNAPLEN, linktype.0 as u32] {
+        output.write_all(&word.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+/// One record of a pcap started by `write_pcap_header`
+pub fn write_pcap_record(output: &mut dyn Write, timestamp: UnixNano, orig_len: u32, data: &[u8]) -> io::Result<()> {
+    output.write_all(&((timestamp / 1_000_000_000) as u32).to_le_bytes())?;
+    output.write_all(&((timestamp % 1_000_000_000) as u32).to_le_bytes())?;
+    output.write_all(&(data.len() as u32).to_le_bytes())?;
+    output.write_all(&orig_len.to_le_bytes())?;
+    output.write_all(data)
+}
+
+/// One packet out of a capture file or a live capture.
+/// # Arguments
+/// * `timestamp` - UnixNano, scaled from the file's resolution (SPB records carry no timestamp and get 0)
+/// * `linktype` - datalink type of the interface the packet was captured on
+/// * `interface_id` - pcapng interface index (always 0 for pcap)
+/// * `orig_len` - length on the wire, `data` may be shorter if the capture was truncated by snaplen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CaptureRecord<'a> {
+    pub timestamp: UnixNano,
+    pub linktype: Linktype,
+    pub interface_id: u32,
+    pub orig_len: u32,
+    pub data: &'a [u8],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptureFormat {
+    /// classic pcap, microsecond timestamps
+    PcapMicro,
+    /// classic pcap, nanosecond timestamps
+    PcapNano,
+    PcapNg,
+}
+
+/// Timestamp resolution of a pcapng interface (if_tsresol)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TsResolution {
+    /// 10^-n seconds per unit
+    Decimal(u8),
+    /// 2^-n seconds per unit
+    Binary(u8),
+}
+
+impl TsResolution {
+    pub fn from_option(value: u8) -> Self {
+        if value & 0x80 == 0 {
+            TsResolution::Decimal(value)
+        } else {
+            TsResolution::Binary(value & 0x7f)
+        }
+    }
+
+    /// Saturates at `UnixNano::MAX`, if_tsresol comes from the file and may be anything
+    pub fn to_nanos(&self, units: u64) -> UnixNano {
+        let nanos = match *self {
+            TsResolution::Decimal(n) if n <= 9 => units as u128 * 10u128.pow(9 - n as u32),
+            // 10^39 and up do not fit in u128, any u64 count of such units is below a nanosecond
+            TsResolution::Decimal(n) => 10u128.checked_pow(n as u32 - 9).map_or(0, |divisor| units as u128 / divisor),
+            TsResolution::Binary(n) => (units as u128 * 1_000_000_000) >> n,
+        };
+        nanos.min(UnixNano::MAX as u128) as UnixNano
+    }
+}
+
+impl Default for TsResolution {
+    /// pcapng default when if_tsresol is absent: microseconds
+    fn default() -> Self {
+        TsResolution::Decimal(6)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Interface {
+    linktype: Linktype,
+    snaplen: u32,
+    resolution: TsResolution,
+    offset_secs: i64,
+}
+
+/// Reads pcap (micro/nanosecond, either byte order) and pcapng (multiple sections/interfaces,
+/// per-interface if_tsresol and if_tsoffset) without going through libpcap.
+pub struct CaptureReader<R: Read> {
+    reader: R,
+    format: CaptureFormat,
+    big_endian: bool,
+    linktype: Linktype,
+    interfaces: Vec<Interface>,
+    // type of a pcapng packet block read ahead (into `buf`) while looking for the first interface
+    pending: Option<u32>,
+    buf: Vec<u8>,
+}
+
+/// Where the pipeline reads packets from: a capture file (`CaptureReader`) or an interface (`LiveCapture`)
+pub trait RecordSource {
+    fn linktype(&self) -> Linktype;
+
+    /// Returns `Ok(None)` at the end of the input
+    fn next_record(&mut self) -> io::Result<Option<CaptureRecord<'_>>>;
+}
+
+impl<R: Read> RecordSource for CaptureReader<R> {
+    fn linktype(&self) -> Linktype {
+        CaptureReader::linktype(self)
+    }
+
+    fn next_record(&mut self) -> io::Result<Option<CaptureRecord<'_>>> {
+        CaptureReader::next_record(self)
+    }
+}
+
+impl CaptureReader<Box<dyn BufRead + Send>> {
+    /// gzip, zstd, xz and 7z captures are decompressed while reading
+    pub fn from_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        CaptureReader::new(compression::open(path)?)
+    }
+}
+
+impl<R: Read> CaptureReader<R> {
+    /// Reads the file header (and, for pcapng, the blocks up to the first interface)
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+
+        let mut capture_reader = CaptureReader {
+            reader,
+            format: CaptureFormat::PcapMicro,
+            big_endian: false,
+            linktype: Linktype::ETHERNET,
+            interfaces: Vec::new(),
+            pending: None,
+            buf: Vec::new(),
+        };
+
+        if u32::from_le_bytes(magic) == PCAPNG_SHB {
+            capture_reader.format = CaptureFormat::PcapNg;
+            let mut raw_len = [0u8; 4];
+            capture_reader.reader.read_exact(&mut raw_len)?;
+            capture_reader.read_section_header(raw_len)?;
+            // surface the first interface's linktype before any packet is requested
+            while capture_reader.interfaces.is_empty() {
+                match capture_reader.read_block()? {
+                    Some(block_type) if is_packet_block(block_type) => {
+                        capture_reader.pending = Some(block_type);
+                        break;
+                    },
+                    Some(_) => {},
+                    None => break,
+                }
+            }
+            return Ok(capture_reader);
+        }
+
+        let (format, big_endian) = match (u32::from_le_bytes(magic), u32::from_be_bytes(magic)) {
+            (PCAP_MAGIC_MICRO, _) => (CaptureFormat::PcapMicro, false),
+            (PCAP_MAGIC_NANO, _) => (CaptureFormat::PcapNano, false),
+            (_, PCAP_MAGIC_MICRO) => (CaptureFormat::PcapMicro, true),
+            (_, PCAP_MAGIC_NANO) => (CaptureFormat::PcapNano, true),
+            _ => return Err(invalid_data("not a pcap or pcapng file")),
+        };
+        capture_reader.format = format;
+        capture_reader.big_endian = big_endian;
+
+        let mut header = [0u8; 20];
+        capture_reader.reader.read_exact(&mut header)?;
+        // network field: the upper bits hold FCS information
+        let network = capture_reader.u32_at(&header, 16) & 0x0fff_ffff;
+        capture_reader.linktype = Linktype(network as i32);
+        Ok(capture_reader)
+    }
+
+    pub fn format(&self) -> CaptureFormat {
+        self.format
+    }
+
+    /// Linktype of the pcap file, or of the first pcapng interface
+    pub fn linktype(&self) -> Linktype {
+        self.linktype
+    }
+
+    /// Returns `Ok(None)` at a clean end of file. A record cut off mid-way is an `UnexpectedEof` error.
+    pub fn next_record(&mut self) -> io::Result<Option<CaptureRecord<'_>>> {
+        match self.format {
+            CaptureFormat::PcapMicro | CaptureFormat::PcapNano => self.next_pcap_record(),
+            CaptureFormat::PcapNg => self.next_pcapng_record(),
+        }
+    }
+
+    fn next_pcap_record(&mut self) -> io::Result<Option<CaptureRecord<'_>>> {
+        let mut header = [0u8; 16];
+        if !read_exact_or_eof(&mut self.reader, &mut header)? {
+            return Ok(None);
+        }
+        let ts_sec = self.u32_at(&header, 0) as UnixNano;
+        let ts_frac = self.u32_at(&header, 4) as UnixNano;
+        let caplen = self.u32_at(&header, 8) as usize;
+        let orig_len = self.u32_at(&header, 12);
+        if caplen > MAX_BLOCK_LEN {
+            return Err(invalid_data("pcap record length is too large"));
+        }
+
+        self.buf.resize(caplen, 0);
+        self.reader.read_exact(&mut self.buf)?;
+
+        let timestamp = match self.format {
+            CaptureFormat::PcapNano => ts_sec * 1_000_000_000 + ts_frac,
+            _ => ts_sec * 1_000_000_000 + ts_frac * 1_000,
+        };
+        Ok(Some(CaptureRecord {
+            timestamp,
+            linktype: self.linktype,
+            interface_id: 0,
+            orig_len,
+            data: &self.buf,
+        }))
+    }
+
+    fn next_pcapng_record(&mut self) -> io::Result<Option<CaptureRecord<'_>>> {
+        loop {
+            let block_type = match self.pending.take() {
+                Some(block) => block,
+                None => match self.read_block()? {
+                    Some(block) => block,
+                    None => return Ok(None),
+                },
+            };
+            if !is_packet_block(block_type) {
+                continue;
+            }
+
+            let (interface_id, ts_units, caplen, orig_len, data_start) = match block_type {
+                PCAPNG_EPB => {
+                    self.check_len(20)?;
+                    let ts = ((self.u32_at(&self.buf, 4) as u64) << 32) | self.u32_at(&self.buf, 8) as u64;
+                    (self.u32_at(&self.buf, 0), Some(ts), self.u32_at(&self.buf, 12) as usize, self.u32_at(&self.buf, 16), 20)
+                },
+                PCAPNG_OPB => {
+                    self.check_len(20)?;
+                    let interface_id = self.u16_at(&self.buf, 0) as u32;
+                    let ts = ((self.u32_at(&self.buf, 4) as u64) << 32) | self.u32_at(&self.buf, 8) as u64;
+                    (interface_id, Some(ts), self.u32_at(&self.buf, 12) as usize, self.u32_at(&self.buf, 16), 20)
+                },
+                _ => {
+                    // SPB: implicit interface 0, no timestamp, caplen bounded by the snaplen
+                    self.check_len(4)?;
+                    let orig_len = self.u32_at(&self.buf, 0);
+                    let snaplen = self.interface(0)?.snaplen;
+                    let mut caplen = orig_len as usize;
+                    if snaplen != 0 {
+                        caplen = caplen.min(snaplen as usize);
+                    }
+                    (0, None, caplen.min(self.buf.len() - 4), orig_len, 4)
+                },
+            };
+            self.check_len(data_start + caplen)?;
+
+            let interface = self.interface(interface_id)?;
+            let timestamp = match ts_units {
+                Some(units) => {
+                    let nanos = interface.resolution.to_nanos(units) as i128;
+                    (nanos + interface.offset_secs as i128 * 1_000_000_000).clamp(0, UnixNano::MAX as i128) as UnixNano
+                },
+                None => 0,
+            };
+            return Ok(Some(CaptureRecord {
+                timestamp,
+                linktype: interface.linktype,
+                interface_id,
+                orig_len,
+                data: &self.buf[data_start..data_start + caplen],
+            }));
+        }
+    }
+
+    fn interface(&self, interface_id: u32) -> io::Result<Interface> {
+        self.interfaces
+            .get(interface_id as usize)
+            .copied()
+            .ok_or_else(|| invalid_data("pcapng packet refers to an undefined interface"))
+    }
+
+    fn check_len(&self, len: usize) -> io::Result<()> {
+        if self.buf.len() < len {
+            return Err(invalid_data("pcapng block is shorter than its fields"));
+        }
+        Ok(())
+    }
+
+    /// The block type and the (not yet decodable) block length have already been consumed.
+    /// Determines the byte order and drops the interfaces of the previous section.
+    fn read_section_header(&mut self, raw_len: [u8; 4]) -> io::Result<()> {
+        let mut magic = [0u8; 4];
+        self.reader.read_exact(&mut magic)?;
+        self.big_endian = match u32::from_le_bytes(magic) {
+            PCAPNG_BYTE_ORDER_MAGIC => false,
+            m if m.swap_bytes() == PCAPNG_BYTE_ORDER_MAGIC => true,
+            _ => return Err(invalid_data("bad pcapng byte-order magic")),
+        };
+        let total_len = self.u32_at(&raw_len, 0) as usize;
+        if !(28..=MAX_BLOCK_LEN).contains(&total_len) || !total_len.is_multiple_of(4) {
+            return Err(invalid_data("bad pcapng section header length"));
+        }
+        let remaining = (total_len - 12) as u64;
+        if io::copy(&mut (&mut self.reader).take(remaining), &mut io::sink())? != remaining {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "capture file is truncated"));
+        }
+        self.interfaces.clear();
+        Ok(())
+    }
+
+    /// Reads blocks, handling section and interface blocks internally, and returns the type of
+    /// the next other block. Its body (without the type/length framing) is left in `buf`.
+    fn read_block(&mut self) -> io::Result<Option<u32>> {
+        loop {
+            let mut head = [0u8; 8];
+            if !read_exact_or_eof(&mut self.reader, &mut head)? {
+                return Ok(None);
+            }
+            if u32::from_le_bytes(head[..4].try_into().unwrap()) == PCAPNG_SHB {
+                self.read_section_header(head[4..8].try_into().unwrap())?;
+                continue;
+            }
+
+            let block_type = self.u32_at(&head, 0);
+            let total_len = self.u32_at(&head, 4) as usize;
+            if !(12..=MAX_BLOCK_LEN).contains(&total_len) || !total_len.is_multiple_of(4) {
+                return Err(invalid_data("bad pcapng block length"));
+            }
+            // body plus the trailing copy of the block length
+            self.buf.resize(total_len - 8, 0);
+            self.reader.read_exact(&mut self.buf)?;
+            self.buf.truncate(total_len - 12);
+
+            if block_type == PCAPNG_IDB {
+                let interface = self.parse_interface(&self.buf)?;
+                if self.interfaces.is_empty() {
+                    self.linktype = interface.linktype;
+                }
+                self.interfaces.push(interface);
+                continue;
+            }
+            return Ok(Some(block_type));
+        }
+    }
+
+    fn parse_interface(&self, body: &[u8]) -> io::Result<Interface> {
+        if body.len() < 8 {
+            return Err(invalid_data("pcapng interface block is too short"));
+        }
+        let mut interface = Interface {
+            linktype: Linktype(self.u16_at(body, 0) as i32),
+            snaplen: self.u32_at(body, 4),
+            resolution: TsResolution::default(),
+            offset_secs: 0,
+        };
+
+        let mut pos = 8;
+        while pos + 4 <= body.len() {
+            let code = self.u16_at(body, pos);
+            let len = self.u16_at(body, pos + 2) as usize;
+            pos += 4;
+            if code == OPT_END_OF_OPT || pos + len > body.len() {
+                break;
+            }
+            match code {
+                OPT_IF_TSRESOL if len >= 1 => {
+                    interface.resolution = TsResolution::from_option(body[pos]);
+                },
+                OPT_IF_TSOFFSET if len >= 8 => {
+                    let bytes: [u8; 8] = body[pos..pos + 8].try_into().unwrap();
+                    interface.offset_secs = if self.big_endian {
+                        i64::from_be_bytes(bytes)
+                    } else {
+                        i64::from_le_bytes(bytes)
+                    };
+                },
+                _ => {},
+            }
+            pos += (len + 3) & !3;
+        }
+        Ok(interface)
+    }
+
+    fn u16_at(&self, bytes: &[u8], pos: usize) -> u16 {
+        let raw: [u8; 2] = bytes[pos..pos + 2].try_into().unwrap();
+        if self.big_endian { u16::from_be_bytes(raw) } else { u16::from_le_bytes(raw) }
+    }
+
+    fn u32_at(&self, bytes: &[u8], pos: usize) -> u32 {
+        let raw: [u8; 4] = bytes[pos..pos + 4].try_into().unwrap();
+        if self.big_endian { u32::from_be_bytes(raw) } else { u32::from_le_bytes(raw) }
+    }
+}
+
+fn is_packet_block(block_type: u32) -> bool {
+    matches!(block_type, PCAPNG_EPB | PCAPNG_OPB | PCAPNG_SPB)
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// `Ok(false)` if the reader is exhausted before the first byte, `UnexpectedEof` if it ends part-way
+fn read_exact_or_eof<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<bool> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) if filled == 0 => return Ok(false),
+            Ok(0) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "capture file is truncated")),
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {},
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pcapng_block(block_type: u32, body: &[u8]) -> Vec<u8> {
+        let padded = (body.len() + 3) & !3;
+        let total_len = (padded + 12) as u32;
+        let mut block = Vec::new();
+        block.extend_from_slice(&block_type.to_le_bytes());
+        block.extend_from_slice(&total_len.to_le_bytes());
+        block.extend_from_slice(body);
+        block.resize(8 + padded, 0);
+        block.extend_from_slice(&total_len.to_le_bytes());
+        block
+    }
+
+    fn section_header() -> Vec<u8> {
+        let mut body = Vec::new();
+        body.extend_from_slice(&PCAPNG_BYTE_ORDER_MAGIC.to_le_bytes());
+        body.extend_from_slice(&1u16.to_le_bytes());
+        body.extend_from_slice(&0u16.to_le_bytes());
+        body.extend_from_slice(&(-1i64).to_le_bytes());
+        pcapng_block(PCAPNG_SHB, &body)
+    }
+
+    fn interface_block(linktype: u16, tsresol: Option<u8>) -> Vec<u8> {
+        let mut body = Vec::new();
+        body.extend_from_slice(&linktype.to_le_bytes());
+        body.extend_from_slice(&0u16.to_le_bytes());
+        body.extend_from_slice(&65535u32.to_le_bytes());
+        if let Some(tsresol) = tsresol {
+            body.extend_from_slice(&OPT_IF_TSRESOL.to_le_bytes());
+            body.extend_from_slice(&1u16.to_le_bytes());
+            body.extend_from_slice(&[tsresol, 0, 0, 0]);
+            body.extend_from_slice(&[0, 0, 0, 0]);
+        }
+        pcapng_block(PCAPNG_IDB, &body)
+    }
+
+    fn enhanced_packet_block(interface_id: u32, ts_units: u64, data: &[u8]) -> Vec<u8> {
+        let mut body = Vec::new();
+        body.extend_from_slice(&interface_id.to_le_bytes());
+        body.extend_from_slice(&((ts_units >> 32) as u32).to_le_bytes());
+        body.extend_from_slice(&(ts_units as u32).to_le_bytes());
+        body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        body.extend_from_slice(data);
+        pcapng_block(PCAPNG_EPB, &body)
+    }
+
+    #[test]
+    fn test_nanosecond_pcap() -> anyhow::Result<()> {
+        let mut file = Vec::new();
+        for word in [PCAP_MAGIC_NANO, 0x0004_0002, 0, 0, 65535, 1] {
+            file.extend_from_slice(&word.to_be_bytes());
+        }
+        for word in [1_727_400_000u32, 123_456_789, 3, 3] {
+            file.extend_from_slice(&word.to_be_bytes());
+        }
+        file.extend_from_slice(b"abc");
+
+        let mut reader = CaptureReader::new(file.as_slice())?;
+        assert_eq!(reader.format(), CaptureFormat::PcapNano);
+        assert_eq!(reader.linktype(), Linktype::ETHERNET);
+        let record = reader.next_record()?.unwrap();
+        assert_eq!(record.timestamp, 1_727_400_000_123_456_789);
+        assert_eq!(record.data, b"abc");
+        assert!(reader.next_record()?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_pcapng_per_interface_resolution() -> anyhow::Result<()> {
+        let mut file = section_header();
+        file.extend(interface_block(1, None));
+        file.extend(interface_block(113, Some(9)));
+        file.extend(enhanced_packet_block(0, 1_727_400_000_123_456, b"usec"));
+        file.extend(enhanced_packet_block(1, 1_727_400_000_123_456_789, b"nsec"));
+
+        let mut reader = CaptureReader::new(file.as_slice())?;
+        assert_eq!(reader.format(), CaptureFormat::PcapNg);
+        assert_eq!(reader.linktype(), Linktype::ETHERNET);
+
+        let record = reader.next_record()?.unwrap();
+        assert_eq!((record.interface_id, record.timestamp), (0, 1_727_400_000_123_456_000));
+        assert_eq!(record.data, b"usec");
+
+        let record = reader.next_record()?.unwrap();
+        assert_eq!((record.interface_id, record.timestamp), (1, 1_727_400_000_123_456_789));
+        assert_eq!(record.linktype, Linktype::LINUX_SLL);
+        assert_eq!(record.data, b"nsec");
+
+        assert!(reader.next_record()?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_compressed_file() -> anyhow::Result<()> {
+        let mut file = section_header();
+        file.extend(interface_block(1, Some(9)));
+        for i in 0..1000u64 {
+            file.extend(enhanced_packet_block(0, 1_727_400_000_000_000_000 + i, b"abc"));
+        }
+        let path = std::env::temp_dir().join("capture_reader_test.pcapng.zst");
+        zstd::stream::copy_encode(file.as_slice(), std::fs::File::create(&path)?, 0)?;
+
+        let mut reader = CaptureReader::from_file(&path)?;
+        let mut count = 0;
+        while let Some(record) = reader.next_record()? {
+            assert_eq!(record.timestamp, 1_727_400_000_000_000_000 + count);
+            count += 1;
+        }
+        std::fs::remove_file(&path)?;
+        assert_eq!(count, 1000);
+        Ok(())
+    }
+
+    #[test]
+    fn test_binary_resolution() {
+        assert_eq!(TsResolution::from_option(0x80 | 10).to_nanos(1024 * 3 + 512), 3_500_000_000);
+        assert_eq!(TsResolution::from_option(12).to_nanos(1_500), 1);
+    }
+
+    #[test]
+    fn test_resolution_out_of_range() {
+        assert_eq!(TsResolution::from_option(127).to_nanos(u64::MAX), 0);
+        assert_eq!(TsResolution::from_option(48).to_nanos(u64::MAX), 0);
+        assert_eq!(TsResolution::from_option(0).to_nanos(u64::MAX), UnixNano::MAX);
+        assert_eq!(TsResolution::from_option(3).to_nanos(u64::MAX / 2), UnixNano::MAX);
+        assert_eq!(TsResolution::from_option(0x80).to_nanos(u64::MAX), UnixNano::MAX);
+        assert_eq!(TsResolution::from_option(0x80 | 127).to_nanos(u64::MAX), 0);
+    }
+}
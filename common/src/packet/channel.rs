@@ -0,0 +1,158 @@
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::net::IpAddr;
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use crate::packet::decoder::{DecodedPacket, TransportProtocol};
+
+/// Selects a feed channel by its addressing. Unset fields match anything.
+/// # Arguments
+/// * `group` - destination address, the multicast group for UDP feeds (e.g., 233.37.54.1)
+/// * `port` - destination port
+/// * `source` - sender address
+/// * `protocol` - Udp or Tcp
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChannelFilter {
+    #[serde(default)]
+    pub group: Option<IpAddr>,
+    #[serde(default)]
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub source: Option<IpAddr>,
+    #[serde(default)]
+    pub protocol: Option<TransportProtocol>,
+}
+
+impl ChannelFilter {
+    pub fn new(group: Option<IpAddr>, port: Option<u16>, source: Option<IpAddr>, protocol: Option<TransportProtocol>) -> Self {
+        ChannelFilter {
+            group,
+            port,
+            source,
+            protocol,
+        }
+    }
+
+    pub fn matches(&self, packet: &DecodedPacket) -> bool {
+        self.group.is_none_or(|group| group == packet.dst)
+            && self.port.is_none_or(|port| port == packet.dst_port)
+            && self.source.is_none_or(|source| source == packet.src)
+            && self.protocol.is_none_or(|protocol| protocol == packet.protocol)
+    }
+}
+
+/// Returns true if the packet belongs to one of the channels, or if there is no filter.
+pub fn matches_channel(channel_filter: &Option<Vec<ChannelFilter>>, packet: &DecodedPacket) -> bool {
+    match channel_filter {
+        Some(ref channels) => channels.iter().any(|channel| channel.matches(packet)),
+        None => true,
+    }
+}
+
+/// A named feed channel, e.g., "derivatives A line"
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Channel {
+    pub name: String,
+    #[serde(flatten)]
+    pub filter: ChannelFilter,
+}
+
+/// Named channels so that jobs can refer to a line by name instead of listing addresses.
+/// The table is site-specific (it depends on the subscribed lines), so it is loaded from JSON:
+/// ```json
+/// [
+///     {"name": "derivatives A line", "group": "233.37.54.1", "port": 20001, "protocol": "Udp"},
+///     {"name": "derivatives B line", "group": "233.37.54.2", "port": 20001, "protocol": "Udp"}
+/// ]
+/// ```
+/// Names are matched case-insensitively.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChannelTable {
+    channels: Vec<Channel>,
+}
+
+impl ChannelTable {
+    pub fn new(channels: Vec<Channel>) -> Self {
+        ChannelTable { channels }
+    }
+
+    pub fn load_from_json<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let reader = BufReader::new(File::open(path)?);
+        let channels: Vec<Channel> = serde_json::from_reader(reader)?;
+        Ok(ChannelTable { channels })
+    }
+
+    /// Adds a channel, replacing one with the same name
+    pub fn insert(&mut self, name: &str, filter: ChannelFilter) {
+        match self.channels.iter_mut().find(|channel| channel.name.eq_ignore_ascii_case(name)) {
+            Some(channel) => channel.filter = filter,
+            None => self.channels.push(Channel { name: name.to_string(), filter }),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&ChannelFilter> {
+        self.channels
+            .iter()
+            .find(|channel| channel.name.eq_ignore_ascii_case(name.trim()))
+            .map(|channel| &channel.filter)
+    }
+
+    /// Looks up several names at once, failing on the first unknown name
+    pub fn resolve<S: AsRef<str>>(&self, names: &[S]) -> Result<Vec<ChannelFilter>, String> {
+        names
+            .iter()
+            .map(|name| self.get(name.as_ref()).cloned().ok_or_else(|| name.as_ref().to_string()))
+            .collect()
+    }
+
+    pub fn channels(&self) -> &[Channel] {
+        &self.channels
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn packet(dst: [u8; 4], dst_port: u16) -> DecodedPacket<'static> {
+        DecodedPacket {
+            src: IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+            dst: IpAddr::V4(Ipv4Addr::from(dst)),
+            protocol: TransportProtocol::Udp,
+            src_port: 30000,
+            dst_port,
+            tcp: None,
+            payload: b"B606F",
+        }
+    }
+
+    #[test]
+    fn test_channel_filter_matches() {
+        let filter = ChannelFilter::new(Some("233.37.54.1".parse().unwrap()), Some(20001), None, Some(TransportProtocol::Udp));
+        assert!(filter.matches(&packet([233, 37, 54, 1], 20001)));
+        assert!(!filter.matches(&packet([233, 37, 54, 1], 20002)));
+        assert!(!filter.matches(&packet([233, 37, 54, 2], 20001)));
+        assert!(ChannelFilter::default().matches(&packet([233, 37, 54, 2], 20001)));
+        assert!(matches_channel(&None, &packet([1, 2, 3, 4], 1)));
+        assert!(!matches_channel(&Some(vec![filter]), &packet([1, 2, 3, 4], 1)));
+    }
+
+    #[test]
+    fn test_channel_table_from_json() {
+        let json = r#"[
+            {"name": "derivatives A line", "group": "233.37.54.1", "port": 20001, "protocol": "Udp"},
+            {"name": "derivatives B line", "group": "233.37.54.2", "port": 20001, "source": "10.0.0.1"}
+        ]"#;
+        let table = ChannelTable::new(serde_json::from_str(json).unwrap());
+
+        let a_line = table.get("Derivatives A Line").unwrap();
+        assert_eq!(a_line.port, Some(20001));
+        assert_eq!(a_line.source, None);
+
+        let resolved = table.resolve(&["derivatives A line", "derivatives B line"]).unwrap();
+        assert_eq!(resolved.len(), 2);
+        assert!(resolved[1].matches(&packet([233, 37, 54, 2], 20001)));
+        assert_eq!(table.resolve(&["bond line"]), Err("bond line".to_string()));
+    }
+}
@@ -0,0 +1,479 @@
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr};
+use serde::{Deserialize, Serialize};
+use pcap::Linktype;
+use pnet::packet::ethernet::{EtherType, EtherTypes, EthernetPacket};
+use pnet::packet::vlan::VlanPacket;
+use pnet::packet::sll::SLLPacket;
+use pnet::packet::sll2::SLL2Packet;
+use pnet::packet::ipv4::{Ipv4Flags, Ipv4Packet};
+use pnet::packet::ipv6::Ipv6Packet;
+use pnet::packet::ip::{IpNextHeaderProtocol, IpNextHeaderProtocols};
+use pnet::packet::udp::UdpPacket;
+use pnet::packet::tcp::{TcpFlags, TcpPacket};
+
+const LOCAL_LOOPBACK: EtherType = EtherType(32785);
+const LOCAL_LOOPBACK_HEADER_LEN: usize = 18;
+
+const ETHERNET_HEADER_LEN: usize = 14;
+const VLAN_TAG_LEN: usize = 4;
+const SLL_HEADER_LEN: usize = 16;
+const SLL2_HEADER_LEN: usize = 20;
+const NULL_HEADER_LEN: usize = 4;
+const IPV4_MIN_HEADER_LEN: usize = 20;
+const IPV6_HEADER_LEN: usize = 40;
+const UDP_HEADER_LEN: usize = 8;
+const TCP_MIN_HEADER_LEN: usize = 20;
+
+/// 802.1Q, 802.1ad and the legacy QinQ tag are unwrapped up to this depth
+const MAX_VLAN_TAGS: usize = 4;
+
+/// BSD/Linux address family values found in NULL/LOOP headers
+const AF_INET: u32 = 2;
+const AF_INET6: [u32; 4] = [10, 24, 28, 30];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum TransportProtocol {
+    Udp,
+    Tcp,
+    /// the 32785 loopback frame, payload at a fixed offset without IP/port information
+    LocalLoopback,
+}
+
+/// Application payload with the addressing it was delivered with
+/// # Arguments
+/// * `src`, `dst` - unspecified (0.0.0.0) for `LocalLoopback`
+/// * `src_port`, `dst_port` - 0 for `LocalLoopback`
+/// * `tcp` - sequence number and flags, for `TcpReassembler`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodedPacket<'a> {
+    pub src: IpAddr,
+    pub dst: IpAddr,
+    pub protocol: TransportProtocol,
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub tcp: Option<TcpHeader>,
+    pub payload: &'a [u8],
+}
+
+/// The part of the TCP header stream reassembly needs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TcpHeader {
+    pub seq: u32,
+    pub flags: u8,
+}
+
+impl TcpHeader {
+    pub fn is_syn(&self) -> bool {
+        self.flags & TcpFlags::SYN != 0
+    }
+
+    pub fn is_fin(&self) -> bool {
+        self.flags & TcpFlags::FIN != 0
+    }
+
+    pub fn is_rst(&self) -> bool {
+        self.flags & TcpFlags::RST != 0
+    }
+}
+
+/// One fragment of an IPv4/IPv6 datagram, to be put back together by `IpReassembler`
+/// # Arguments
+/// * `id` - IPv4 identification or IPv6 fragment identification
+/// * `offset` - in bytes, relative to the start of the transport header
+/// * `data` - fragment payload (the IP/fragment headers are stripped)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpFragment<'a> {
+    pub src: IpAddr,
+    pub dst: IpAddr,
+    pub protocol: IpNextHeaderProtocol,
+    pub id: u32,
+    pub offset: usize,
+    pub more_fragments: bool,
+    pub data: &'a [u8],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodedFrame<'a> {
+    Packet(DecodedPacket<'a>),
+    Fragment(IpFragment<'a>),
+}
+
+/// Why a frame did not yield a payload
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum SkipReason {
+    /// shorter than the headers it announces (or cut by snaplen)
+    Truncated,
+    /// header fields are inconsistent (e.g., IHL < 5, too many VLAN tags)
+    Malformed,
+    UnsupportedLinktype,
+    /// ARP, LLDP, ...
+    UnsupportedEtherType,
+    /// ICMP, IGMP, ...
+    UnsupportedIpProtocol,
+    /// fragment of an IP datagram that was not (or could not be) reassembled
+    Fragmented,
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SkipReason::Truncated => write!(f, "Truncated frame"),
+            SkipReason::Malformed => write!(f, "Malformed header"),
+            SkipReason::UnsupportedLinktype => write!(f, "Unsupported linktype"),
+            SkipReason::UnsupportedEtherType => write!(f, "Unsupported ethertype"),
+            SkipReason::UnsupportedIpProtocol => write!(f, "Unsupported IP protocol"),
+            SkipReason::Fragmented => write!(f, "IP fragment"),
+        }
+    }
+}
+
+/// Number of frames skipped while decoding, per reason
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SkipCounts {
+    pub truncated: u64,
+    pub malformed: u64,
+    pub unsupported_linktype: u64,
+    pub unsupported_ethertype: u64,
+    pub unsupported_ip_protocol: u64,
+    pub fragmented: u64,
+}
+
+impl SkipCounts {
+    pub fn record(&mut self, reason: SkipReason) {
+        match reason {
+            SkipReason::Truncated => self.truncated += 1,
+            SkipReason::Malformed => self.malformed += 1,
+            SkipReason::UnsupportedLinktype => self.unsupported_linktype += 1,
+            SkipReason::UnsupportedEtherType => self.unsupported_ethertype += 1,
+            SkipReason::UnsupportedIpProtocol => self.unsupported_ip_protocol += 1,
+            SkipReason::Fragmented => self.fragmented += 1,
+        }
+    }
+
+    pub fn accumulate(&mut self, other: &SkipCounts) {
+        self.truncated += other.truncated;
+        self.malformed += other.malformed;
+        self.unsupported_linktype += other.unsupported_linktype;
+        self.unsupported_ethertype += other.unsupported_ethertype;
+        self.unsupported_ip_protocol += other.unsupported_ip_protocol;
+        self.fragmented += other.fragmented;
+    }
+
+    pub fn total(&self) -> u64 {
+        self.truncated
+            + self.malformed
+            + self.unsupported_linktype
+            + self.unsupported_ethertype
+            + self.unsupported_ip_protocol
+            + self.fragmented
+    }
+}
+
+/// Number of packets per transport protocol
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProtocolCounts {
+    pub udp: u64,
+    pub tcp: u64,
+    pub local_loopback: u64,
+}
+
+impl ProtocolCounts {
+    pub fn record(&mut self, protocol: TransportProtocol) {
+        match protocol {
+            TransportProtocol::Udp => self.udp += 1,
+            TransportProtocol::Tcp => self.tcp += 1,
+            TransportProtocol::LocalLoopback => self.local_loopback += 1,
+        }
+    }
+
+    pub fn accumulate(&mut self, other: &ProtocolCounts) {
+        self.udp += other.udp;
+        self.tcp += other.tcp;
+        self.local_loopback += other.local_loopback;
+    }
+
+    pub fn total(&self) -> u64 {
+        self.udp + self.tcp + self.local_loopback
+    }
+}
+
+/// Dispatches on the capture's datalink type and unwraps VLAN/QinQ tags, IPv4/IPv6 (with extension
+/// headers) and UDP/TCP down to the application payload. IP fragments are reported as `Fragmented`,
+/// use `decode_frame` with an `IpReassembler` to recover them.
+pub fn decode_packet(linktype: Linktype, data: &[u8]) -> Result<DecodedPacket<'_>, SkipReason> {
+    match decode_frame(linktype, data)? {
+        DecodedFrame::Packet(packet) => Ok(packet),
+        DecodedFrame::Fragment(_) => Err(SkipReason::Fragmented),
+    }
+}
+
+/// Same as `decode_packet`, but hands IP fragments back instead of skipping them.
+/// pnet's `payload()` borrows from the wrapper, so the slices are cut from the original buffer instead.
+pub fn decode_frame(linktype: Linktype, data: &[u8]) -> Result<DecodedFrame<'_>, SkipReason> {
+    match linktype {
+        Linktype::ETHERNET => {
+            let ethernet = EthernetPacket::new(data).ok_or(SkipReason::Truncated)?;
+            decode_ethertype(ethernet.get_ethertype(), &data[ETHERNET_HEADER_LEN..])
+        },
+        Linktype::LINUX_SLL => {
+            let sll = SLLPacket::new(data).ok_or(SkipReason::Truncated)?;
+            decode_ethertype(sll.get_protocol(), &data[SLL_HEADER_LEN..])
+        },
+        Linktype::LINUX_SLL2 => {
+            let sll2 = SLL2Packet::new(data).ok_or(SkipReason::Truncated)?;
+            decode_ethertype(sll2.get_protocol_type(), &data[SLL2_HEADER_LEN..])
+        },
+        Linktype::NULL | Linktype::LOOP => {
+            let header: [u8; 4] = data.get(..NULL_HEADER_LEN).ok_or(SkipReason::Truncated)?.try_into().unwrap();
+            // LOOP is in network byte order, NULL in the capturing host's (small values tell which)
+            let family = match u32::from_le_bytes(header) {
+                _ if linktype == Linktype::LOOP => u32::from_be_bytes(header),
+                le if le <= 0xffff => le,
+                _ => u32::from_be_bytes(header),
+            };
+            let ip = &data[NULL_HEADER_LEN..];
+            if family == AF_INET {
+                decode_ipv4(ip)
+            } else if AF_INET6.contains(&family) {
+                decode_ipv6(ip)
+            } else {
+                Err(SkipReason::UnsupportedEtherType)
+            }
+        },
+        Linktype::RAW | Linktype::IPV4 | Linktype::IPV6 => match data.first().map(|b| b >> 4) {
+            Some(4) => decode_ipv4(data),
+            Some(6) => decode_ipv6(data),
+            Some(_) => Err(SkipReason::Malformed),
+            None => Err(SkipReason::Truncated),
+        },
+        _ => Err(SkipReason::UnsupportedLinktype),
+    }
+}
+
+fn decode_ethertype(mut ethertype: EtherType, mut bytes: &[u8]) -> Result<DecodedFrame<'_>, SkipReason> {
+    let mut tags = 0;
+    while matches!(ethertype, EtherTypes::Vlan | EtherTypes::PBridge | EtherTypes::QinQ) {
+        if tags == MAX_VLAN_TAGS {
+            return Err(SkipReason::Malformed);
+        }
+        let vlan = VlanPacket::new(bytes).ok_or(SkipReason::Truncated)?;
+        ethertype = vlan.get_ethertype();
+        bytes = &bytes[VLAN_TAG_LEN..];
+        tags += 1;
+    }
+
+    match ethertype {
+        EtherTypes::Ipv4 => decode_ipv4(bytes),
+        EtherTypes::Ipv6 => decode_ipv6(bytes),
+        LOCAL_LOOPBACK => {
+            let payload = bytes.get(LOCAL_LOOPBACK_HEADER_LEN..).ok_or(SkipReason::Truncated)?;
+            Ok(DecodedFrame::Packet(DecodedPacket {
+                src: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                dst: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                protocol: TransportProtocol::LocalLoopback,
+                src_port: 0,
+                dst_port: 0,
+                tcp: None,
+                payload,
+            }))
+        },
+        _ => Err(SkipReason::UnsupportedEtherType),
+    }
+}
+
+fn decode_ipv4(bytes: &[u8]) -> Result<DecodedFrame<'_>, SkipReason> {
+    let ipv4 = Ipv4Packet::new(bytes).ok_or(SkipReason::Truncated)?;
+    let header_len = ipv4.get_header_length() as usize * 4;
+    let total_len = ipv4.get_total_length() as usize;
+    if header_len < IPV4_MIN_HEADER_LEN || total_len < header_len {
+        return Err(SkipReason::Malformed);
+    }
+    // ethernet padding is dropped by total length, snaplen truncation is tolerated
+    let transport = bytes.get(header_len..total_len.min(bytes.len())).ok_or(SkipReason::Truncated)?;
+    let src = IpAddr::V4(ipv4.get_source());
+    let dst = IpAddr::V4(ipv4.get_destination());
+
+    let more_fragments = ipv4.get_flags() & Ipv4Flags::MoreFragments != 0;
+    let offset = ipv4.get_fragment_offset() as usize * 8;
+    if more_fragments || offset != 0 {
+        return Ok(DecodedFrame::Fragment(IpFragment {
+            src,
+            dst,
+            protocol: ipv4.get_next_level_protocol(),
+            id: ipv4.get_identification() as u32,
+            offset,
+            more_fragments,
+            data: transport,
+        }));
+    }
+    decode_transport(src, dst, ipv4.get_next_level_protocol(), transport).map(DecodedFrame::Packet)
+}
+
+fn decode_ipv6(bytes: &[u8]) -> Result<DecodedFrame<'_>, SkipReason> {
+    let ipv6 = Ipv6Packet::new(bytes).ok_or(SkipReason::Truncated)?;
+    let end = (IPV6_HEADER_LEN + ipv6.get_payload_length() as usize).min(bytes.len());
+
+    let src = IpAddr::V6(ipv6.get_source());
+    let dst = IpAddr::V6(ipv6.get_destination());
+    let mut next_header = ipv6.get_next_header();
+    let mut offset = IPV6_HEADER_LEN;
+    loop {
+        let ext = bytes.get(offset..offset + 8);
+        match next_header {
+            IpNextHeaderProtocols::Hopopt | IpNextHeaderProtocols::Ipv6Route | IpNextHeaderProtocols::Ipv6Opts => {
+                let ext = ext.ok_or(SkipReason::Truncated)?;
+                next_header = IpNextHeaderProtocol(ext[0]);
+                offset += (ext[1] as usize + 1) * 8;
+            },
+            IpNextHeaderProtocols::Ah => {
+                let ext = ext.ok_or(SkipReason::Truncated)?;
+                next_header = IpNextHeaderProtocol(ext[0]);
+                offset += (ext[1] as usize + 2) * 4;
+            },
+            IpNextHeaderProtocols::Ipv6Frag => {
+                let ext = ext.ok_or(SkipReason::Truncated)?;
+                // fragment offset (upper 13 bits) and the more-fragments flag; an atomic fragment has neither
+                let offset_flags = u16::from_be_bytes([ext[2], ext[3]]);
+                next_header = IpNextHeaderProtocol(ext[0]);
+                offset += 8;
+                if offset_flags & 0xfff9 != 0 {
+                    return Ok(DecodedFrame::Fragment(IpFragment {
+                        src,
+                        dst,
+                        protocol: next_header,
+                        id: u32::from_be_bytes(ext[4..8].try_into().unwrap()),
+                        offset: (offset_flags & 0xfff8) as usize,
+                        more_fragments: offset_flags & 0x0001 != 0,
+                        data: bytes.get(offset..end).ok_or(SkipReason::Truncated)?,
+                    }));
+                }
+            },
+            _ => break,
+        }
+    }
+
+    let transport = bytes.get(offset..end).ok_or(SkipReason::Truncated)?;
+    decode_transport(src, dst, next_header, transport).map(DecodedFrame::Packet)
+}
+
+/// UDP/TCP header => payload, also used on reassembled datagrams
+pub fn decode_transport(src: IpAddr, dst: IpAddr, protocol: IpNextHeaderProtocol, transport: &[u8]) -> Result<DecodedPacket<'_>, SkipReason> {
+    match protocol {
+        IpNextHeaderProtocols::Udp => {
+            let udp = UdpPacket::new(transport).ok_or(SkipReason::Truncated)?;
+            Ok(DecodedPacket {
+                src,
+                dst,
+                protocol: TransportProtocol::Udp,
+                src_port: udp.get_source(),
+                dst_port: udp.get_destination(),
+                tcp: None,
+                payload: &transport[UDP_HEADER_LEN..],
+            })
+        },
+        IpNextHeaderProtocols::Tcp => {
+            let tcp = TcpPacket::new(transport).ok_or(SkipReason::Truncated)?;
+            let header_len = tcp.get_data_offset() as usize * 4;
+            if header_len < TCP_MIN_HEADER_LEN {
+                return Err(SkipReason::Malformed);
+            }
+            Ok(DecodedPacket {
+                src,
+                dst,
+                protocol: TransportProtocol::Tcp,
+                src_port: tcp.get_source(),
+                dst_port: tcp.get_destination(),
+                tcp: Some(TcpHeader {
+                    seq: tcp.get_sequence(),
+                    flags: tcp.get_flags(),
+                }),
+                payload: transport.get(header_len..).ok_or(SkipReason::Truncated)?,
+            })
+        },
+        _ => Err(SkipReason::UnsupportedIpProtocol),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv6Addr;
+
+    fn ipv4_udp(payload: &[u8]) -> Vec<u8> {
+        let mut ip = vec![0x45, 0x00];
+        ip.extend_from_slice(&((20 + 8 + payload.len()) as u16).to_be_bytes());
+        ip.extend_from_slice(&[0, 0, 0, 0, 64, 17, 0, 0]);
+        ip.extend_from_slice(&[10, 0, 0, 1, 233, 37, 54, 1]);
+        ip.extend_from_slice(&20000u16.to_be_bytes());
+        ip.extend_from_slice(&20001u16.to_be_bytes());
+        ip.extend_from_slice(&((8 + payload.len()) as u16).to_be_bytes());
+        ip.extend_from_slice(&[0, 0]);
+        ip.extend_from_slice(payload);
+        ip
+    }
+
+    fn ipv6_udp_with_hop_by_hop(payload: &[u8]) -> Vec<u8> {
+        let mut ip = vec![0x60, 0, 0, 0];
+        ip.extend_from_slice(&((8 + 8 + payload.len()) as u16).to_be_bytes());
+        ip.extend_from_slice(&[0, 64]);
+        ip.extend_from_slice(&Ipv6Addr::LOCALHOST.octets());
+        ip.extend_from_slice(&"ff15::1".parse::<Ipv6Addr>().unwrap().octets());
+        ip.extend_from_slice(&[17, 0, 1, 4, 0, 0, 0, 0]);
+        ip.extend_from_slice(&20000u16.to_be_bytes());
+        ip.extend_from_slice(&20001u16.to_be_bytes());
+        ip.extend_from_slice(&((8 + payload.len()) as u16).to_be_bytes());
+        ip.extend_from_slice(&[0, 0]);
+        ip.extend_from_slice(payload);
+        ip
+    }
+
+    #[test]
+    fn test_qinq_ethernet() {
+        let mut frame = vec![0u8; 12];
+        frame.extend_from_slice(&[0x88, 0xa8, 0x00, 0x64, 0x81, 0x00, 0x00, 0xc8, 0x08, 0x00]);
+        frame.extend(ipv4_udp(b"B606F"));
+        frame.extend_from_slice(&[0; 6]);
+
+        let decoded = decode_packet(Linktype::ETHERNET, &frame).unwrap();
+        assert_eq!(decoded.payload, b"B606F");
+        assert_eq!(decoded.protocol, TransportProtocol::Udp);
+        assert_eq!(decoded.dst, IpAddr::V4(Ipv4Addr::new(233, 37, 54, 1)));
+        assert_eq!(decoded.dst_port, 20001);
+    }
+
+    #[test]
+    fn test_sll_ipv6() {
+        let mut frame = vec![0, 0, 0, 1, 0, 6, 0, 0, 0, 0, 0, 0, 0, 0, 0x86, 0xdd];
+        frame.extend(ipv6_udp_with_hop_by_hop(b"G706F"));
+
+        let decoded = decode_packet(Linktype::LINUX_SLL, &frame).unwrap();
+        assert_eq!(decoded.payload, b"G706F");
+        assert_eq!(decoded.src, IpAddr::V6(Ipv6Addr::LOCALHOST));
+    }
+
+    #[test]
+    fn test_sll2_and_raw() {
+        let mut frame = vec![0x08, 0x00, 0, 0, 0, 0, 0, 2, 0, 1, 0, 6];
+        frame.extend_from_slice(&[0; 8]);
+        frame.extend(ipv4_udp(b"A301K"));
+        assert_eq!(decode_packet(Linktype::LINUX_SLL2, &frame).unwrap().payload, b"A301K");
+        assert_eq!(decode_packet(Linktype::RAW, &ipv4_udp(b"H201F")).unwrap().payload, b"H201F");
+    }
+
+    #[test]
+    fn test_skip_reasons() {
+        let mut arp = vec![0u8; 12];
+        arp.extend_from_slice(&[0x08, 0x06]);
+        arp.extend_from_slice(&[0; 28]);
+
+        let mut counts = SkipCounts::default();
+        for (linktype, frame) in [(Linktype::ETHERNET, arp), (Linktype::ETHERNET, vec![0; 10]), (Linktype(147), vec![0; 64])] {
+            counts.record(decode_packet(linktype, &frame).unwrap_err());
+        }
+        assert_eq!(counts.unsupported_ethertype, 1);
+        assert_eq!(counts.truncated, 1);
+        assert_eq!(counts.unsupported_linktype, 1);
+        assert_eq!(counts.total(), 3);
+    }
+}
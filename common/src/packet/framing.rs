@@ -0,0 +1,415 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::LazyLock;
+use serde::{Deserialize, Serialize};
+use crate::layout::LayoutRegistry;
+use crate::packet::tcp_reassembly::{FlowKey, StreamSegment};
+
+/// Every KRX message ends with this byte (the spec's End Keyword).
+/// Payloads are ASCII/EUC-KR text where 0xFF never appears, so it is a safe delimiter.
+pub const END_KEYWORD: u8 = 0xFF;
+
+const TRCODE_LEN: usize = 5;
+
+/// Message lengths (End Keyword included) of the specs bundled in `crate::layout::KRX_SPECS`
+pub static KRX_MESSAGE_LENGTHS: LazyLock<MessageLengths> = LazyLock::new(|| {
+    let registry = LayoutRegistry::krx().expect("bundled KRX specs are valid");
+    let mut lengths = MessageLengths::empty();
+    for (trcode, layout) in registry.iter() {
+        lengths.insert(trcode, layout.message_length());
+    }
+    lengths
+});
+
+/// Expected message length per trcode. Trcodes without an entry are cut on the End Keyword only.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MessageLengths {
+    lengths: HashMap<String, usize>,
+}
+
+impl MessageLengths {
+    /// No lengths at all, every message is cut on the End Keyword
+    pub fn empty() -> Self {
+        MessageLengths { lengths: HashMap::new() }
+    }
+
+    pub fn insert(&mut self, trcode: &str, len: usize) {
+        self.lengths.insert(trcode.to_string(), len);
+    }
+
+    pub fn get(&self, trcode: &[u8]) -> Option<usize> {
+        let trcode = std::str::from_utf8(trcode).ok()?;
+        self.lengths.get(trcode).copied()
+    }
+}
+
+/// `KRX_MESSAGE_LENGTHS`
+impl Default for MessageLengths {
+    fn default() -> Self {
+        KRX_MESSAGE_LENGTHS.clone()
+    }
+}
+
+/// Why a message did not pass validation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FrameError {
+    /// shorter than a trcode, or cut before its spec length
+    Truncated,
+    /// terminated before or after its spec length
+    LengthMismatch { expected: usize, found: usize },
+    /// no End Keyword where the message should end
+    MissingEndKeyword,
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameError::Truncated => write!(f, "Truncated message"),
+            FrameError::LengthMismatch { expected, found } => write!(f, "Message length {} (expected {})", found, expected),
+            FrameError::MissingEndKeyword => write!(f, "Missing End Keyword"),
+        }
+    }
+}
+
+/// Checks a terminated message against the spec length of its trcode
+pub fn validate_message(message: &[u8], lengths: &MessageLengths) -> Result<(), FrameError> {
+    if message.len() < TRCODE_LEN {
+        return Err(FrameError::Truncated);
+    }
+    if message.last() != Some(&END_KEYWORD) {
+        return Err(FrameError::MissingEndKeyword);
+    }
+    match lengths.get(&message[..TRCODE_LEN]) {
+        Some(expected) if expected != message.len() => Err(FrameError::LengthMismatch { expected, found: message.len() }),
+        _ => Ok(()),
+    }
+}
+
+/// Splits a UDP payload into its messages, see `split_datagram`
+#[derive(Debug, Clone)]
+pub struct DatagramMessages<'a> {
+    payload: &'a [u8],
+    pos: usize,
+    lengths: &'a MessageLengths,
+}
+
+impl<'a> Iterator for DatagramMessages<'a> {
+    type Item = Result<&'a [u8], FrameError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let rest = &self.payload[self.pos..];
+        if rest.is_empty() {
+            return None;
+        }
+        let first = self.pos == 0;
+        let result = match rest.iter().position(|&b| b == END_KEYWORD) {
+            Some(end) => {
+                self.pos += end + 1;
+                validate_message(&rest[..=end], self.lengths).map(|_| &rest[..=end])
+            },
+            None => {
+                self.pos = self.payload.len();
+                match rest.get(..TRCODE_LEN).and_then(|trcode| self.lengths.get(trcode)) {
+                    Some(expected) if rest.len() < expected => Err(FrameError::Truncated),
+                    Some(_) => Err(FrameError::MissingEndKeyword),
+                    // a payload without any End Keyword is taken whole, as older captures were
+                    None if first && rest.len() >= TRCODE_LEN => Ok(rest),
+                    None => Err(FrameError::MissingEndKeyword),
+                }
+            },
+        };
+        Some(result)
+    }
+}
+
+/// Splits a UDP payload carrying several KRX messages. Each message runs up to its End Keyword
+/// and is checked against the spec length of its trcode; a broken message is returned as an error
+/// and splitting goes on after its End Keyword.
+pub fn split_datagram<'a>(payload: &'a [u8], lengths: &'a MessageLengths) -> DatagramMessages<'a> {
+    DatagramMessages { payload, pos: 0, lengths }
+}
+
+/// # Arguments
+/// * `messages` - messages cut out of the stream (or datagram), valid or not
+/// * `invalid` - messages failing `validate_message`
+/// * `gaps` - holes reported by the transport
+/// * `discarded_bytes` - bytes of messages broken by a gap, or left unterminated at the end of the stream
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FramingStats {
+    pub messages: u64,
+    pub invalid: u64,
+    pub gaps: u64,
+    pub discarded_bytes: u64,
+}
+
+impl FramingStats {
+    pub fn accumulate(&mut self, other: &FramingStats) {
+        self.messages += other.messages;
+        self.invalid += other.invalid;
+        self.gaps += other.gaps;
+        self.discarded_bytes += other.discarded_bytes;
+    }
+}
+
+/// One message and where it starts in the stream
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FramedMessage {
+    pub offset: u64,
+    pub bytes: Vec<u8>,
+}
+
+impl FramedMessage {
+    /// stream offset right after the END_KEYWORD
+    pub fn end(&self) -> u64 {
+        self.offset + self.bytes.len() as u64
+    }
+}
+
+/// Cuts a byte stream (e.g., a reassembled TCP session) into KRX messages on `END_KEYWORD`.
+/// After a gap the head of the current message is lost, so everything up to the next
+/// `END_KEYWORD` is discarded and framing resumes from the following byte, unless that chunk
+/// starts with a trcode and has its spec length, i.e., the gap ended on a message boundary.
+#[derive(Debug, Default)]
+pub struct MessageFramer {
+    lengths: MessageLengths,
+    buf: Vec<u8>,
+    // buf[..start] is already returned or discarded
+    start: usize,
+    // buf[start..scanned] holds no END_KEYWORD
+    scanned: usize,
+    // stream offset of buf[start]
+    offset: u64,
+    resync: bool,
+    stats: FramingStats,
+}
+
+impl MessageFramer {
+    pub fn new() -> Self {
+        MessageFramer::default()
+    }
+
+    /// Spec lengths telling a whole message after a gap from the tail of a broken one,
+    /// `KRX_MESSAGE_LENGTHS` by default
+    pub fn with_lengths(mut self, lengths: MessageLengths) -> Self {
+        self.lengths = lengths;
+        self
+    }
+
+    pub fn push(&mut self, data: &[u8]) {
+        if self.start > 0 {
+            self.buf.drain(..self.start);
+            self.scanned -= self.start;
+            self.start = 0;
+        }
+        self.buf.extend_from_slice(data);
+    }
+
+    /// `len` bytes are missing right after what was pushed so far
+    pub fn mark_gap(&mut self, len: u64) {
+        let partial = self.buf.len() - self.start;
+        self.stats.gaps += 1;
+        self.stats.discarded_bytes += partial as u64;
+        self.offset += partial as u64 + len;
+        self.buf.clear();
+        self.start = 0;
+        self.scanned = 0;
+        self.resync = true;
+    }
+
+    pub fn next_message(&mut self) -> Option<FramedMessage> {
+        loop {
+            let found = self.buf[self.scanned..].iter().position(|&b| b == END_KEYWORD);
+            let end = match found {
+                Some(pos) => self.scanned + pos + 1,
+                None => {
+                    self.scanned = self.buf.len();
+                    return None;
+                }
+            };
+            let offset = self.offset;
+            let len = end - self.start;
+            let bytes = &self.buf[self.start..end];
+            self.start = end;
+            self.scanned = end;
+            self.offset += len as u64;
+
+            if self.resync {
+                self.resync = false;
+                let whole = bytes.get(..TRCODE_LEN).and_then(|trcode| self.lengths.get(trcode)) == Some(len);
+                if !whole {
+                    self.stats.discarded_bytes += len as u64;
+                    continue;
+                }
+            }
+            self.stats.messages += 1;
+            return Some(FramedMessage { offset, bytes: bytes.to_vec() });
+        }
+    }
+
+    /// Stream offset of the first byte not yet returned (or discarded), i.e., the start of the partial message
+    pub fn pending_offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// End of stream: an unterminated tail is discarded
+    pub fn finish(&mut self) {
+        self.stats.discarded_bytes += (self.buf.len() - self.start) as u64;
+        self.offset += (self.buf.len() - self.start) as u64;
+        self.buf.clear();
+        self.start = 0;
+        self.scanned = 0;
+    }
+
+    pub fn stats(&self) -> &FramingStats {
+        &self.stats
+    }
+}
+
+/// A `MessageFramer` per TCP flow, fed with `TcpReassembler` output
+#[derive(Debug, Default)]
+pub struct FlowFramers {
+    framers: HashMap<FlowKey, MessageFramer>,
+    // stats of the framers of closed flows, and invalid messages of all flows
+    closed: FramingStats,
+}
+
+impl FlowFramers {
+    pub fn new() -> Self {
+        FlowFramers::default()
+    }
+
+    /// Calls `on_message` with every valid message, its position among the messages completed by
+    /// the same segment, and the segment carrying its last byte
+    pub fn frame<T, F>(&mut self, segments: Vec<StreamSegment<T>>, lengths: &MessageLengths, mut on_message: F)
+    where
+        F: FnMut(FramedMessage, usize, &StreamSegment<T>),
+    {
+        for segment in segments {
+            let framer = self
+                .framers
+                .entry(segment.flow)
+                .or_insert_with(|| MessageFramer::new().with_lengths(lengths.clone()));
+            if segment.gap > 0 {
+                framer.mark_gap(segment.gap);
+            }
+            framer.push(&segment.data);
+            let mut subidx = 0;
+            while let Some(message) = framer.next_message() {
+                match validate_message(&message.bytes, lengths) {
+                    Ok(()) => on_message(message, subidx, &segment),
+                    Err(_) => self.closed.invalid += 1,
+                }
+                subidx += 1;
+            }
+            if segment.fin {
+                if let Some(mut framer) = self.framers.remove(&segment.flow) {
+                    framer.finish();
+                    self.closed.accumulate(framer.stats());
+                }
+            }
+        }
+    }
+
+    /// Start of the partial message of a flow, bytes before it are done with
+    pub fn pending_offset(&self, flow: &FlowKey) -> Option<u64> {
+        self.framers.get(flow).map(|framer| framer.pending_offset())
+    }
+
+    /// End of capture: unterminated tails are discarded
+    pub fn finish(&mut self) {
+        for (_, mut framer) in self.framers.drain() {
+            framer.finish();
+            self.closed.accumulate(framer.stats());
+        }
+    }
+
+    pub fn stats(&self) -> FramingStats {
+        let mut stats = self.closed;
+        for framer in self.framers.values() {
+            stats.accumulate(framer.stats());
+        }
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_messages_across_pushes() {
+        let mut framer = MessageFramer::new();
+        framer.push(b"B606F001\xffA3");
+        assert_eq!(framer.next_message().unwrap().bytes, b"B606F001\xff");
+        assert!(framer.next_message().is_none());
+
+        framer.push(b"01K002\xffB6");
+        let message = framer.next_message().unwrap();
+        assert_eq!(message.bytes, b"A301K002\xff");
+        assert_eq!((message.offset, message.end()), (9, 18));
+        assert_eq!(framer.pending_offset(), 18);
+
+        framer.finish();
+        assert_eq!(framer.stats(), &FramingStats { messages: 2, invalid: 0, gaps: 0, discarded_bytes: 2 });
+    }
+
+    #[test]
+    fn test_default_lengths_from_bundled_specs() {
+        let lengths = MessageLengths::default();
+        assert_eq!(lengths.get(b"B606F"), Some(324));
+        assert_eq!(lengths.get(b"Z999F"), None);
+    }
+
+    #[test]
+    fn test_gap_resyncs_on_next_end_keyword() {
+        let mut framer = MessageFramer::new();
+        framer.push(b"B606F0");
+        framer.mark_gap(10);
+        framer.push(b"1\xffB606F003\xff");
+        let message = framer.next_message().unwrap();
+        assert_eq!(message.bytes, b"B606F003\xff");
+        assert_eq!(message.offset, 6 + 10 + 2);
+        assert!(framer.next_message().is_none());
+        assert_eq!(framer.stats(), &FramingStats { messages: 1, invalid: 0, gaps: 1, discarded_bytes: 8 });
+    }
+
+    #[test]
+    fn test_gap_on_message_boundary() {
+        let mut lengths = MessageLengths::empty();
+        lengths.insert("B606F", 9);
+        let mut framer = MessageFramer::new().with_lengths(lengths);
+        framer.push(b"B606F001\xffB606F0");
+        assert_eq!(framer.next_message().unwrap().bytes, b"B606F001\xff");
+        // the gap takes the rest of the second message, the third one comes whole
+        framer.mark_gap(4);
+        framer.push(b"B606F003\xff06F004\xffB606F005\xff");
+        let message = framer.next_message().unwrap();
+        assert_eq!((message.bytes.as_slice(), message.offset), (&b"B606F003\xff"[..], 9 + 6 + 4));
+        assert_eq!(framer.next_message().unwrap().bytes, b"06F004\xff");
+        assert_eq!(framer.next_message().unwrap().bytes, b"B606F005\xff");
+
+        // a gap inside a message: the tail is not a whole message and is dropped
+        framer.mark_gap(4);
+        framer.push(b"F006\xffB606F007\xff");
+        assert_eq!(framer.next_message().unwrap().bytes, b"B606F007\xff");
+        assert_eq!(framer.stats(), &FramingStats { messages: 5, invalid: 0, gaps: 2, discarded_bytes: 6 + 5 });
+    }
+
+    #[test]
+    fn test_split_datagram() {
+        let mut lengths = MessageLengths::empty();
+        lengths.insert("B606F", 10);
+        let payload = b"B606F0001\xffA301K02\xffB606F03\xffB606F000";
+        let messages: Vec<_> = split_datagram(payload, &lengths).collect();
+        assert_eq!(messages, vec![
+            Ok(&b"B606F0001\xff"[..]),
+            Ok(&b"A301K02\xff"[..]),
+            Err(FrameError::LengthMismatch { expected: 10, found: 8 }),
+            Err(FrameError::Truncated),
+        ]);
+
+        // no End Keyword at all, the payload is one message
+        assert_eq!(split_datagram(b"A301K02", &lengths).collect::<Vec<_>>(), vec![Ok(&b"A301K02"[..])]);
+        assert_eq!(split_datagram(b"B606F00", &lengths).collect::<Vec<_>>(), vec![Err(FrameError::Truncated)]);
+    }
+}
@@ -0,0 +1,247 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use serde::{Deserialize, Serialize};
+use crate::types::index_range::IndexRange;
+use crate::{KrxMsg, TrCode, UnixNano};
+
+/// What a message did to the distidx sequence of its trcode
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SequenceEvent {
+    /// distidx in `range` were skipped (end exclusive)
+    Gap { trcode: String, range: IndexRange },
+    /// a distidx already seen
+    Duplicate { trcode: String, distidx: usize },
+    /// a distidx arriving after a later one, filling part of a gap
+    Reordered { trcode: String, distidx: usize },
+}
+
+/// distidx sequence of one trcode
+/// # Arguments
+/// * `first`, `last` - lowest and highest distidx seen
+/// * `messages` - messages with a distidx, duplicates included
+/// * `gaps_opened` - number of jumps in the sequence, whether or not they were filled later
+/// * `missing` - ranges still missing at the end
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrcodeSequence {
+    pub first: usize,
+    pub last: usize,
+    pub messages: u64,
+    pub duplicates: u64,
+    pub reordered: u64,
+    pub gaps_opened: u64,
+    pub missing: Vec<IndexRange>,
+}
+
+impl TrcodeSequence {
+    fn new(distidx: usize) -> Self {
+        TrcodeSequence {
+            first: distidx,
+            last: distidx,
+            messages: 0,
+            duplicates: 0,
+            reordered: 0,
+            gaps_opened: 0,
+            missing: Vec::new(),
+        }
+    }
+
+    pub fn missing_count(&self) -> usize {
+        self.missing.iter().map(|range| range.len()).sum()
+    }
+
+    fn push(&mut self, trcode: &str, distidx: usize) -> Option<SequenceEvent> {
+        self.messages += 1;
+        if self.messages == 1 {
+            return None;
+        }
+        if distidx > self.last {
+            let expected = self.last + 1;
+            self.last = distidx;
+            if distidx == expected {
+                return None;
+            }
+            let range = IndexRange::new(expected, distidx);
+            self.gaps_opened += 1;
+            self.missing.push(range);
+            return Some(SequenceEvent::Gap { trcode: trcode.to_string(), range });
+        }
+
+        // below the highest seen: either fills a gap or was seen already
+        let position = self.missing.iter().position(|range| range.contains(distidx));
+        let Some(position) = position else {
+            if distidx < self.first {
+                // before the first message seen, e.g., the capture started during a reorder.
+                // What lies between it and the old first is missing until it arrives too.
+                if distidx + 1 < self.first {
+                    self.missing.insert(0, IndexRange::new(distidx + 1, self.first));
+                    self.gaps_opened += 1;
+                }
+                self.first = distidx;
+                self.reordered += 1;
+                return Some(SequenceEvent::Reordered { trcode: trcode.to_string(), distidx });
+            }
+            self.duplicates += 1;
+            return Some(SequenceEvent::Duplicate { trcode: trcode.to_string(), distidx });
+        };
+        let range = self.missing[position];
+        let before = IndexRange::new(range.start, distidx);
+        let after = IndexRange::new(distidx + 1, range.end);
+        let rest: Vec<IndexRange> = [before, after].into_iter().filter(|range| !range.is_empty()).collect();
+        self.missing.splice(position..position + 1, rest);
+        self.reordered += 1;
+        Some(SequenceEvent::Reordered { trcode: trcode.to_string(), distidx })
+    }
+}
+
+/// # Arguments
+/// * `trcodes` - sequence of every trcode that carries a distidx
+/// * `without_distidx` - messages that could not be tracked
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SequenceReport {
+    pub trcodes: BTreeMap<String, TrcodeSequence>,
+    pub without_distidx: u64,
+}
+
+impl SequenceReport {
+    /// false if any distidx is still missing, i.e., the session lost data
+    pub fn is_complete(&self) -> bool {
+        self.trcodes.values().all(|sequence| sequence.missing.is_empty())
+    }
+
+    pub fn missing_count(&self) -> usize {
+        self.trcodes.values().map(|sequence| sequence.missing_count()).sum()
+    }
+}
+
+/// Follows the per-trcode distidx counter of a `KrxMsg` stream and finds gaps, duplicates and
+/// reorderings. A gap is reported when it opens; a later message inside it counts as reordered and
+/// shrinks it, what is left at the end is in `TrcodeSequence::missing`.
+/// Gaps are logged with flashlog (warn, topic "SEQUENCE"), duplicates and reorderings at info.
+/// The first message of a trcode starts its sequence, a capture starting mid-session has no gap.
+#[derive(Debug, Default)]
+pub struct GapTracker {
+    sequences: HashMap<Option<TrCode>, TrcodeSequence>,
+    without_distidx: u64,
+}
+
+impl GapTracker {
+    pub fn new() -> Self {
+        GapTracker::default()
+    }
+
+    pub fn push(&mut self, msg: &KrxMsg) -> Option<SequenceEvent> {
+        let distidx = match msg.distidx {
+            Some(distidx) if distidx >= 0 => distidx as usize,
+            _ => {
+                self.without_distidx += 1;
+                return None;
+            },
+        };
+        let sequence = self.sequences.entry(msg.trcode).or_insert_with(|| TrcodeSequence::new(distidx));
+        let event = sequence.push(msg.trcode().unwrap_or_default(), distidx);
+        if let Some(event) = &event {
+            log_event(event, msg.packet_timestamp);
+        }
+        event
+    }
+
+    pub fn report(&self) -> SequenceReport {
+        SequenceReport {
+            trcodes: self
+                .sequences
+                .iter()
+                .map(|(trcode, sequence)| (trcode.map(|trcode| trcode.to_string()).unwrap_or_default(), sequence.clone()))
+                .collect(),
+            without_distidx: self.without_distidx,
+        }
+    }
+
+    /// Runs a whole stream (e.g., a `KrxMsgIter`) through a new tracker
+    pub fn track<I>(krx_msgs: I) -> io::Result<SequenceReport>
+    where
+        I: IntoIterator<Item = io::Result<KrxMsg>>,
+    {
+        let mut tracker = GapTracker::new();
+        for msg in krx_msgs {
+            tracker.push(&msg?);
+        }
+        Ok(tracker.report())
+    }
+}
+
+fn log_event(event: &SequenceEvent, packet_timestamp: Option<UnixNano>) {
+    match event {
+        SequenceEvent::Gap { trcode, range } => {
+            let (trcode, range) = (trcode.clone(), *range);
+            flashlog::flash_warn!("SEQUENCE"; "distidx gap"; trcode = trcode, range = range, missing = range.len(), packet_timestamp = packet_timestamp);
+        },
+        SequenceEvent::Duplicate { trcode, distidx } => {
+            let (trcode, distidx) = (trcode.clone(), *distidx);
+            flashlog::flash_info!("SEQUENCE"; "duplicate distidx"; trcode = trcode, distidx = distidx, packet_timestamp = packet_timestamp);
+        },
+        SequenceEvent::Reordered { trcode, distidx } => {
+            let (trcode, distidx) = (trcode.clone(), *distidx);
+            flashlog::flash_info!("SEQUENCE"; "reordered distidx"; trcode = trcode, distidx = distidx, packet_timestamp = packet_timestamp);
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::test_frames::b6_message;
+
+    fn msg(distidx: u32) -> io::Result<KrxMsg> {
+        Ok(KrxMsg::new_from_payload(20240927, &b6_message(distidx), Some(distidx as UnixNano), None).unwrap())
+    }
+
+    #[test]
+    fn test_gaps_duplicates_and_reorders() -> anyhow::Result<()> {
+        let mut tracker = GapTracker::new();
+        let events: Vec<Option<SequenceEvent>> = [5, 6, 10, 8, 8, 13, 4]
+            .into_iter()
+            .map(|distidx| tracker.push(&msg(distidx).unwrap()))
+            .collect();
+        let trcode = "B606F".to_string();
+        assert_eq!(events, vec![
+            None,
+            None,
+            Some(SequenceEvent::Gap { trcode: trcode.clone(), range: IndexRange::new(7, 10) }),
+            Some(SequenceEvent::Reordered { trcode: trcode.clone(), distidx: 8 }),
+            Some(SequenceEvent::Duplicate { trcode: trcode.clone(), distidx: 8 }),
+            Some(SequenceEvent::Gap { trcode: trcode.clone(), range: IndexRange::new(11, 13) }),
+            Some(SequenceEvent::Reordered { trcode: trcode.clone(), distidx: 4 }),
+        ]);
+
+        let report = tracker.report();
+        let sequence = &report.trcodes["B606F"];
+        assert_eq!((sequence.first, sequence.last), (4, 13));
+        assert_eq!((sequence.messages, sequence.duplicates, sequence.reordered, sequence.gaps_opened), (7, 1, 2, 2));
+        assert_eq!(sequence.missing, vec![IndexRange::new(7, 8), IndexRange::new(9, 10), IndexRange::new(11, 13)]);
+        assert_eq!(report.missing_count(), 4);
+        assert!(!report.is_complete());
+        Ok(())
+    }
+
+    #[test]
+    fn test_reorder_below_first() -> anyhow::Result<()> {
+        let report = GapTracker::track([5, 2, 3].into_iter().map(msg))?;
+        let sequence = &report.trcodes["B606F"];
+        assert_eq!((sequence.first, sequence.duplicates, sequence.reordered), (2, 0, 2));
+        assert_eq!(sequence.missing, vec![IndexRange::new(4, 5)]);
+        assert!(!report.is_complete());
+
+        let report = GapTracker::track([5, 2, 3, 4].into_iter().map(msg))?;
+        assert!(report.is_complete());
+        assert_eq!(report.trcodes["B606F"].reordered, 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_filled_gap_is_complete() -> anyhow::Result<()> {
+        let report = GapTracker::track([1, 3, 2, 4].into_iter().map(msg))?;
+        assert!(report.is_complete());
+        assert_eq!(report.trcodes["B606F"].gaps_opened, 1);
+        Ok(())
+    }
+}
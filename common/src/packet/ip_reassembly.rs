@@ -0,0 +1,213 @@
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+use serde::{Deserialize, Serialize};
+use crate::packet::decoder::IpFragment;
+use crate::UnixNano;
+
+/// Fragments of one datagram arrive back-to-back on a market data line, 1 second is plenty
+pub const DEFAULT_FRAGMENT_TIMEOUT: UnixNano = 1_000_000_000;
+
+/// largest IP payload a fragment train can describe (13-bit offset * 8 + 16-bit length)
+const MAX_DATAGRAM_LEN: usize = 0x1_ffff;
+
+/// # Arguments
+/// * `fragments` - fragments pushed
+/// * `reassembled` - datagrams completed
+/// * `incomplete` - datagrams dropped with holes, either timed out or still pending at the end of the capture
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReassemblyStats {
+    pub fragments: u64,
+    pub reassembled: u64,
+    pub incomplete: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct DatagramKey {
+    src: IpAddr,
+    dst: IpAddr,
+    protocol: u8,
+    id: u32,
+}
+
+#[derive(Debug)]
+struct PendingDatagram<T> {
+    first_seen: UnixNano,
+    total_len: Option<usize>,
+    // offset => data, the first copy of an offset wins
+    parts: BTreeMap<usize, Vec<u8>>,
+    tags: Vec<T>,
+}
+
+impl<T> PendingDatagram<T> {
+    fn is_complete(&self) -> bool {
+        let total_len = match self.total_len {
+            Some(total_len) => total_len,
+            None => return false,
+        };
+        let mut covered = 0;
+        for (&offset, data) in self.parts.iter() {
+            if offset > covered {
+                return false;
+            }
+            covered = covered.max(offset + data.len());
+        }
+        covered >= total_len
+    }
+
+    fn assemble(&self) -> Vec<u8> {
+        let total_len = self.total_len.unwrap_or(0);
+        let mut datagram = vec![0u8; total_len];
+        // later offsets are copied last, so on overlap the fragment starting later wins
+        for (&offset, data) in self.parts.iter() {
+            let end = (offset + data.len()).min(total_len);
+            if offset < end {
+                datagram[offset..end].copy_from_slice(&data[..end - offset]);
+            }
+        }
+        datagram
+    }
+}
+
+/// Puts IPv4/IPv6 fragments back together, keyed by (src, dst, protocol, id).
+/// `T` is attached to every fragment and handed back with the datagram (or when it is dropped),
+/// e.g., the original frames so that they can be written out together. Use `()` if not needed.
+#[derive(Debug)]
+pub struct IpReassembler<T = ()> {
+    timeout: UnixNano,
+    pending: HashMap<DatagramKey, PendingDatagram<T>>,
+    stats: ReassemblyStats,
+}
+
+impl<T> IpReassembler<T> {
+    pub fn new(timeout: UnixNano) -> Self {
+        IpReassembler {
+            timeout,
+            pending: HashMap::new(),
+            stats: ReassemblyStats::default(),
+        }
+    }
+
+    /// Returns the complete transport bytes (UDP/TCP header included) and the tags of all its
+    /// fragments, in arrival order, once the last hole is filled.
+    pub fn push(&mut self, timestamp: UnixNano, fragment: &IpFragment, tag: T) -> Option<(Vec<u8>, Vec<T>)> {
+        self.stats.fragments += 1;
+        let key = DatagramKey {
+            src: fragment.src,
+            dst: fragment.dst,
+            protocol: fragment.protocol.0,
+            id: fragment.id,
+        };
+        let pending = self.pending.entry(key).or_insert_with(|| PendingDatagram {
+            first_seen: timestamp,
+            total_len: None,
+            parts: BTreeMap::new(),
+            tags: Vec::new(),
+        });
+
+        let end = fragment.offset + fragment.data.len();
+        if end <= MAX_DATAGRAM_LEN {
+            if !fragment.more_fragments {
+                pending.total_len = Some(end);
+            }
+            pending.parts.entry(fragment.offset).or_insert_with(|| fragment.data.to_vec());
+        }
+        pending.tags.push(tag);
+
+        if !pending.is_complete() {
+            return None;
+        }
+        let pending = self.pending.remove(&key)?;
+        self.stats.reassembled += 1;
+        Some((pending.assemble(), pending.tags))
+    }
+
+    /// Drops datagrams whose first fragment is older than the timeout and returns their tags
+    pub fn expire(&mut self, now: UnixNano) -> Vec<T> {
+        if self.pending.is_empty() {
+            return Vec::new();
+        }
+        let timeout = self.timeout;
+        let expired: Vec<DatagramKey> = self.pending
+            .iter()
+            .filter(|(_, pending)| now.saturating_sub(pending.first_seen) > timeout)
+            .map(|(key, _)| *key)
+            .collect();
+        self.drop_pending(&expired)
+    }
+
+    /// End of capture: everything still pending is incomplete
+    pub fn finish(&mut self) -> Vec<T> {
+        let keys: Vec<DatagramKey> = self.pending.keys().copied().collect();
+        self.drop_pending(&keys)
+    }
+
+    pub fn stats(&self) -> &ReassemblyStats {
+        &self.stats
+    }
+
+    pub fn pending_len(&self) -> usize {
+        self.pending.len()
+    }
+
+    fn drop_pending(&mut self, keys: &[DatagramKey]) -> Vec<T> {
+        let mut tags = Vec::new();
+        for key in keys {
+            if let Some(pending) = self.pending.remove(key) {
+                self.stats.incomplete += 1;
+                tags.extend(pending.tags);
+            }
+        }
+        tags
+    }
+}
+
+impl<T> Default for IpReassembler<T> {
+    fn default() -> Self {
+        IpReassembler::new(DEFAULT_FRAGMENT_TIMEOUT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+    use pnet::packet::ip::IpNextHeaderProtocols;
+
+    fn fragment(id: u32, offset: usize, more_fragments: bool, data: &[u8]) -> IpFragment<'_> {
+        IpFragment {
+            src: IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+            dst: IpAddr::V4(Ipv4Addr::new(233, 37, 54, 1)),
+            protocol: IpNextHeaderProtocols::Udp,
+            id,
+            offset,
+            more_fragments,
+            data,
+        }
+    }
+
+    #[test]
+    fn test_out_of_order_reassembly() {
+        let mut reassembler = IpReassembler::default();
+        assert!(reassembler.push(10, &fragment(7, 16, false, b"tail"), 2).is_none());
+        assert!(reassembler.push(11, &fragment(7, 0, true, b"01234567"), 1).is_none());
+        // a fragment of another datagram stays pending
+        assert!(reassembler.push(12, &fragment(8, 0, true, b"other datagram.."), 3).is_none());
+
+        let (datagram, tags) = reassembler.push(13, &fragment(7, 8, true, b"89abcdef"), 4).unwrap();
+        assert_eq!(datagram, b"0123456789abcdeftail");
+        assert_eq!(tags, vec![2, 1, 4]);
+        assert_eq!(reassembler.pending_len(), 1);
+        assert_eq!(reassembler.stats().reassembled, 1);
+    }
+
+    #[test]
+    fn test_timeout_counts_incomplete() {
+        let mut reassembler = IpReassembler::new(100);
+        reassembler.push(0, &fragment(1, 0, true, b"01234567"), ());
+        reassembler.push(50, &fragment(2, 0, true, b"01234567"), ());
+        assert!(reassembler.expire(100).is_empty());
+        assert_eq!(reassembler.expire(101).len(), 1);
+        assert_eq!(reassembler.finish().len(), 1);
+        assert_eq!(reassembler.stats(), &ReassemblyStats { fragments: 2, reassembled: 0, incomplete: 2 });
+    }
+}
@@ -0,0 +1,621 @@
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use crate::filter::{matches_filter, Filter};
+use crate::layout::LayoutRegistry;
+use crate::packet::capture_reader::{CaptureReader, CaptureRecord, RecordSource};
+use crate::packet::channel::{matches_channel, ChannelFilter};
+use crate::packet::decoder::{
+    decode_frame, decode_transport, DecodedFrame, DecodedPacket, SkipCounts, SkipReason, TransportProtocol,
+};
+use crate::packet::framing::{split_datagram, FlowFramers, FramingStats, MessageLengths};
+use crate::packet::ip_reassembly::{IpReassembler, ReassemblyStats};
+use crate::packet::packet_extractor::matches_header;
+use crate::packet::tcp_reassembly::{StreamSegment, TcpReassembler, TcpStats};
+use crate::packet::time_window::TimeWindow;
+use crate::validation::{ValidationError, Validator};
+use crate::{KrxMsg, KrxMsgRef, UnixNano};
+
+/// Streams `KrxMsg` out of a capture file (pcap or pcapng, `packet_timestamp` keeps the file's full precision).
+/// Compressed captures (gzip, zstd, xz, 7z) are decompressed while reading.
+/// `from_source` runs the same pipeline on any `RecordSource`, e.g., a `LiveCapture` on an interface.
+/// IP fragments are reassembled before filtering (the message gets the timestamp of the last fragment).
+/// TCP flows are reassembled and cut into messages on the End Keyword, a message gets the timestamp
+/// of the segment carrying its last byte.
+/// A UDP payload carrying several messages is split (see `split_datagram`), its messages share the
+/// packet timestamp and are numbered by `subidx`. Messages failing validation are counted in `framing_stats`.
+/// Frames that cannot be decoded down to a UDP/TCP payload are skipped and counted in `skip_counts`,
+/// packets failing the channel or header filter and messages failing the filter expression are skipped silently.
+/// With a `Validator`, messages failing it are quarantined instead of yielded, see `with_validator`.
+/// Instcode and distidx offsets and message lengths are built in unless given spec layouts, see `with_layouts`.
+/// # Arguments
+/// * `date` - yyyymmdd, stamped on every message
+/// * `header_filter` - payload prefixes to keep (e.g., B606F), `None` keeps everything
+pub struct KrxMsgIter {
+    reader: Box<dyn RecordSource + Send>,
+    date: i32,
+    time_window: TimeWindow,
+    pipeline: DatagramPipeline,
+    // frames with `message_lengths`, or on the End Keyword only with a validator
+    messages: MessageFilter,
+    tcp: TcpReassembler<UnixNano>,
+    framers: FlowFramers,
+    message_lengths: MessageLengths,
+    // messages split out of UDP payloads
+    datagram_stats: FramingStats,
+    queue: VecDeque<KrxMsg>,
+    finished: bool,
+    skip_counts: SkipCounts,
+    validator: Option<Validator>,
+    quarantine: Option<Box<dyn Write + Send>>,
+    quarantined: u64,
+}
+
+impl KrxMsgIter {
+    pub fn from_file(file_input: &str, date: i32, header_filter: Option<Vec<String>>) -> io::Result<Self> {
+        let reader = CaptureReader::from_file(file_input)?;
+        Ok(KrxMsgIter::from_source(Box::new(reader), date, header_filter))
+    }
+
+    pub fn from_source(reader: Box<dyn RecordSource + Send>, date: i32, header_filter: Option<Vec<String>>) -> Self {
+        KrxMsgIter {
+            reader,
+            date,
+            time_window: TimeWindow::default(),
+            pipeline: DatagramPipeline::new(None),
+            messages: MessageFilter { header_filter, ..MessageFilter::default() },
+            tcp: TcpReassembler::default(),
+            framers: FlowFramers::new(),
+            message_lengths: MessageLengths::default(),
+            datagram_stats: FramingStats::default(),
+            queue: VecDeque::new(),
+            finished: false,
+            skip_counts: SkipCounts::default(),
+            validator: None,
+            quarantine: None,
+            quarantined: 0,
+        }
+    }
+
+    /// Keeps only packets on one of the channels, on top of the header filter
+    pub fn with_channel_filter(mut self, channel_filter: Vec<ChannelFilter>) -> Self {
+        self.pipeline.channel_filter = Some(channel_filter);
+        self
+    }
+
+    /// Skips packets captured outside the window. The capture is still read from the start, see
+    /// `PacketExtractor::write_slice` to cut a large capture first.
+    pub fn with_time_window(mut self, time_window: TimeWindow) -> Self {
+        self.time_window = time_window;
+        self
+    }
+
+    /// Keeps only messages passing the filter expression, see `Filter::matches`.
+    /// It runs on the payload in the record, so a message failing it is never copied.
+    pub fn with_filter(mut self, filter: Filter) -> Self {
+        self.messages.filter = Some(filter);
+        self
+    }
+
+    /// Takes the instcode and distidx offsets (of the messages and of the filter expression) and the
+    /// message lengths of the trcodes in the registry from their specs.
+    /// Message lengths are set as `with_message_lengths(layouts.message_lengths())` would.
+    pub fn with_layouts(mut self, layouts: &LayoutRegistry) -> Self {
+        self.messages.layouts = Some(layouts.clone());
+        self.with_message_lengths(layouts.message_lengths())
+    }
+
+    /// Overrides `DEFAULT_FRAGMENT_TIMEOUT`
+    pub fn with_fragment_timeout(mut self, timeout: UnixNano) -> Self {
+        self.pipeline.reassembler = IpReassembler::new(timeout);
+        self
+    }
+
+    /// Fragments of datagrams that never completed are counted as `fragmented`
+    pub fn skip_counts(&self) -> &SkipCounts {
+        &self.skip_counts
+    }
+
+    pub fn reassembly_stats(&self) -> &ReassemblyStats {
+        self.pipeline.reassembler.stats()
+    }
+
+    /// Overrides `DEFAULT_GAP_TIMEOUT` of the TCP reassembly
+    pub fn with_gap_timeout(mut self, timeout: UnixNano) -> Self {
+        self.tcp = TcpReassembler::new(timeout);
+        self
+    }
+
+    pub fn tcp_stats(&self) -> &TcpStats {
+        self.tcp.stats()
+    }
+
+    /// Overrides `KRX_MESSAGE_LENGTHS` used to validate messages
+    pub fn with_message_lengths(mut self, message_lengths: MessageLengths) -> Self {
+        self.message_lengths = message_lengths;
+        self.update_framing_lengths();
+        self
+    }
+
+    /// Messages passing the filters are checked by the validator, those failing it are not yielded but
+    /// counted in `quarantined` (and written to the quarantine, if any).
+    /// Messages are then cut on the End Keyword only, whatever `with_message_lengths` set and in whichever
+    /// order the two are called, so that a message of the wrong length reaches the validator instead of
+    /// being dropped by the framing. The validator checks the lengths itself.
+    pub fn with_validator(mut self, validator: Validator) -> Self {
+        self.validator = Some(validator);
+        self.update_framing_lengths();
+        self
+    }
+
+    fn update_framing_lengths(&mut self) {
+        self.messages.message_lengths = match self.validator {
+            Some(_) => MessageLengths::empty(),
+            None => self.message_lengths.clone(),
+        };
+    }
+
+    /// Side file for the messages failing the validator, one JSON line per message:
+    /// the message as `KrxMsg::to_extended_json` writes it (so it reads back as a JSON dump)
+    /// with the failed checks under "validation"
+    pub fn with_quarantine(mut self, quarantine: Box<dyn Write + Send>) -> Self {
+        self.quarantine = Some(quarantine);
+        self
+    }
+
+    /// Messages failing the validator
+    pub fn quarantined(&self) -> u64 {
+        self.quarantined
+    }
+
+    /// `None` if the message fails the validator, after it is written to the quarantine
+    fn admit(&mut self, msg: KrxMsg) -> io::Result<Option<KrxMsg>> {
+        let error = match self.validator.as_ref().map(|validator| validator.validate(&msg.payload)) {
+            Some(Err(error)) => error,
+            _ => return Ok(Some(msg)),
+        };
+        self.quarantined += 1;
+        if let Some(ref mut quarantine) = self.quarantine {
+            write_quarantined(quarantine, &msg, &error)?;
+        }
+        Ok(None)
+    }
+
+    /// Messages framed out of UDP payloads and TCP streams
+    pub fn framing_stats(&self) -> FramingStats {
+        let mut stats = self.framers.stats();
+        stats.accumulate(&self.datagram_stats);
+        stats
+    }
+}
+
+impl Iterator for KrxMsgIter {
+    type Item = io::Result<KrxMsg>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(msg) = self.queue.pop_front() {
+                match self.admit(msg) {
+                    Ok(Some(msg)) => return Some(Ok(msg)),
+                    Ok(None) => continue,
+                    Err(e) => return Some(Err(e)),
+                }
+            }
+            if self.finished {
+                return match self.quarantine.as_mut().map(|quarantine| quarantine.flush()) {
+                    Some(Err(e)) => {
+                        self.quarantine = None;
+                        Some(Err(e))
+                    },
+                    _ => None,
+                };
+            }
+            let record = match self.reader.next_record() {
+                Ok(Some(record)) => record,
+                Ok(None) => {
+                    self.finished = true;
+                    self.skip_counts.fragmented += self.pipeline.finish();
+                    let segments = self.tcp.finish();
+                    frame_messages(&mut self.framers, segments, self.date, &self.messages, &mut self.queue);
+                    self.framers.finish();
+                    continue;
+                },
+                Err(e) => return Some(Err(e)),
+            };
+            let timestamp = record.timestamp;
+            if !self.time_window.contains(timestamp) {
+                continue;
+            }
+            self.skip_counts.fragmented += self.pipeline.expire(timestamp);
+            let segments = self.tcp.expire(timestamp);
+            frame_messages(&mut self.framers, segments, self.date, &self.messages, &mut self.queue);
+
+            let delivered = self.pipeline.push(&record, || (), |datagram| {
+                if !datagram.on_channel {
+                    return;
+                }
+                if datagram.packet.protocol == TransportProtocol::Tcp {
+                    let segments = self.tcp.push(timestamp, &datagram.packet, timestamp);
+                    frame_messages(&mut self.framers, segments, self.date, &self.messages, &mut self.queue);
+                    return;
+                }
+                for (subidx, message) in self.messages.split(datagram.packet.payload, Some(timestamp)).enumerate() {
+                    self.datagram_stats.messages += 1;
+                    match message {
+                        // only messages kept are copied out of the record
+                        SplitMessage::Kept(message) => {
+                            let msg = self.messages.msg_ref(self.date, message, timestamp).with_subidx(subidx as i32);
+                            self.queue.push_back(msg.to_krx_msg());
+                        },
+                        SplitMessage::Skipped(_) => {},
+                        SplitMessage::Invalid => self.datagram_stats.invalid += 1,
+                    }
+                }
+            });
+            if let Err(reason) = delivered {
+                self.skip_counts.record(reason);
+            }
+        }
+    }
+}
+
+/// Decode and IP reassembly of capture records, with the channel filter: the first half of the
+/// datagram pipeline shared by `KrxMsgIter`, `PacketExtractor`, `PcapSplitter` and `UdpReplayer`.
+/// `T` is kept with every fragment and handed back with its datagram, see `IpReassembler`.
+#[derive(Debug)]
+pub(crate) struct DatagramPipeline<T = ()> {
+    pub reassembler: IpReassembler<T>,
+    pub channel_filter: Option<Vec<ChannelFilter>>,
+}
+
+/// A UDP/TCP packet out of `DatagramPipeline::push`
+/// # Arguments
+/// * `frames` - tags of the fragments of a reassembled datagram, empty for a packet that was not fragmented
+/// * `on_channel` - whether the packet passes the channel filter
+pub(crate) struct Datagram<'a, T> {
+    pub packet: DecodedPacket<'a>,
+    pub frames: Vec<T>,
+    pub on_channel: bool,
+}
+
+impl<T> DatagramPipeline<T> {
+    pub fn new(channel_filter: Option<Vec<ChannelFilter>>) -> Self {
+        DatagramPipeline { reassembler: IpReassembler::default(), channel_filter }
+    }
+
+    /// Hands the UDP/TCP packet of the record to `deliver`. A fragment is held (with `tag()`) until its
+    /// datagram is complete, which is then delivered with the record completing it (`Ok(None)` before).
+    /// Frames that cannot be decoded give the reason.
+    pub fn push<R>(
+        &mut self,
+        record: &CaptureRecord,
+        tag: impl FnOnce() -> T,
+        deliver: impl FnOnce(Datagram<'_, T>) -> R,
+    ) -> Result<Option<R>, SkipReason> {
+        let fragment = match decode_frame(record.linktype, record.data)? {
+            DecodedFrame::Packet(packet) => {
+                let on_channel = matches_channel(&self.channel_filter, &packet);
+                return Ok(Some(deliver(Datagram { packet, frames: Vec::new(), on_channel })));
+            },
+            DecodedFrame::Fragment(fragment) => fragment,
+        };
+        let Some((datagram, frames)) = self.reassembler.push(record.timestamp, &fragment, tag()) else {
+            return Ok(None);
+        };
+        let packet = decode_transport(fragment.src, fragment.dst, fragment.protocol, &datagram)?;
+        let on_channel = matches_channel(&self.channel_filter, &packet);
+        Ok(Some(deliver(Datagram { packet, frames, on_channel })))
+    }
+
+    /// Drops the datagrams missing fragments past the timeout, returns the fragments dropped
+    pub fn expire(&mut self, timestamp: UnixNano) -> u64 {
+        self.reassembler.expire(timestamp).len() as u64
+    }
+
+    /// End of capture: returns the fragments of the datagrams still missing some
+    pub fn finish(&mut self) -> u64 {
+        self.reassembler.finish().len() as u64
+    }
+}
+
+/// One message of a datagram, see `MessageFilter::split`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SplitMessage<'a> {
+    /// passes the header filter and the filter expression
+    Kept(&'a [u8]),
+    Skipped(&'a [u8]),
+    /// fails `validate_message`
+    Invalid,
+}
+
+/// The second half of the datagram pipeline: datagrams are split into messages with `message_lengths`,
+/// which are then checked against the header filter and the filter expression, with the spec layouts
+/// their offsets come from
+#[derive(Debug, Clone, Default)]
+pub(crate) struct MessageFilter {
+    pub header_filter: Option<Vec<String>>,
+    pub filter: Option<Filter>,
+    pub layouts: Option<LayoutRegistry>,
+    pub message_lengths: MessageLengths,
+}
+
+impl MessageFilter {
+    /// The messages of a UDP payload (see `split_datagram`), in order
+    pub fn split<'a>(&'a self, payload: &'a [u8], timestamp: Option<UnixNano>) -> impl Iterator<Item = SplitMessage<'a>> {
+        split_datagram(payload, &self.message_lengths).map(move |message| match message {
+            Ok(message) if self.matches(message, timestamp) => SplitMessage::Kept(message),
+            Ok(message) => SplitMessage::Skipped(message),
+            Err(_) => SplitMessage::Invalid,
+        })
+    }
+
+    /// true if one of the messages of the datagram is kept
+    pub fn matches_datagram(&self, payload: &[u8], timestamp: Option<UnixNano>) -> bool {
+        self.split(payload, timestamp).any(|message| matches!(message, SplitMessage::Kept(_)))
+    }
+
+    /// A single message (not a whole datagram) and its capture time
+    pub fn matches(&self, message: &[u8], timestamp: Option<UnixNano>) -> bool {
+        matches_header(&self.header_filter, message) && matches_filter(&self.filter, message, timestamp, self.layouts.as_ref())
+    }
+
+    /// The message stamped with its capture time, parsed with the layouts
+    pub fn msg_ref<'a>(&'a self, date: i32, message: &'a [u8], timestamp: UnixNano) -> KrxMsgRef<'a> {
+        KrxMsgRef { layouts: self.layouts.as_ref(), ..KrxMsgRef::new(date, message, Some(timestamp), None) }
+    }
+}
+
+fn write_quarantined(quarantine: &mut dyn Write, msg: &KrxMsg, error: &ValidationError) -> io::Result<()> {
+    let mut json = msg.to_extended_json().map_err(io::Error::other)?;
+    if let serde_json::Value::Object(ref mut object) = json {
+        let failures = error.failures.iter().map(|failure| failure.to_string()).collect::<Vec<_>>();
+        object.insert("validation".to_string(), serde_json::json!(failures));
+    }
+    serde_json::to_writer(&mut *quarantine, &json)?;
+    quarantine.write_all(b"\n")
+}
+
+/// TCP stream => `KrxMsg`, stamped with the capture time of the segment completing the message
+fn frame_messages(
+    framers: &mut FlowFramers,
+    segments: Vec<StreamSegment<UnixNano>>,
+    date: i32,
+    messages: &MessageFilter,
+    queue: &mut VecDeque<KrxMsg>,
+) {
+    framers.frame(segments, &messages.message_lengths, |message, subidx, segment| {
+        if messages.matches(&message.bytes, Some(segment.tag)) {
+            let msg = messages.msg_ref(date, &message.bytes, segment.tag).with_subidx(subidx as i32);
+            queue.push_back(msg.to_krx_msg());
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::test_frames::{b6_message, ipv4_fragment, tcp_frame, udp_frame, udp_frame_to, write_pcap};
+
+    #[test]
+    fn test_krx_msg_iter_filters_and_stamps() -> anyhow::Result<()> {
+        let path = std::env::temp_dir().join("krx_msg_iter_test.pcap");
+        write_pcap(&path, &[
+            (1_727_400_000, 123_456_789, udp_frame(&b6_message(1))),
+            (1_727_400_001, 0, udp_frame(b"A301K00000002")),
+            (1_727_400_002, 1, udp_frame(b"B6")),
+        ])?;
+
+        let msgs = KrxMsgIter::from_file(path.to_str().unwrap(), 20240927, Some(vec!["B606F".to_string()]))?
+            .collect::<Result<Vec<_>, _>>()?;
+        std::fs::remove_file(&path)?;
+
+        assert_eq!(msgs.len(), 1);
+        assert_eq!(msgs[0].trcode(), Some("B606F"));
+        assert_eq!(msgs[0].date, 20240927);
+        assert_eq!(msgs[0].distidx, Some(1));
+        assert_eq!(msgs[0].instcode.as_deref(), Some("KR4165N30007"));
+        assert_eq!(msgs[0].packet_timestamp, Some(1_727_400_000_123_456_789));
+        Ok(())
+    }
+
+    #[test]
+    fn test_krx_msg_iter_from_source() -> anyhow::Result<()> {
+        let path = std::env::temp_dir().join("krx_msg_iter_source_test.pcap");
+        write_pcap(&path, &[(1_727_400_000, 0, udp_frame(&b6_message(7)))])?;
+        let reader = CaptureReader::new(std::io::Cursor::new(std::fs::read(&path)?))?;
+        std::fs::remove_file(&path)?;
+
+        let msgs = KrxMsgIter::from_source(Box::new(reader), 20240927, None).collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(msgs.len(), 1);
+        assert_eq!(msgs[0].distidx, Some(7));
+        Ok(())
+    }
+
+    #[test]
+    fn test_krx_msg_iter_filter_expression() -> anyhow::Result<()> {
+        let path = std::env::temp_dir().join("krx_msg_iter_filter_test.pcap");
+        let frames: Vec<_> = (0..10).map(|i| (1_727_400_000, i, udp_frame(&b6_message(i as u32)))).collect();
+        write_pcap(&path, &frames)?;
+
+        let filter = Filter::parse(r#"instcode ^= "KR4165" and (distidx in 2..5 or distidx == 8)"#)?;
+        let msgs = KrxMsgIter::from_file(path.to_str().unwrap(), 20240927, None)?
+            .with_filter(filter)
+            .collect::<Result<Vec<_>, _>>()?;
+        std::fs::remove_file(&path)?;
+
+        assert_eq!(msgs.iter().map(|m| m.distidx.unwrap()).collect::<Vec<_>>(), vec![2, 3, 4, 8]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_krx_msg_iter_channel_filter() -> anyhow::Result<()> {
+        let path = std::env::temp_dir().join("krx_msg_iter_channel_test.pcap");
+        write_pcap(&path, &[
+            (1_727_400_000, 0, udp_frame_to([233, 37, 54, 1], &b6_message(1))),
+            (1_727_400_000, 1, udp_frame_to([233, 37, 54, 2], &b6_message(1))),
+            (1_727_400_000, 2, udp_frame_to([233, 37, 54, 2], b"A301K00000002")),
+        ])?;
+
+        let b_line = ChannelFilter::new(Some("233.37.54.2".parse()?), Some(20001), None, None);
+        let msgs = KrxMsgIter::from_file(path.to_str().unwrap(), 20240927, Some(vec!["B606F".to_string()]))?
+            .with_channel_filter(vec![b_line])
+            .collect::<Result<Vec<_>, _>>()?;
+        std::fs::remove_file(&path)?;
+
+        assert_eq!(msgs.len(), 1);
+        assert_eq!(msgs[0].packet_timestamp, Some(1_727_400_000_000_000_001));
+        Ok(())
+    }
+
+    #[test]
+    fn test_krx_msg_iter_reassembles_fragments() -> anyhow::Result<()> {
+        let path = std::env::temp_dir().join("krx_msg_iter_fragment_test.pcap");
+        // UDP header + payload, split at 24 bytes
+        let datagram = udp_frame(&b6_message(3))[14 + 20..].to_vec();
+        write_pcap(&path, &[
+            (1_727_400_000, 0, ipv4_fragment(9, 24, false, &datagram[24..])),
+            (1_727_400_000, 5, ipv4_fragment(9, 0, true, &datagram[..24])),
+            // never completed
+            (1_727_400_000, 9, ipv4_fragment(10, 0, true, &datagram[..24])),
+        ])?;
+
+        let mut iter = KrxMsgIter::from_file(path.to_str().unwrap(), 20240927, Some(vec!["B606F".to_string()]))?;
+        let msgs = iter.by_ref().collect::<Result<Vec<_>, _>>()?;
+        std::fs::remove_file(&path)?;
+
+        assert_eq!(msgs.len(), 1);
+        assert_eq!(msgs[0].distidx, Some(3));
+        assert_eq!(msgs[0].packet_timestamp, Some(1_727_400_000_000_000_005));
+        assert_eq!(iter.skip_counts().fragmented, 1);
+        assert_eq!(iter.reassembly_stats(), &ReassemblyStats { fragments: 3, reassembled: 1, incomplete: 1 });
+        Ok(())
+    }
+
+    #[test]
+    fn test_krx_msg_iter_tcp_stream() -> anyhow::Result<()> {
+        use pnet::packet::tcp::TcpFlags;
+
+        let path = std::env::temp_dir().join("krx_msg_iter_tcp_test.pcap");
+        let stream = [b6_message(1), b"A301K00000002\xff".to_vec(), b6_message(3)].concat();
+        // the SYN takes sequence number 99, the stream starts at 100
+        write_pcap(&path, &[
+            (1_727_400_000, 0, tcp_frame(99, TcpFlags::SYN, b"")),
+            (1_727_400_000, 1, tcp_frame(100, TcpFlags::ACK, &stream[..10])),
+            // ahead of the hole at 110..430
+            (1_727_400_000, 2, tcp_frame(430, TcpFlags::ACK, &stream[330..])),
+            (1_727_400_000, 3, tcp_frame(110, TcpFlags::ACK, &stream[10..330])),
+            // retransmission
+            (1_727_400_000, 4, tcp_frame(430, TcpFlags::ACK, &stream[330..])),
+        ])?;
+
+        let mut iter = KrxMsgIter::from_file(path.to_str().unwrap(), 20240927, Some(vec!["B606F".to_string()]))?;
+        let msgs = iter.by_ref().collect::<Result<Vec<_>, _>>()?;
+        std::fs::remove_file(&path)?;
+
+        assert_eq!(msgs.iter().map(|msg| msg.distidx).collect::<Vec<_>>(), vec![Some(1), Some(3)]);
+        // the segment filling the hole completes the first message, the buffered one behind it the last
+        assert_eq!(msgs[0].packet_timestamp, Some(1_727_400_000_000_000_003));
+        assert_eq!(msgs[1].packet_timestamp, Some(1_727_400_000_000_000_002));
+        assert_eq!(iter.tcp_stats().retransmitted, 1);
+        assert_eq!(iter.framing_stats().messages, 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_krx_msg_iter_splits_datagrams() -> anyhow::Result<()> {
+        let path = std::env::temp_dir().join("krx_msg_iter_split_test.pcap");
+        let mut broken = b6_message(6);
+        broken.remove(100);
+        let payload = [b6_message(4), b"A301K00000002\xff".to_vec(), broken, b6_message(5)].concat();
+        write_pcap(&path, &[(1_727_400_000, 7, udp_frame(&payload))])?;
+
+        let mut iter = KrxMsgIter::from_file(path.to_str().unwrap(), 20240927, Some(vec!["B606F".to_string()]))?;
+        let msgs = iter.by_ref().collect::<Result<Vec<_>, _>>()?;
+        std::fs::remove_file(&path)?;
+
+        assert_eq!(msgs.iter().map(|msg| (msg.distidx, msg.subidx)).collect::<Vec<_>>(), vec![
+            (Some(4), Some(0)),
+            (Some(5), Some(3)),
+        ]);
+        assert!(msgs.iter().all(|msg| msg.packet_timestamp == Some(1_727_400_000_000_000_007)));
+        assert_eq!(msgs[0].payload.len(), 324);
+        assert_eq!((iter.framing_stats().messages, iter.framing_stats().invalid), (4, 1));
+        Ok(())
+    }
+
+    #[test]
+    fn test_krx_msg_iter_layouts() -> anyhow::Result<()> {
+        use crate::layout::PayloadField;
+
+        let field = |item_name: &str, start: i32, length: i32| PayloadField {
+            korean_name: String::new(),
+            item_name: item_name.to_string(),
+            sub_section: "0".to_string(),
+            data_type: "String".to_string(),
+            length,
+            cumulative_length: start + length,
+            start_point: start,
+        };
+        // Z999F is not a KRX trcode, its offsets and length come from the spec only
+        let layouts = LayoutRegistry::new().with_layout("Z999F", vec![
+            field("Message sequence number", 5, 5),
+            field("ISIN Code", 10, 12),
+            field("End Keyword", 22, 1),
+        ]);
+        let path = std::env::temp_dir().join("krx_msg_iter_layouts_test.pcap");
+        let payload = [b"Z999F00042KR4165N30007\xff".to_vec(), b6_message(42)].concat();
+        write_pcap(&path, &[(1_727_400_000, 0, udp_frame(&payload))])?;
+
+        let msgs = KrxMsgIter::from_file(path.to_str().unwrap(), 20240927, None)?
+            .with_filter(Filter::parse("distidx == 42")?)
+            .with_layouts(&layouts)
+            .collect::<Result<Vec<_>, _>>()?;
+        let without = KrxMsgIter::from_file(path.to_str().unwrap(), 20240927, None)?
+            .with_filter(Filter::parse("distidx == 42")?)
+            .collect::<Result<Vec<_>, _>>()?;
+        std::fs::remove_file(&path)?;
+
+        assert_eq!(msgs.iter().map(|msg| (msg.trcode().unwrap(), msg.distidx)).collect::<Vec<_>>(), vec![
+            ("Z999F", Some(42)),
+            ("B606F", Some(42)),
+        ]);
+        assert_eq!(msgs[0].instcode.as_deref(), Some("KR4165N30007"));
+        assert_eq!(without.iter().map(|msg| msg.trcode().unwrap()).collect::<Vec<_>>(), vec!["B606F"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_krx_msg_iter_quarantine() -> anyhow::Result<()> {
+        use crate::mongodb_collection::krx_msg::json_reader::KrxMsgJsonReader;
+
+        let path = std::env::temp_dir().join("krx_msg_iter_quarantine_test.pcap");
+        let quarantine = std::env::temp_dir().join("krx_msg_iter_quarantine_test.json");
+        let mut broken = b6_message(6);
+        broken.remove(100);
+        let payload = [b6_message(4), b"J907700000002\xff".to_vec(), broken].concat();
+        write_pcap(&path, &[(1_727_400_000, 7, udp_frame(&payload))])?;
+
+        // the lengths set after the validator do not drop the broken message before it is validated
+        let mut reordered = KrxMsgIter::from_file(path.to_str().unwrap(), 20240927, None)?
+            .with_validator(Validator::default())
+            .with_message_lengths(MessageLengths::default());
+        assert_eq!(reordered.by_ref().count(), 1);
+        assert_eq!(reordered.quarantined(), 2);
+
+        let mut iter = KrxMsgIter::from_file(path.to_str().unwrap(), 20240927, None)?
+            .with_validator(Validator::default())
+            .with_quarantine(Box::new(std::fs::File::create(&quarantine)?));
+        let msgs = iter.by_ref().collect::<Result<Vec<_>, _>>()?;
+        std::fs::remove_file(&path)?;
+        assert_eq!(msgs.iter().map(|msg| msg.distidx).collect::<Vec<_>>(), vec![Some(4)]);
+        assert_eq!(iter.quarantined(), 2);
+
+        let text = std::fs::read_to_string(&quarantine)?;
+        assert!(text.lines().next().is_some_and(|line| line.contains(r#""validation":["Unknown trcode"]"#)));
+        assert!(text.contains("Message length 323 (expected 324)"));
+        // reads back as a JSON dump
+        let quarantined = KrxMsgJsonReader::from_file(&quarantine)?.collect::<Result<Vec<_>, _>>()?;
+        std::fs::remove_file(&quarantine)?;
+        assert_eq!(quarantined.iter().map(|msg| msg.payload.len()).collect::<Vec<_>>(), vec![14, 323]);
+        Ok(())
+    }
+}
\ No newline at end of file
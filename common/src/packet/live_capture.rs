@@ -0,0 +1,223 @@
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use pcap::{Active, Capture, Linktype, Precision};
+use serde::{Deserialize, Serialize};
+use crate::packet::capture_reader::{CaptureRecord, RecordSource};
+use crate::UnixNano;
+
+pub const DEFAULT_SNAPLEN: i32 = 65535;
+/// kernel buffer, large enough for the bursts at the open and close auctions
+pub const DEFAULT_BUFFER_SIZE: i32 = 64 * 1024 * 1024;
+/// how often a blocked read wakes up to check the stop handle
+pub const DEFAULT_READ_TIMEOUT_MS: i32 = 100;
+
+/// Settings of a capture on a network interface, applied by `open`.
+/// Defaults: snaplen 65535, 64 MiB buffer, immediate mode, not promiscuous, nanosecond timestamps.
+#[derive(Debug, Clone)]
+pub struct LiveCaptureConfig {
+    device: String,
+    snaplen: i32,
+    buffer_size: i32,
+    immediate_mode: bool,
+    promisc: bool,
+    read_timeout_ms: i32,
+    precision: Precision,
+    bpf_filter: Option<String>,
+}
+
+impl LiveCaptureConfig {
+    /// # Arguments
+    /// * `device` - interface name, e.g., "eth0" or "lo"
+    pub fn new(device: &str) -> Self {
+        LiveCaptureConfig {
+            device: device.to_string(),
+            snaplen: DEFAULT_SNAPLEN,
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            immediate_mode: true,
+            promisc: false,
+            read_timeout_ms: DEFAULT_READ_TIMEOUT_MS,
+            precision: Precision::Nano,
+            bpf_filter: None,
+        }
+    }
+
+    pub fn with_snaplen(mut self, snaplen: i32) -> Self {
+        self.snaplen = snaplen;
+        self
+    }
+
+    /// Kernel buffer size in bytes
+    pub fn with_buffer_size(mut self, buffer_size: i32) -> Self {
+        self.buffer_size = buffer_size;
+        self
+    }
+
+    /// With immediate mode (the default) packets are handed over as they arrive instead of in
+    /// buffer-sized batches, which costs more wakeups but no latency
+    pub fn with_immediate_mode(mut self, immediate_mode: bool) -> Self {
+        self.immediate_mode = immediate_mode;
+        self
+    }
+
+    pub fn with_promisc(mut self, promisc: bool) -> Self {
+        self.promisc = promisc;
+        self
+    }
+
+    pub fn with_read_timeout_ms(mut self, read_timeout_ms: i32) -> Self {
+        self.read_timeout_ms = read_timeout_ms;
+        self
+    }
+
+    /// Timestamp precision asked of libpcap, `Precision::Micro` for platforms without nanosecond support
+    pub fn with_precision(mut self, precision: Precision) -> Self {
+        self.precision = precision;
+        self
+    }
+
+    /// Kernel-side pre-filter in BPF syntax, e.g., "udp and dst net 233.37.54.0/24"
+    pub fn with_bpf_filter(mut self, bpf_filter: &str) -> Self {
+        self.bpf_filter = Some(bpf_filter.to_string());
+        self
+    }
+
+    /// Needs the capture privileges of the platform (e.g., CAP_NET_RAW on Linux)
+    pub fn open(&self) -> io::Result<LiveCapture> {
+        let error = |e: pcap::Error| io::Error::other(format!("{}: {}", self.device, e));
+        let mut capture = Capture::from_device(self.device.as_str())
+            .map_err(error)?
+            .snaplen(self.snaplen)
+            .buffer_size(self.buffer_size)
+            .immediate_mode(self.immediate_mode)
+            .promisc(self.promisc)
+            .timeout(self.read_timeout_ms)
+            .precision(self.precision)
+            .open()
+            .map_err(error)?;
+        if let Some(ref bpf_filter) = self.bpf_filter {
+            capture.filter(bpf_filter, true).map_err(error)?;
+        }
+        Ok(LiveCapture {
+            linktype: capture.get_datalink(),
+            capture,
+            precision: self.precision,
+            stop: Arc::new(AtomicBool::new(false)),
+            buf: Vec::new(),
+        })
+    }
+}
+
+/// # Arguments
+/// * `received` - packets that passed the BPF filter
+/// * `dropped` - packets the kernel dropped because they were not read fast enough
+/// * `if_dropped` - packets dropped by the interface or its driver
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LiveStats {
+    pub received: u32,
+    pub dropped: u32,
+    pub if_dropped: u32,
+}
+
+/// An open interface, read like a capture file through `RecordSource`, e.g.,
+/// `KrxMsgIter::from_source(Box::new(live), date, header_filter)` runs the offline pipeline on it.
+/// Reading blocks until a packet arrives; it ends (`Ok(None)`) once the stop handle is set.
+pub struct LiveCapture {
+    capture: Capture<Active>,
+    linktype: Linktype,
+    precision: Precision,
+    stop: Arc<AtomicBool>,
+    buf: Vec<u8>,
+}
+
+impl LiveCapture {
+    /// Set it (from another thread, a signal handler, ...) to end the capture within the read timeout
+    pub fn stop_handle(&self) -> Arc<AtomicBool> {
+        self.stop.clone()
+    }
+
+    pub fn stats(&mut self) -> io::Result<LiveStats> {
+        let stats = self.capture.stats().map_err(io::Error::other)?;
+        Ok(LiveStats {
+            received: stats.received,
+            dropped: stats.dropped,
+            if_dropped: stats.if_dropped,
+        })
+    }
+}
+
+impl RecordSource for LiveCapture {
+    fn linktype(&self) -> Linktype {
+        self.linktype
+    }
+
+    fn next_record(&mut self) -> io::Result<Option<CaptureRecord<'_>>> {
+        let header = loop {
+            if self.stop.load(Ordering::Relaxed) {
+                return Ok(None);
+            }
+            match self.capture.next_packet() {
+                Ok(packet) => {
+                    self.buf.clear();
+                    self.buf.extend_from_slice(packet.data);
+                    break *packet.header;
+                },
+                Err(pcap::Error::TimeoutExpired) => continue,
+                Err(pcap::Error::NoMorePackets) => return Ok(None),
+                Err(e) => return Err(io::Error::other(e)),
+            }
+        };
+        let frac = header.ts.tv_usec as UnixNano;
+        let timestamp = header.ts.tv_sec as UnixNano * 1_000_000_000 + match self.precision {
+            Precision::Nano => frac,
+            Precision::Micro => frac * 1_000,
+        };
+        Ok(Some(CaptureRecord {
+            timestamp,
+            linktype: self.linktype,
+            interface_id: 0,
+            orig_len: header.len,
+            data: &self.buf,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use crate::packet::krx_msg_iter::KrxMsgIter;
+    use crate::packet::replayer::{Pacing, UdpReplayer};
+    use crate::packet::test_frames::{b6_message, udp_frame, write_pcap};
+
+    /// Replays B606F distidx 0..10 to the loopback and reads them back through `KrxMsgIter`
+    #[test]
+    #[ignore = "needs libpcap and capture privileges on the loopback interface"]
+    fn test_loopback_capture() -> anyhow::Result<()> {
+        let path = std::env::temp_dir().join("live_capture_test.pcap");
+        let frames: Vec<_> = (0..10).map(|i| (1_727_400_000, i as i64, udp_frame(&b6_message(i)))).collect();
+        write_pcap(&path, &frames)?;
+        let port = 43_917;
+
+        let live = LiveCaptureConfig::new("lo")
+            .with_bpf_filter(&format!("udp dst port {}", port))
+            .open()?;
+        let stop = live.stop_handle();
+        let replay = std::thread::spawn(move || {
+            let replayer = UdpReplayer::new(path.to_str().unwrap().to_string(), ([127, 0, 0, 1], port).into(), None);
+            let stats = replayer.with_pacing(Pacing::MaxRate).and_then(|replayer| replayer.replay());
+            // ends the capture if packets were lost
+            std::thread::sleep(Duration::from_secs(2));
+            stop.store(true, Ordering::Relaxed);
+            std::fs::remove_file(&path).ok();
+            stats
+        });
+
+        let msgs = KrxMsgIter::from_source(Box::new(live), 20240927, Some(vec!["B606F".to_string()]))
+            .take(10)
+            .collect::<io::Result<Vec<_>>>()?;
+        assert_eq!(replay.join().unwrap()?.sent, 10);
+        assert_eq!(msgs.iter().map(|m| m.distidx.unwrap()).collect::<Vec<_>>(), (0..10).collect::<Vec<_>>());
+        Ok(())
+    }
+}
@@ -0,0 +1,419 @@
+use std::fs::File;
+use std::io;
+use std::ops::Range;
+use std::path::Path;
+use memmap2::Mmap;
+use pcap::Linktype;
+use rayon::prelude::*;
+use crate::packet::capture_reader::{
+    CaptureFormat, CaptureRecord, MAX_BLOCK_LEN, PCAP_FILE_HEADER_LEN, PCAP_MAGIC_MICRO, PCAP_MAGIC_NANO,
+    PCAP_RECORD_HEADER_LEN,
+};
+use crate::packet::time_window::TimeWindow;
+use crate::UnixNano;
+
+/// consecutive plausible record headers required to accept a chunk boundary
+const BOUNDARY_CHAIN: usize = 8;
+
+/// binary search stops narrowing below this many bytes and walks the records
+const SEEK_WALK_LEN: usize = 64 * 1024;
+
+/// A classic pcap file mapped into memory, so that record-aligned chunks of it can be scanned on
+/// several threads. pcapng is not supported (use `CaptureReader`).
+pub struct MmapCapture {
+    mmap: Mmap,
+    format: CaptureFormat,
+    big_endian: bool,
+    snaplen: u32,
+    linktype: Linktype,
+}
+
+impl MmapCapture {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = File::open(path)?;
+        // Safety: the capture is only read; a file truncated by another process while mapped is
+        // the usual mmap caveat and is not guarded against
+        let mmap = unsafe { Mmap::map(&file)? };
+        let header = mmap
+            .get(..PCAP_FILE_HEADER_LEN)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "capture file is truncated"))?;
+        let magic: [u8; 4] = header[..4].try_into().unwrap();
+        let (format, big_endian) = match (u32::from_le_bytes(magic), u32::from_be_bytes(magic)) {
+            (PCAP_MAGIC_MICRO, _) => (CaptureFormat::PcapMicro, false),
+            (PCAP_MAGIC_NANO, _) => (CaptureFormat::PcapNano, false),
+            (_, PCAP_MAGIC_MICRO) => (CaptureFormat::PcapMicro, true),
+            (_, PCAP_MAGIC_NANO) => (CaptureFormat::PcapNano, true),
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "not a classic pcap file")),
+        };
+
+        let mut capture = MmapCapture {
+            mmap,
+            format,
+            big_endian,
+            snaplen: 0,
+            linktype: Linktype::ETHERNET,
+        };
+        capture.snaplen = capture.u32_at(16);
+        // network field: the upper bits hold FCS information
+        capture.linktype = Linktype((capture.u32_at(20) & 0x0fff_ffff) as i32);
+        Ok(capture)
+    }
+
+    pub fn format(&self) -> CaptureFormat {
+        self.format
+    }
+
+    pub fn linktype(&self) -> Linktype {
+        self.linktype
+    }
+
+    pub fn len(&self) -> usize {
+        self.mmap.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mmap.len() <= PCAP_FILE_HEADER_LEN
+    }
+
+    /// All records of the file
+    pub fn records(&self) -> MmapRecords<'_> {
+        self.records_in(self.body())
+    }
+
+    /// Records starting at `range.start`, up to the first one that starts at or after `range.end`
+    pub fn records_in(&self, range: Range<usize>) -> MmapRecords<'_> {
+        MmapRecords {
+            capture: self,
+            offset: range.start,
+            end: range.end,
+            corrupt: false,
+        }
+    }
+
+    /// Splits the records into about `num_chunks` byte ranges of similar size, each starting on a
+    /// record header. Boundaries are found by looking for a chain of plausible headers, which
+    /// `scan_chunks` double-checks.
+    pub fn chunks(&self, num_chunks: usize) -> Vec<Range<usize>> {
+        self.chunks_in(self.body(), num_chunks)
+    }
+
+    /// `chunks` of a record-aligned range, e.g., from `range_of`
+    pub fn chunks_in(&self, range: Range<usize>, num_chunks: usize) -> Vec<Range<usize>> {
+        let len = range.end - range.start;
+        let num_chunks = num_chunks.max(1);
+        let mut starts = vec![range.start];
+        for i in 1..num_chunks {
+            let target = range.start + len / num_chunks * i;
+            let from = target.max(*starts.last().unwrap() + 1);
+            match (from..range.end).find(|&offset| self.is_boundary(offset)) {
+                Some(start) => starts.push(start),
+                None => break,
+            }
+        }
+        starts.dedup();
+
+        let mut chunks = Vec::with_capacity(starts.len());
+        for (i, &start) in starts.iter().enumerate() {
+            let end = starts.get(i + 1).copied().unwrap_or(range.end);
+            chunks.push(start..end);
+        }
+        chunks
+    }
+
+    /// Byte range of the records (everything after the file header)
+    pub fn body(&self) -> Range<usize> {
+        PCAP_FILE_HEADER_LEN..self.mmap.len()
+    }
+
+    /// The file header, which a slice of the body can be appended to
+    pub fn file_header(&self) -> &[u8] {
+        &self.mmap[..PCAP_FILE_HEADER_LEN]
+    }
+
+    pub fn bytes(&self, range: Range<usize>) -> &[u8] {
+        &self.mmap[range]
+    }
+
+    /// Offset of the first record at or after `timestamp` (the end of the file if there is none),
+    /// found by binary search over record boundaries. The capture must be sorted by timestamp.
+    /// The probes of the search are only plausible record headers (see `is_boundary`), so the records
+    /// between the last two are walked to confirm them: `None` if the walk does not land exactly on
+    /// the upper one, i.e., a probe was a false boundary or a record is corrupt.
+    pub fn seek(&self, timestamp: UnixNano) -> Option<usize> {
+        // records before `low` are earlier than `timestamp`, the answer is not after `high`
+        let mut low = PCAP_FILE_HEADER_LEN;
+        let mut high = self.mmap.len();
+        while high - low > SEEK_WALK_LEN {
+            let middle = low + (high - low) / 2;
+            let probe = match (middle..high).find(|&offset| self.is_boundary(offset)) {
+                Some(probe) => probe,
+                None => break,
+            };
+            match self.header_at(probe) {
+                Some((probe_timestamp, _, _)) if probe_timestamp < timestamp => low = probe,
+                _ => high = probe,
+            }
+        }
+
+        let mut records = self.records_in(low..high);
+        let mut found = None;
+        loop {
+            let offset = records.offset;
+            match records.next() {
+                Some(record) if found.is_none() && record.timestamp >= timestamp => found = Some(offset),
+                Some(_) => {},
+                None => break,
+            }
+        }
+        (records.offset == high && !records.corrupt).then(|| found.unwrap_or(high))
+    }
+
+    /// Byte range of the records in `window`, see `seek`. `None` if a bound could not be confirmed.
+    pub fn range_of(&self, window: &TimeWindow) -> Option<Range<usize>> {
+        let start = match window.start {
+            Some(start) => self.seek(start)?,
+            None => PCAP_FILE_HEADER_LEN,
+        };
+        let end = match window.end {
+            Some(end) => self.seek(end)?,
+            None => self.mmap.len(),
+        };
+        Some(start..end.max(start))
+    }
+
+    /// Runs `scan` over every chunk on the current rayon pool and returns the results in capture order.
+    /// If a chunk walk does not end exactly where the next chunk starts (a false boundary, or a corrupt
+    /// record), the whole file is scanned again as one chunk, so the result never depends on the
+    /// boundary heuristic.
+    /// Like `CaptureReader`, scanning stops at the first corrupt or truncated record.
+    pub fn scan_chunks<'a, T, F>(&'a self, num_chunks: usize, scan: F) -> Vec<T>
+    where
+        T: Send,
+        F: Fn(&mut MmapRecords<'a>) -> T + Sync,
+    {
+        self.scan_chunks_in(self.body(), num_chunks, scan)
+    }
+
+    /// `scan_chunks` over a record-aligned range
+    pub fn scan_chunks_in<'a, T, F>(&'a self, range: Range<usize>, num_chunks: usize, scan: F) -> Vec<T>
+    where
+        T: Send,
+        F: Fn(&mut MmapRecords<'a>) -> T + Sync,
+    {
+        let chunks = self.chunks_in(range.clone(), num_chunks);
+        if chunks.len() > 1 {
+            let results: Vec<(T, bool)> = chunks
+                .into_par_iter()
+                .map(|chunk| {
+                    let end = chunk.end;
+                    let mut records = self.records_in(chunk);
+                    let result = scan(&mut records);
+                    (result, records.offset == end && !records.corrupt)
+                })
+                .collect();
+            if results.iter().all(|(_, aligned)| *aligned) {
+                return results.into_iter().map(|(result, _)| result).collect();
+            }
+        }
+        vec![scan(&mut self.records_in(range))]
+    }
+
+    /// Parses the record header at `offset`, returning (timestamp, caplen, orig_len)
+    fn header_at(&self, offset: usize) -> Option<(UnixNano, usize, u32)> {
+        let header = self.mmap.get(offset..offset + PCAP_RECORD_HEADER_LEN)?;
+        let ts_sec = self.u32_of(&header[0..4]) as UnixNano;
+        let ts_frac = self.u32_of(&header[4..8]) as UnixNano;
+        let caplen = self.u32_of(&header[8..12]) as usize;
+        let orig_len = self.u32_of(&header[12..16]);
+        let timestamp = match self.format {
+            CaptureFormat::PcapNano => ts_sec * 1_000_000_000 + ts_frac,
+            _ => ts_sec * 1_000_000_000 + ts_frac * 1_000,
+        };
+        Some((timestamp, caplen, orig_len))
+    }
+
+    fn is_plausible(&self, offset: usize) -> Option<usize> {
+        let header = self.mmap.get(offset..offset + PCAP_RECORD_HEADER_LEN)?;
+        let ts_frac = self.u32_of(&header[4..8]);
+        let caplen = self.u32_of(&header[8..12]);
+        let orig_len = self.u32_of(&header[12..16]);
+        let frac_bound = match self.format {
+            CaptureFormat::PcapNano => 1_000_000_000,
+            _ => 1_000_000,
+        };
+        let plausible = ts_frac < frac_bound
+            && caplen <= orig_len
+            && caplen as usize <= MAX_BLOCK_LEN
+            && (self.snaplen == 0 || caplen <= self.snaplen);
+        let next = offset + PCAP_RECORD_HEADER_LEN + caplen as usize;
+        (plausible && next <= self.mmap.len()).then_some(next)
+    }
+
+    fn is_boundary(&self, mut offset: usize) -> bool {
+        for _ in 0..BOUNDARY_CHAIN {
+            match self.is_plausible(offset) {
+                Some(next) if next == self.mmap.len() => return true,
+                Some(next) => offset = next,
+                None => return false,
+            }
+        }
+        true
+    }
+
+    fn u32_at(&self, pos: usize) -> u32 {
+        self.u32_of(&self.mmap[pos..pos + 4])
+    }
+
+    fn u32_of(&self, bytes: &[u8]) -> u32 {
+        let raw: [u8; 4] = bytes.try_into().unwrap();
+        if self.big_endian { u32::from_be_bytes(raw) } else { u32::from_le_bytes(raw) }
+    }
+}
+
+/// Records of one chunk, borrowed from the mapping
+pub struct MmapRecords<'a> {
+    capture: &'a MmapCapture,
+    offset: usize,
+    end: usize,
+    corrupt: bool,
+}
+
+impl MmapRecords<'_> {
+    /// true if iteration stopped on a corrupt or truncated record
+    pub fn is_corrupt(&self) -> bool {
+        self.corrupt
+    }
+
+    /// Where the next record starts
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+}
+
+impl<'a> Iterator for MmapRecords<'a> {
+    type Item = CaptureRecord<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.corrupt || self.offset >= self.end {
+            return None;
+        }
+        let capture = self.capture;
+        let record = capture.header_at(self.offset).and_then(|(timestamp, caplen, orig_len)| {
+            let start = self.offset + PCAP_RECORD_HEADER_LEN;
+            if caplen > MAX_BLOCK_LEN {
+                return None;
+            }
+            let data = capture.mmap.get(start..start + caplen)?;
+            Some((start + caplen, CaptureRecord {
+                timestamp,
+                linktype: capture.linktype,
+                interface_id: 0,
+                orig_len,
+                data,
+            }))
+        });
+        match record {
+            Some((next, record)) => {
+                self.offset = next;
+                Some(record)
+            },
+            None => {
+                self.corrupt = true;
+                None
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pcap::{Capture, PacketHeader, Precision};
+
+    fn write_pcap(path: &Path, count: usize) -> anyhow::Result<()> {
+        let cap = Capture::dead_with_precision(Linktype::ETHERNET, Precision::Nano)?;
+        let mut savefile = cap.savefile(path)?;
+        for i in 0..count {
+            // lengths vary so that boundaries do not fall on a fixed stride
+            let frame = vec![(i % 251) as u8; 60 + (i * 37) % 300];
+            let header = PacketHeader {
+                ts: libc::timeval { tv_sec: 1_727_400_000 + i as i64, tv_usec: i as i64 },
+                caplen: frame.len() as u32,
+                len: frame.len() as u32,
+            };
+            savefile.write(&pcap::Packet::new(&header, &frame));
+        }
+        savefile.flush()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_chunks_are_record_aligned() -> anyhow::Result<()> {
+        let path = std::env::temp_dir().join("mmap_capture_test.pcap");
+        write_pcap(&path, 1000)?;
+        let capture = MmapCapture::open(&path)?;
+
+        let sequential: Vec<UnixNano> = capture.records().map(|record| record.timestamp).collect();
+        assert_eq!(sequential.len(), 1000);
+        assert_eq!(capture.chunks(8).len(), 8);
+
+        let chunked: Vec<UnixNano> = capture
+            .scan_chunks(8, |records| records.map(|record| record.timestamp).collect::<Vec<_>>())
+            .into_iter()
+            .flatten()
+            .collect();
+        std::fs::remove_file(&path)?;
+        assert_eq!(chunked, sequential);
+        Ok(())
+    }
+
+    #[test]
+    fn test_seek_by_timestamp() -> anyhow::Result<()> {
+        // one record per second, long enough for the binary search to narrow before walking
+        let path = std::env::temp_dir().join("mmap_capture_seek_test.pcap");
+        write_pcap(&path, 2000)?;
+        let capture = MmapCapture::open(&path)?;
+        let second = |i: u64| (1_727_400_000 + i) * 1_000_000_000;
+
+        for target in [0, 1, 999, 1500, 1999] {
+            let offset = capture.seek(second(target) + target + 1).unwrap();
+            let first = capture.records_in(offset..capture.len()).next().map(|record| record.timestamp);
+            assert_eq!(first, (target < 1999).then(|| second(target + 1) + target + 1));
+        }
+        assert_eq!(capture.seek(0), Some(24));
+        assert_eq!(capture.seek(u64::MAX), Some(capture.len()));
+
+        let window = TimeWindow::new(Some(second(100)), Some(second(200)));
+        let range = capture.range_of(&window).unwrap();
+        let in_window: Vec<UnixNano> = capture.records_in(range).map(|record| record.timestamp).collect();
+        std::fs::remove_file(&path)?;
+        assert_eq!(in_window.len(), 100);
+        assert_eq!(in_window[0], second(100) + 100);
+        Ok(())
+    }
+
+    #[test]
+    fn test_seek_walk_must_land_on_probe() -> anyhow::Result<()> {
+        let path = std::env::temp_dir().join("mmap_capture_unconfirmed_seek_test.pcap");
+        write_pcap(&path, 2000)?;
+        let second = |i: u64| (1_727_400_000 + i) * 1_000_000_000;
+        // record 1000 claims 1 byte (still a plausible header), so a walk over it goes off the record chain
+        let offset = {
+            let capture = MmapCapture::open(&path)?;
+            let mut records = capture.records();
+            records.by_ref().take(1000).for_each(drop);
+            records.offset()
+        };
+        let mut bytes = std::fs::read(&path)?;
+        bytes[offset + 8..offset + 12].copy_from_slice(&1u32.to_le_bytes());
+        std::fs::write(&path, &bytes)?;
+        let capture = MmapCapture::open(&path)?;
+        std::fs::remove_file(&path)?;
+
+        assert_eq!(capture.seek(second(1000) + 1001), None);
+        assert!(capture.range_of(&TimeWindow::new(Some(second(1000)), None)).is_none());
+        // far from it, the probes are confirmed
+        assert!(capture.seek(second(10) + 10).is_some());
+        Ok(())
+    }
+}
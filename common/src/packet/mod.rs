@@ -1 +1,18 @@
-pub mod packet_extractor;
\ No newline at end of file
+pub mod packet_extractor;
+pub mod krx_msg_iter;
+pub mod capture_reader;
+pub mod decoder;
+pub mod channel;
+pub mod ip_reassembly;
+pub mod framing;
+pub mod tcp_reassembly;
+pub mod mmap_capture;
+pub mod arbitration;
+pub mod gap_tracker;
+pub mod replayer;
+pub mod time_window;
+pub mod splitter;
+pub mod live_capture;
+
+#[cfg(test)]
+pub(crate) mod test_frames;
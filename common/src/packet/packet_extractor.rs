@@ -1,19 +1,107 @@
-use pnet::packet::ethernet::EtherType;
-use pnet::packet::ethernet::EthernetPacket;
-use pnet::packet::ipv4::Ipv4Packet;
-use pnet::packet::udp::UdpPacket;
-use pnet::packet::tcp::TcpPacket;
-use pnet::packet::ip::IpNextHeaderProtocols;
-use pnet::packet::Packet;
-use pcap::Capture;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use pcap::{Capture, Linktype, PacketHeader, Precision, Savefile};
+use serde::{Deserialize, Serialize};
+use crate::filter::{matches_filter, Filter};
+use crate::packet::capture_reader::{CaptureReader, CaptureRecord, OwnedFrame};
+use crate::packet::channel::{matches_channel, ChannelFilter};
+use crate::packet::decoder::{
+    decode_frame, DecodedFrame, ProtocolCounts, SkipCounts, SkipReason, TransportProtocol,
+};
+use crate::packet::framing::{FlowFramers, MessageLengths};
+use crate::layout::LayoutRegistry;
+use crate::packet::krx_msg_iter::{DatagramPipeline, KrxMsgIter, MessageFilter, SplitMessage};
+use crate::packet::mmap_capture::MmapCapture;
+use crate::packet::tcp_reassembly::{FlowKey, StreamSegment, TcpReassembler};
+use crate::packet::time_window::TimeWindow;
+use crate::validation::Validator;
+use crate::{Error, UnixNano};
 
-const LOCAL_LOOPBACK: EtherType = EtherType(32785);
+const CHUNKS_PER_THREAD: usize = 4;
+
+enum Verdict {
+    Write,
+    Stateful,
+    Drop,
+}
+
+/// What an extraction did, for batch jobs to check a capture
+/// # Arguments
+/// * `read` - records read (inside the time window, if any)
+/// * `matched` - UDP datagrams and TCP messages passing the filters
+/// * `written` - records written, all fragments of a datagram and all segments of a message count
+/// * `malformed` - truncated frames, inconsistent headers and UDP messages failing validation
+/// * `skip_counts` - frames that could not be decoded, per reason
+/// * `skipped_per_protocol` - UDP datagrams and TCP messages filtered out (off-channel TCP segments count one each)
+/// * `skipped_per_trcode` - messages failing the header filter or the filter expression, by their first 5 bytes
+/// * `first_timestamp`, `last_timestamp` - earliest and latest record read
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExtractionSummary {
+    pub read: u64,
+    pub matched: u64,
+    pub written: u64,
+    pub malformed: u64,
+    pub skip_counts: SkipCounts,
+    pub skipped_per_protocol: ProtocolCounts,
+    pub skipped_per_trcode: BTreeMap<String, u64>,
+    pub first_timestamp: Option<UnixNano>,
+    pub last_timestamp: Option<UnixNano>,
+}
+
+impl ExtractionSummary {
+    fn record_read(&mut self, timestamp: UnixNano) {
+        self.read += 1;
+        self.first_timestamp = Some(self.first_timestamp.map_or(timestamp, |first| first.min(timestamp)));
+        self.last_timestamp = Some(self.last_timestamp.map_or(timestamp, |last| last.max(timestamp)));
+    }
+
+    fn record_skip(&mut self, reason: SkipReason) {
+        if matches!(reason, SkipReason::Truncated | SkipReason::Malformed) {
+            self.malformed += 1;
+        }
+        self.skip_counts.record(reason);
+    }
+
+    fn record_skipped_message(&mut self, protocol: Option<TransportProtocol>, message: &[u8]) {
+        if let Some(protocol) = protocol {
+            self.skipped_per_protocol.record(protocol);
+        }
+        let trcode = String::from_utf8_lossy(&message[..message.len().min(5)]).into_owned();
+        *self.skipped_per_trcode.entry(trcode).or_default() += 1;
+    }
+
+    pub fn accumulate(&mut self, other: &ExtractionSummary) {
+        self.read += other.read;
+        self.matched += other.matched;
+        self.written += other.written;
+        self.malformed += other.malformed;
+        self.skip_counts.accumulate(&other.skip_counts);
+        self.skipped_per_protocol.accumulate(&other.skipped_per_protocol);
+        for (trcode, count) in other.skipped_per_trcode.iter() {
+            *self.skipped_per_trcode.entry(trcode.clone()).or_default() += count;
+        }
+        self.first_timestamp = match (self.first_timestamp, other.first_timestamp) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        self.last_timestamp = match (self.last_timestamp, other.last_timestamp) {
+            (Some(a), Some(b)) => Some(a.max(b)),
+            (a, b) => a.or(b),
+        };
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct PacketExtractor {
     file_input: String,
     file_output: String,
-    header_filter: Option<Vec<String>>,
+    channel_filter: Option<Vec<ChannelFilter>>,
+    time_window: Option<TimeWindow>,
+    sorted_input: bool,
+    messages: MessageFilter,
+    validator: Option<Validator>,
+    quarantine: Option<String>,
 }
 
 impl PacketExtractor {
@@ -21,88 +109,703 @@ impl PacketExtractor {
         PacketExtractor {
             file_input,
             file_output,
-            header_filter,
+            channel_filter: None,
+            time_window: None,
+            sorted_input: false,
+            messages: MessageFilter { header_filter, ..MessageFilter::default() },
+            validator: None,
+            quarantine: None,
         }
     }
 
-    pub fn filter_packets_with_header(&self) {
-        // Open PCAP file
-        let mut cap = match Capture::from_file(&self.file_input) {
-            Ok(c) => c,
-            Err(e) => {
-                eprintln!("Cannot open input file: {}", e);
-                return;
-            }
-        };
-    
-        let output_cap = Capture::dead(cap.get_datalink()).unwrap();
-        let mut savefile = output_cap.savefile(self.file_output.as_str()).unwrap();
-    
+    /// Keeps only packets on one of the channels, on top of the header filter
+    pub fn with_channel_filter(mut self, channel_filter: Vec<ChannelFilter>) -> PacketExtractor {
+        self.channel_filter = Some(channel_filter);
+        self
+    }
+
+    /// Overrides `KRX_MESSAGE_LENGTHS` used to split and validate messages
+    pub fn with_message_lengths(mut self, message_lengths: MessageLengths) -> PacketExtractor {
+        self.messages.message_lengths = message_lengths;
+        self
+    }
+
+    /// Keeps only packets captured inside the window, e.g., `TimeWindow::kst(date, "08:45", "15:45")`.
+    /// The whole input is read unless it is declared sorted, see `with_sorted_input`.
+    pub fn with_time_window(mut self, time_window: TimeWindow) -> PacketExtractor {
+        self.time_window = Some(time_window);
+        self
+    }
+
+    /// The input is sorted by timestamp, so that the time window of classic pcap input is sought by binary
+    /// search instead of reading the whole file. Not checked: packets of an unsorted capture (e.g., A and B
+    /// lines merged) outside the part found by the search are lost.
+    pub fn with_sorted_input(mut self) -> PacketExtractor {
+        self.sorted_input = true;
+        self
+    }
+
+    /// A message must also pass the filter expression, e.g., `instcode ^= "KR4165" and distidx in 100..200`.
+    /// `time` predicates see the capture time of the packet (of the segment completing the message for TCP).
+    pub fn with_filter(mut self, filter: Filter) -> PacketExtractor {
+        self.messages.filter = Some(filter);
+        self
+    }
+
+    /// Takes the instcode and distidx offsets of the filter expression and the message lengths of the
+    /// trcodes in the registry from their specs, also for `krx_msgs` (see `KrxMsgIter::with_layouts`).
+    /// Message lengths are set as `with_message_lengths(layouts.message_lengths())` would.
+    pub fn with_layouts(mut self, layouts: &LayoutRegistry) -> PacketExtractor {
+        self.messages.message_lengths = layouts.message_lengths();
+        self.messages.layouts = Some(layouts.clone());
+        self
+    }
+
+    /// Messages of `krx_msgs` failing the validator are left out of the iteration, see `KrxMsgIter::with_validator`.
+    /// Only `krx_msgs` validates, the filter and slice methods write packets without checking their messages.
+    pub fn with_validator(mut self, validator: Validator) -> PacketExtractor {
+        self.validator = Some(validator);
+        self
+    }
+
+    /// File the messages failing the validator are written to by `krx_msgs`, as JSON lines
+    /// (see `KrxMsgIter::with_quarantine`). Without `with_validator`, the default `Validator` is used.
+    pub fn with_quarantine(mut self, quarantine: String) -> PacketExtractor {
+        self.quarantine = Some(quarantine);
+        self
+    }
+
+    /// Reads pcap or pcapng input and writes the matching packets as a nanosecond pcap.
+    /// IP fragments are reassembled to apply the filters, and all fragments of a matching datagram are written.
+    /// A UDP packet is written if one of its (valid) messages matches the header filter and the filter expression.
+    /// TCP flows are reassembled and cut into messages, the segments carrying a matching message are
+    /// written once the message is complete (so they can come after later UDP packets in the output).
+    /// The output keeps the linktype of the first interface, frames of other linktypes are skipped.
+    /// With a time window on sorted input (`with_sorted_input`), classic pcap input goes through
+    /// `filter_packets_parallel` on one thread, which seeks to the window instead of reading the whole file.
+    /// Returns what was read, matched and written, see `ExtractionSummary`.
+    /// A truncated or corrupt record ends the scan with `Error::ReadInput`, after the output is flushed.
+    pub fn filter_packets_with_header(&self) -> Result<ExtractionSummary, Error> {
+        if self.sorted_input && self.time_window.is_some() && MmapCapture::open(&self.file_input).is_ok() {
+            return self.filter_packets_parallel(1);
+        }
+        let mut reader = CaptureReader::from_file(&self.file_input).map_err(|e| self.open_error(e))?;
+        let mut output = FilterOutput::create(self, reader.linktype())?;
+
         // Process each packet
-        while let Ok(packet) = cap.next_packet() {
-            if self.is_valid_packet(packet.data) {
-                savefile.write(&packet);
+        let time_window = self.time_window.unwrap_or_default();
+        let read_error = loop {
+            let record = match reader.next_record() {
+                Ok(Some(record)) => record,
+                Ok(None) => break None,
+                Err(e) => break Some(e),
+            };
+            if !time_window.contains(record.timestamp) {
+                continue;
             }
+            output.expire(record.timestamp);
+            match self.classify(output.linktype, &record, &mut output.summary) {
+                Verdict::Write => output.write(&record),
+                Verdict::Stateful => output.process(self, &record),
+                Verdict::Drop => {},
+            }
+        };
+        let summary = output.finish(self)?;
+        match read_error {
+            Some(e) => Err(self.read_error(e)),
+            None => Ok(summary),
         }
     }
 
-    fn is_valid_packet(&self, packet: &[u8]) -> bool {
-        if let Some(ethernet_packet) = EthernetPacket::new(packet) {
-            if ethernet_packet.get_ethertype() == pnet::packet::ethernet::EtherTypes::Ipv4 {
-                if let Some(ipv4_packet) = Ipv4Packet::new(ethernet_packet.payload()) {
-                    match ipv4_packet.get_next_level_protocol() {
-                        IpNextHeaderProtocols::Udp => {
-                            if let Some(udp_packet) = UdpPacket::new(ipv4_packet.payload()) {
-                                let payload = udp_packet.payload();
-                                match self.header_filter {
-                                    Some(ref filter) => {
-                                        for header in filter.iter() {
-                                            if payload.starts_with(header.as_bytes()) {
-                                                return true;
-                                            }
-                                        }
-                                        return false;
-                                    }
-                                    None => return true,
-                                }
-                            }
-                        },
-                        IpNextHeaderProtocols::Tcp => {
-                            if let Some(tcp_packet) = TcpPacket::new(ipv4_packet.payload()) {
-                                let payload = tcp_packet.payload();
-                                match self.header_filter {
-                                    Some(ref filter) => {
-                                        for header in filter.iter() {
-                                            if payload.starts_with(header.as_bytes()) {
-                                                return true;
-                                            }
-                                        }
-                                        return false;
-                                    }
-                                    None => return true,
-                                }
-                            }
-                        }
-                        _ => {
-                            return false;
-                        }
+    /// Same output as `filter_packets_with_header`, for classic pcap input too large to scan on one thread.
+    /// The file is memory-mapped and split into record-aligned chunks that are decoded and filtered on
+    /// `num_threads` threads; the kept records are then written in capture order. IP fragments and TCP
+    /// segments go through reassembly on the writing thread, whose timeouts are checked at kept records only.
+    /// On sorted input, only the records inside the time window (found by `MmapCapture::range_of`) are scanned.
+    /// pcapng and compressed input fall back to `filter_packets_with_header`.
+    pub fn filter_packets_parallel(&self, num_threads: usize) -> Result<ExtractionSummary, Error> {
+        let capture = match MmapCapture::open(&self.file_input) {
+            Ok(capture) => capture,
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => return self.filter_packets_with_header(),
+            Err(e) => return Err(self.open_error(e)),
+        };
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(num_threads)
+            .build()
+            .map_err(|e| Error::ThreadPool(e.to_string()))?;
+        let mut output = FilterOutput::create(self, capture.linktype())?;
+
+        let linktype = output.linktype;
+        let time_window = self.time_window.unwrap_or_default();
+        let range = match self.time_window {
+            // a window bound the binary search cannot confirm falls back to scanning everything
+            Some(ref time_window) if self.sorted_input => capture.range_of(time_window).unwrap_or_else(|| capture.body()),
+            _ => capture.body(),
+        };
+        // several chunks per thread so that a slow chunk does not hold the others up
+        let chunks = pool.install(|| {
+            capture.scan_chunks_in(range, num_threads * CHUNKS_PER_THREAD, |records| {
+                let mut kept = Vec::new();
+                let mut summary = ExtractionSummary::default();
+                for record in records.by_ref() {
+                    if !time_window.contains(record.timestamp) {
+                        continue;
                     }
+                    match self.classify(linktype, &record, &mut summary) {
+                        Verdict::Write => kept.push((true, record)),
+                        Verdict::Stateful => kept.push((false, record)),
+                        Verdict::Drop => {},
+                    }
+                }
+                (kept, summary, records.is_corrupt())
+            })
+        });
+
+        let mut corrupt = false;
+        for (kept, summary, chunk_corrupt) in chunks {
+            output.summary.accumulate(&summary);
+            corrupt |= chunk_corrupt;
+            for (write, record) in kept {
+                output.expire(record.timestamp);
+                if write {
+                    output.write(&record);
+                } else {
+                    output.process(self, &record);
+                }
+            }
+        }
+        let summary = output.finish(self)?;
+        if corrupt {
+            return Err(self.read_error(io::Error::new(io::ErrorKind::InvalidData, "truncated or corrupt pcap record")));
+        }
+        Ok(summary)
+    }
+
+    /// The stateless part of the filters: whether a record is written as is, dropped,
+    /// or needs reassembly (IP fragments and TCP segments). Counts the record as read.
+    fn classify(&self, output_linktype: Linktype, record: &CaptureRecord, summary: &mut ExtractionSummary) -> Verdict {
+        summary.record_read(record.timestamp);
+        if record.linktype != output_linktype {
+            summary.record_skip(SkipReason::UnsupportedLinktype);
+            return Verdict::Drop;
+        }
+        match decode_frame(record.linktype, record.data) {
+            Ok(DecodedFrame::Packet(decoded)) => {
+                if !matches_channel(&self.channel_filter, &decoded) {
+                    summary.skipped_per_protocol.record(decoded.protocol);
+                    Verdict::Drop
+                } else if decoded.protocol == TransportProtocol::Tcp {
+                    Verdict::Stateful
+                } else if self.matches_datagram(decoded.payload, record.timestamp, summary) {
+                    summary.matched += 1;
+                    Verdict::Write
+                } else {
+                    summary.skipped_per_protocol.record(decoded.protocol);
+                    Verdict::Drop
                 }
-            } else if ethernet_packet.get_ethertype() == LOCAL_LOOPBACK && ethernet_packet.payload().len() >= 18 {
-                let clipped = ethernet_packet.payload()[18..].to_vec();
-                match self.header_filter {
-                    Some(ref filter) => {
-                        for header in filter.iter() {
-                            if clipped.starts_with(header.as_bytes()) {
-                                return true;
-                            }
-                        }
-                        return false;
+            },
+            Ok(DecodedFrame::Fragment(_)) => Verdict::Stateful,
+            Err(reason) => {
+                summary.record_skip(reason);
+                Verdict::Drop
+            },
+        }
+    }
+
+    /// true if one of the messages passes the filters, the others are counted as skipped
+    fn matches_datagram(&self, payload: &[u8], timestamp: UnixNano, summary: &mut ExtractionSummary) -> bool {
+        let mut matched = false;
+        for message in self.messages.split(payload, Some(timestamp)) {
+            match message {
+                SplitMessage::Kept(_) => matched = true,
+                SplitMessage::Skipped(message) => summary.record_skipped_message(None, message),
+                SplitMessage::Invalid => summary.malformed += 1,
+            }
+        }
+        matched
+    }
+
+    fn open_error(&self, e: io::Error) -> Error {
+        Error::OpenInput { path: self.file_input.clone(), reason: e.to_string() }
+    }
+
+    fn read_error(&self, e: io::Error) -> Error {
+        Error::ReadInput { path: self.file_input.clone(), reason: e.to_string() }
+    }
+
+    /// Iterates over the input file and yields a `KrxMsg` for every packet passing the channel and header filters.
+    /// With a validator or a quarantine, messages failing validation are quarantined instead.
+    /// # Arguments
+    /// * `date` - yyyymmdd, stamped on every message
+    pub fn krx_msgs(&self, date: i32) -> std::io::Result<KrxMsgIter> {
+        let mut iter = KrxMsgIter::from_file(&self.file_input, date, self.messages.header_filter.clone())?;
+        if let Some(ref layouts) = self.messages.layouts {
+            iter = iter.with_layouts(layouts);
+        }
+        iter = iter.with_message_lengths(self.messages.message_lengths.clone());
+        if let Some(ref channel_filter) = self.channel_filter {
+            iter = iter.with_channel_filter(channel_filter.clone());
+        }
+        if let Some(time_window) = self.time_window {
+            iter = iter.with_time_window(time_window);
+        }
+        if let Some(ref filter) = self.messages.filter {
+            iter = iter.with_filter(filter.clone());
+        }
+        if self.validator.is_some() || self.quarantine.is_some() {
+            iter = iter.with_validator(self.validator.clone().unwrap_or_default());
+        }
+        if let Some(ref quarantine) = self.quarantine {
+            iter = iter.with_quarantine(Box::new(BufWriter::new(File::create(quarantine)?)));
+        }
+        Ok(iter)
+    }
+
+    /// Writes every record inside the time window to the output, the header and channel filters are not applied.
+    /// Sorted classic pcap input (`with_sorted_input`) is sought by binary search and the slice is
+    /// copied byte for byte, keeping the input's timestamp precision. Other input, and classic pcap whose
+    /// window bounds the search cannot confirm (see `MmapCapture::seek`), is scanned and written as
+    /// nanosecond pcap, keeping the linktype of the first interface.
+    /// Returns the number of records written.
+    pub fn write_slice(&self) -> io::Result<u64> {
+        let time_window = self.time_window.unwrap_or_default();
+        if !self.sorted_input {
+            return self.write_slice_sequential(&time_window);
+        }
+        let capture = match MmapCapture::open(&self.file_input) {
+            Ok(capture) => capture,
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => return self.write_slice_sequential(&time_window),
+            Err(e) => return Err(e),
+        };
+        let Some(range) = capture.range_of(&time_window) else {
+            return self.write_slice_sequential(&time_window);
+        };
+        let mut output = BufWriter::new(File::create(&self.file_output)?);
+        output.write_all(capture.file_header())?;
+        output.write_all(capture.bytes(range.clone()))?;
+        output.flush()?;
+        Ok(capture.records_in(range).count() as u64)
+    }
+
+    fn write_slice_sequential(&self, time_window: &TimeWindow) -> io::Result<u64> {
+        let mut reader = CaptureReader::from_file(&self.file_input)?;
+        let linktype = reader.linktype();
+        let mut savefile = Capture::dead_with_precision(linktype, Precision::Nano)
+            .and_then(|capture| capture.savefile(self.file_output.as_str()))
+            .map_err(io::Error::other)?;
+        let mut written = 0;
+        while let Some(record) = reader.next_record()? {
+            if time_window.contains(record.timestamp) && record.linktype == linktype {
+                write_record(&mut savefile, record.timestamp, record.orig_len, record.data);
+                written += 1;
+            }
+        }
+        savefile.flush().map_err(io::Error::other)?;
+        Ok(written)
+    }
+}
+
+/// Frames of one delivered TCP segment, covering [start, end) of the stream
+struct SegmentFrames {
+    start: u64,
+    end: u64,
+    frames: Vec<OwnedFrame>,
+    written: bool,
+}
+
+/// Holds TCP frames until the messages they carry are complete, then writes those of matching messages
+struct TcpOutput {
+    messages: MessageFilter,
+    framers: FlowFramers,
+    frames: HashMap<FlowKey, VecDeque<SegmentFrames>>,
+}
+
+impl TcpOutput {
+    fn new(messages: MessageFilter) -> Self {
+        TcpOutput {
+            messages,
+            framers: FlowFramers::new(),
+            frames: HashMap::new(),
+        }
+    }
+
+    fn write(
+        &mut self,
+        mut segments: Vec<StreamSegment<Vec<OwnedFrame>>>,
+        savefile: &mut Savefile,
+        summary: &mut ExtractionSummary,
+    ) {
+        if segments.is_empty() {
+            return;
+        }
+        for segment in segments.iter_mut() {
+            self.frames.entry(segment.flow).or_default().push_back(SegmentFrames {
+                start: segment.offset,
+                end: segment.offset + segment.data.len() as u64,
+                frames: std::mem::take(&mut segment.tag),
+                written: false,
+            });
+        }
+        let mut flows: Vec<FlowKey> = segments.iter().map(|segment| segment.flow).collect();
+        flows.dedup();
+
+        let frames = &mut self.frames;
+        let messages = &self.messages;
+        self.framers.frame(segments, &messages.message_lengths, |message, _, segment| {
+            let carries = |f: &SegmentFrames| f.start < message.end() && f.end > message.offset;
+            // the capture time of the segment completing the message
+            let completed_at = || {
+                frames
+                    .get(&segment.flow)
+                    .into_iter()
+                    .flatten()
+                    .filter(|f| carries(f))
+                    .flat_map(|f| f.frames.iter().map(|(timestamp, _, _)| *timestamp))
+                    .max()
+            };
+            if !matches_header(&messages.header_filter, &message.bytes)
+                || (messages.filter.is_some()
+                    && !matches_filter(&messages.filter, &message.bytes, completed_at(), messages.layouts.as_ref()))
+            {
+                summary.record_skipped_message(Some(TransportProtocol::Tcp), &message.bytes);
+                return;
+            }
+            summary.matched += 1;
+            let pending = frames.get_mut(&segment.flow).into_iter().flatten();
+            for segment_frames in pending.filter(|f| carries(f) && !f.written) {
+                for (timestamp, orig_len, data) in segment_frames.frames.iter() {
+                    write_record(savefile, *timestamp, *orig_len, data);
+                    summary.written += 1;
+                }
+                segment_frames.written = true;
+            }
+        });
+
+        // frames before the partial message of a flow are done with
+        for flow in flows {
+            match self.framers.pending_offset(&flow) {
+                Some(offset) => {
+                    let pending = self.frames.entry(flow).or_default();
+                    while pending.front().is_some_and(|f| f.end <= offset) {
+                        pending.pop_front();
                     }
-                    None => return true,
+                },
+                None => {
+                    self.frames.remove(&flow);
+                },
+            }
+        }
+    }
+}
+
+/// The output file and the reassembly state of a scan, fed in capture order
+struct FilterOutput {
+    linktype: Linktype,
+    savefile: Savefile,
+    summary: ExtractionSummary,
+    // fragments are kept until their datagram is complete
+    pipeline: DatagramPipeline<OwnedFrame>,
+    tcp: TcpReassembler<Vec<OwnedFrame>>,
+    tcp_output: TcpOutput,
+}
+
+impl FilterOutput {
+    /// The output keeps the linktype of the input
+    fn create(extractor: &PacketExtractor, linktype: Linktype) -> Result<Self, Error> {
+        let savefile = Capture::dead_with_precision(linktype, Precision::Nano)
+            .and_then(|capture| capture.savefile(extractor.file_output.as_str()))
+            .map_err(|e| Error::CreateOutput { path: extractor.file_output.clone(), reason: e.to_string() })?;
+        Ok(FilterOutput {
+            linktype,
+            savefile,
+            summary: ExtractionSummary::default(),
+            pipeline: DatagramPipeline::new(extractor.channel_filter.clone()),
+            tcp: TcpReassembler::default(),
+            tcp_output: TcpOutput::new(extractor.messages.clone()),
+        })
+    }
+
+    fn expire(&mut self, timestamp: UnixNano) {
+        self.summary.skip_counts.fragmented += self.pipeline.expire(timestamp);
+        self.tcp_output.write(self.tcp.expire(timestamp), &mut self.savefile, &mut self.summary);
+    }
+
+    fn write(&mut self, record: &CaptureRecord) {
+        write_record(&mut self.savefile, record.timestamp, record.orig_len, record.data);
+        self.summary.written += 1;
+    }
+
+    /// A record `classify` left to reassembly
+    fn process(&mut self, extractor: &PacketExtractor, record: &CaptureRecord) {
+        let frame = || (record.timestamp, record.orig_len, record.data.to_vec());
+        let delivered = self.pipeline.push(record, frame, |datagram| {
+            let decoded = datagram.packet;
+            if !datagram.on_channel {
+                self.summary.skipped_per_protocol.record(decoded.protocol);
+                return;
+            }
+            let frames = if datagram.frames.is_empty() { vec![frame()] } else { datagram.frames };
+            if decoded.protocol == TransportProtocol::Tcp {
+                let segments = self.tcp.push(record.timestamp, &decoded, frames);
+                self.tcp_output.write(segments, &mut self.savefile, &mut self.summary);
+            } else if extractor.matches_datagram(decoded.payload, record.timestamp, &mut self.summary) {
+                self.summary.matched += 1;
+                for (timestamp, orig_len, data) in frames.iter() {
+                    write_record(&mut self.savefile, *timestamp, *orig_len, data);
+                    self.summary.written += 1;
                 }
+            } else {
+                self.summary.skipped_per_protocol.record(decoded.protocol);
             }
+        });
+        if let Err(reason) = delivered {
+            self.summary.record_skip(reason);
         }
-        false
     }
-}
\ No newline at end of file
+
+    fn finish(mut self, extractor: &PacketExtractor) -> Result<ExtractionSummary, Error> {
+        self.summary.skip_counts.fragmented += self.pipeline.finish();
+        self.tcp_output.write(self.tcp.finish(), &mut self.savefile, &mut self.summary);
+        self.savefile
+            .flush()
+            .map_err(|e| Error::WriteOutput { path: extractor.file_output.clone(), reason: e.to_string() })?;
+        Ok(self.summary)
+    }
+}
+
+fn write_record(savefile: &mut Savefile, timestamp: UnixNano, orig_len: u32, data: &[u8]) {
+    // with nanosecond precision, tv_usec carries nanoseconds
+    let header = PacketHeader {
+        ts: libc::timeval {
+            tv_sec: (timestamp / 1_000_000_000) as libc::time_t,
+            tv_usec: (timestamp % 1_000_000_000) as libc::suseconds_t,
+        },
+        caplen: data.len() as u32,
+        len: orig_len,
+    };
+    savefile.write(&pcap::Packet::new(&header, data));
+}
+
+/// Returns true if the payload starts with one of the headers, or if there is no filter.
+pub fn matches_header(header_filter: &Option<Vec<String>>, payload: &[u8]) -> bool {
+    match header_filter {
+        Some(ref filter) => filter.iter().any(|header| payload.starts_with(header.as_bytes())),
+        None => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pnet::packet::tcp::TcpFlags;
+    use crate::packet::test_frames::{b6_message, ipv4_fragment, tcp_frame, udp_frame, write_pcap};
+
+    #[test]
+    fn test_parallel_scan_matches_sequential() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir();
+        let input = dir.join("packet_extractor_parallel_in.pcap");
+        let sequential = dir.join("packet_extractor_parallel_seq.pcap");
+        let parallel = dir.join("packet_extractor_parallel_par.pcap");
+
+        let mut frames = Vec::new();
+        let mut expected = 0;
+        let stream = [b6_message(1), b6_message(2)].concat();
+        let datagram = udp_frame(&b6_message(3))[14 + 20..].to_vec();
+        for i in 0..2000u32 {
+            let frame = match i % 500 {
+                0 => tcp_frame(99, TcpFlags::SYN, b""),
+                1 => tcp_frame(100, TcpFlags::ACK, &stream[..400]),
+                2 => tcp_frame(500, TcpFlags::ACK, &stream[400..]),
+                3 => ipv4_fragment(i as u16, 0, true, &datagram[..200]),
+                4 => ipv4_fragment(i as u16 - 1, 200, false, &datagram[200..]),
+                _ if i % 3 == 0 => {
+                    expected += 1;
+                    udp_frame(&b6_message(i))
+                },
+                _ => udp_frame(b"A301K00000002\xff"),
+            };
+            frames.push((1_727_400_000 + (i / 100) as i64, i as i64, frame));
+        }
+        write_pcap(&input, &frames)?;
+
+        let extractor = |output: &std::path::Path| PacketExtractor::new(
+            input.to_str().unwrap().to_string(),
+            output.to_str().unwrap().to_string(),
+            Some(vec!["B606F".to_string()]),
+        );
+        let sequential_summary = extractor(&sequential).filter_packets_with_header()?;
+        let parallel_summary = extractor(&parallel).filter_packets_parallel(4)?;
+
+        let sequential_bytes = std::fs::read(&sequential)?;
+        let parallel_bytes = std::fs::read(&parallel)?;
+        for path in [&input, &sequential, &parallel] {
+            std::fs::remove_file(path)?;
+        }
+        assert_eq!(parallel_summary, sequential_summary);
+        assert_eq!(parallel_bytes, sequential_bytes);
+        // plus both segments of each TCP stream and both fragments of each datagram
+        let mut reader = CaptureReader::new(&parallel_bytes[..])?;
+        let mut records = 0;
+        while reader.next_record()?.is_some() {
+            records += 1;
+        }
+        assert_eq!(records, expected + 4 * 2 + 4 * 2);
+
+        let summary = parallel_summary;
+        let a301k = 2000 - expected - 4 * 5;
+        assert_eq!((summary.read, summary.written, summary.malformed), (2000, records, 0));
+        // two messages per TCP stream and one per fragmented datagram
+        assert_eq!(summary.matched, expected + 4 * 2 + 4);
+        assert_eq!(summary.skipped_per_protocol.udp, a301k);
+        assert_eq!(summary.skipped_per_trcode, BTreeMap::from([("A301K".to_string(), a301k)]));
+        assert_eq!(summary.first_timestamp, Some(1_727_400_000_000_000_000));
+        assert_eq!(summary.last_timestamp, Some(1_727_400_019_000_001_999));
+        Ok(())
+    }
+
+    #[test]
+    fn test_filter_expression() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir();
+        let input = dir.join("packet_extractor_filter_in.pcap");
+        let output = dir.join("packet_extractor_filter_out.pcap");
+
+        let stream = [b6_message(1), b6_message(2)].concat();
+        let datagram = udp_frame(&b6_message(3))[14 + 20..].to_vec();
+        let frames = vec![
+            tcp_frame(99, TcpFlags::SYN, b""),
+            tcp_frame(100, TcpFlags::ACK, &stream[..400]),
+            tcp_frame(500, TcpFlags::ACK, &stream[400..]),
+            ipv4_fragment(7, 0, true, &datagram[..200]),
+            ipv4_fragment(7, 200, false, &datagram[200..]),
+            udp_frame(&b6_message(5)),
+            udp_frame(&b6_message(6)),
+            udp_frame(b"A301K00000002\xff"),
+        ];
+        let frames: Vec<_> = frames.into_iter().enumerate().map(|(i, frame)| (1_727_400_000, i as i64, frame)).collect();
+        write_pcap(&input, &frames)?;
+
+        // message 2 spans both TCP segments, message 3 both fragments
+        let filter = Filter::parse(r#"family == "B6" and (distidx in 2..4 or distidx == 6)"#)?;
+        PacketExtractor::new(input.to_str().unwrap().to_string(), output.to_str().unwrap().to_string(), None)
+            .with_filter(filter)
+            .filter_packets_with_header()?;
+
+        let mut reader = CaptureReader::from_file(&output)?;
+        let mut nanos = Vec::new();
+        while let Some(record) = reader.next_record()? {
+            nanos.push(record.timestamp % 1_000_000_000);
+        }
+        std::fs::remove_file(&input)?;
+        std::fs::remove_file(&output)?;
+        assert_eq!(nanos, vec![1, 2, 3, 4, 6]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_time_window_slice() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir();
+        let input = dir.join("packet_extractor_window_in.pcap");
+        let slice = dir.join("packet_extractor_window_slice.pcap");
+        let filtered = dir.join("packet_extractor_window_filtered.pcap");
+
+        // 10:20:00 KST, a packet every millisecond, every other one B606F
+        let frames: Vec<_> = (0..1000u32)
+            .map(|i| {
+                let frame = if i % 2 == 0 { udp_frame(&b6_message(i)) } else { udp_frame(b"A301K00000002\xff") };
+                (1_727_400_000, i as i64 * 1_000_000, frame)
+            })
+            .collect();
+        write_pcap(&input, &frames)?;
+        let time_window = TimeWindow::kst(20240927, "10:20:00.100", "10:20:00.200").unwrap();
+        let extractor = |output: &std::path::Path| PacketExtractor::new(
+            input.to_str().unwrap().to_string(),
+            output.to_str().unwrap().to_string(),
+            Some(vec!["B606F".to_string()]),
+        ).with_time_window(time_window);
+
+        assert_eq!(extractor(&slice).write_slice()?, 100);
+        assert_eq!(extractor(&slice).with_sorted_input().write_slice()?, 100);
+        extractor(&filtered).with_sorted_input().filter_packets_with_header()?;
+        let msgs = extractor(&filtered).krx_msgs(20240927)?.collect::<io::Result<Vec<_>>>()?;
+
+        let read = |path: &std::path::Path| -> anyhow::Result<Vec<UnixNano>> {
+            let mut reader = CaptureReader::from_file(path)?;
+            let mut timestamps = Vec::new();
+            while let Some(record) = reader.next_record()? {
+                timestamps.push(record.timestamp);
+            }
+            Ok(timestamps)
+        };
+        let sliced = read(&slice)?;
+        let kept = read(&filtered)?;
+        for path in [&input, &slice, &filtered] {
+            std::fs::remove_file(path)?;
+        }
+        assert_eq!(sliced.len(), 100);
+        assert_eq!(sliced[0], 1_727_400_000_100_000_000);
+        assert_eq!(sliced[99], 1_727_400_000_199_000_000);
+        assert_eq!(kept.len(), 50);
+        assert_eq!(msgs.len(), 50);
+        assert_eq!(msgs[0].distidx, Some(100));
+        Ok(())
+    }
+
+    #[test]
+    fn test_time_window_unsorted_input() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir();
+        let input = dir.join("packet_extractor_unsorted_in.pcap");
+        let output = dir.join("packet_extractor_unsorted_out.pcap");
+        // two lines merged one after the other: 10:20:00 to 10:20:01, then 10:20:00 again
+        let frames: Vec<_> = (0..2000u32)
+            .map(|i| (1_727_400_000, (i % 1000) as i64 * 1_000_000, udp_frame(&b6_message(i))))
+            .collect();
+        write_pcap(&input, &frames)?;
+        let time_window = TimeWindow::kst(20240927, "10:20:00.100", "10:20:00.200").unwrap();
+        let extractor = PacketExtractor::new(
+            input.to_str().unwrap().to_string(),
+            output.to_str().unwrap().to_string(),
+            None,
+        ).with_time_window(time_window);
+
+        let summary = extractor.filter_packets_with_header()?;
+        assert_eq!((summary.read, summary.written), (200, 200));
+        assert_eq!(extractor.filter_packets_parallel(4)?.written, 200);
+        assert_eq!(extractor.write_slice()?, 200);
+        std::fs::remove_file(&input)?;
+        std::fs::remove_file(&output)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_extraction_errors() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir();
+        let input = dir.join("packet_extractor_errors_in.pcap");
+        let output = dir.join("packet_extractor_errors_out.pcap");
+        let extractor = |input: &std::path::Path, output: &std::path::Path| PacketExtractor::new(
+            input.to_str().unwrap().to_string(),
+            output.to_str().unwrap().to_string(),
+            None,
+        );
+
+        let missing = dir.join("packet_extractor_errors_missing.pcap");
+        let error = extractor(&missing, &output).filter_packets_with_header().unwrap_err();
+        assert!(matches!(error, Error::OpenInput { .. }), "{}", error);
+
+        write_pcap(&input, &[(1_727_400_000, 0, udp_frame(&b6_message(1))), (1_727_400_000, 1, udp_frame(&b6_message(2)))])?;
+        let error = extractor(&input, &dir.join("no_such_dir").join("out.pcap")).filter_packets_with_header().unwrap_err();
+        assert!(matches!(error, Error::CreateOutput { .. }), "{}", error);
+
+        // the capture ends in the middle of the second record
+        let bytes = std::fs::read(&input)?;
+        std::fs::write(&input, &bytes[..bytes.len() - 10])?;
+        for result in [extractor(&input, &output).filter_packets_with_header(), extractor(&input, &output).filter_packets_parallel(2)] {
+            assert!(matches!(result, Err(Error::ReadInput { .. })), "{:?}", result);
+        }
+        // what came before is written
+        let mut reader = CaptureReader::from_file(&output)?;
+        assert!(reader.next_record()?.is_some());
+        std::fs::remove_file(&input)?;
+        std::fs::remove_file(&output)?;
+        Ok(())
+    }
+}
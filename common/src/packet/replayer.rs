@@ -0,0 +1,294 @@
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::thread;
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+use socket2::{Domain, Protocol, Socket, Type};
+use crate::packet::capture_reader::CaptureReader;
+use crate::packet::channel::ChannelFilter;
+use crate::packet::decoder::{SkipCounts, TransportProtocol};
+use crate::packet::framing::MessageLengths;
+use crate::packet::krx_msg_iter::{DatagramPipeline, MessageFilter};
+use crate::UnixNano;
+
+/// below this the replayer spins instead of sleeping
+const SPIN_THRESHOLD: Duration = Duration::from_micros(200);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pacing {
+    /// capture time scaled by the factor, 1.0 is real time and 10.0 ten times faster
+    Speed(f64),
+    /// as fast as the socket takes them
+    MaxRate,
+}
+
+impl Default for Pacing {
+    fn default() -> Self {
+        Pacing::Speed(1.0)
+    }
+}
+
+/// # Arguments
+/// * `sent`, `bytes` - datagrams (UDP payloads) sent and their total size
+/// * `filtered` - UDP packets outside the time window or failing the channel/header filters, and TCP packets
+/// * `send_errors` - datagrams the socket refused, they are not retried
+/// * `max_lag` - ns, the latest a datagram went out after its paced time
+/// * `skip_counts` - frames that could not be decoded
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplayStats {
+    pub sent: u64,
+    pub bytes: u64,
+    pub filtered: u64,
+    pub send_errors: u64,
+    pub max_lag: UnixNano,
+    pub skip_counts: SkipCounts,
+}
+
+/// Sends the UDP payloads of a capture to `target`, paced by their capture timestamps.
+/// IP fragments are reassembled and the datagram is sent whole. With a header filter, a datagram is
+/// sent if one of its messages matches. Multicast targets get `multicast_ttl` (default 1) and loop
+/// back to local receivers.
+#[derive(Debug, Clone)]
+pub struct UdpReplayer {
+    file_input: String,
+    target: SocketAddr,
+    channel_filter: Option<Vec<ChannelFilter>>,
+    messages: MessageFilter,
+    pacing: Pacing,
+    start: Option<UnixNano>,
+    stop: Option<UnixNano>,
+    multicast_interface: Option<Ipv4Addr>,
+    multicast_ttl: u32,
+}
+
+impl UdpReplayer {
+    pub fn new(file_input: String, target: SocketAddr, header_filter: Option<Vec<String>>) -> Self {
+        UdpReplayer {
+            file_input,
+            target,
+            channel_filter: None,
+            messages: MessageFilter { header_filter, ..MessageFilter::default() },
+            pacing: Pacing::default(),
+            start: None,
+            stop: None,
+            multicast_interface: None,
+            multicast_ttl: 1,
+        }
+    }
+
+    pub fn with_channel_filter(mut self, channel_filter: Vec<ChannelFilter>) -> Self {
+        self.channel_filter = Some(channel_filter);
+        self
+    }
+
+    /// Overrides `KRX_MESSAGE_LENGTHS` used to split datagrams for the header filter
+    pub fn with_message_lengths(mut self, message_lengths: MessageLengths) -> Self {
+        self.messages.message_lengths = message_lengths;
+        self
+    }
+
+    /// `InvalidInput` for a speed that is not a positive finite number
+    pub fn with_pacing(mut self, pacing: Pacing) -> io::Result<Self> {
+        if let Pacing::Speed(speed) = pacing {
+            if !speed.is_finite() || speed <= 0.0 {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid replay speed {}", speed)));
+            }
+        }
+        self.pacing = pacing;
+        Ok(self)
+    }
+
+    /// Packets captured before `start` (UnixNano) are not sent, pacing starts at the first packet sent
+    pub fn with_start(mut self, start: UnixNano) -> Self {
+        self.start = Some(start);
+        self
+    }
+
+    /// The replay ends at the first packet captured at or after `stop` (UnixNano)
+    pub fn with_stop(mut self, stop: UnixNano) -> Self {
+        self.stop = Some(stop);
+        self
+    }
+
+    /// Local interface for IPv4 multicast targets, the system default otherwise
+    pub fn with_multicast_interface(mut self, interface: Ipv4Addr) -> Self {
+        self.multicast_interface = Some(interface);
+        self
+    }
+
+    pub fn with_multicast_ttl(mut self, ttl: u32) -> Self {
+        self.multicast_ttl = ttl;
+        self
+    }
+
+    pub fn replay(&self) -> io::Result<ReplayStats> {
+        let socket = self.socket()?;
+        let mut reader = CaptureReader::from_file(&self.file_input)?;
+        let mut pipeline: DatagramPipeline = DatagramPipeline::new(self.channel_filter.clone());
+        let mut stats = ReplayStats::default();
+        // capture time and wall clock of the first packet sent
+        let mut origin: Option<(UnixNano, Instant)> = None;
+
+        while let Some(record) = reader.next_record()? {
+            let timestamp = record.timestamp;
+            if self.stop.is_some_and(|stop| timestamp >= stop) {
+                break;
+            }
+            stats.skip_counts.fragmented += pipeline.expire(timestamp);
+
+            let delivered = pipeline.push(&record, || (), |datagram| {
+                let payload = datagram.packet.payload;
+                if datagram.packet.protocol != TransportProtocol::Udp
+                    || self.start.is_some_and(|start| timestamp < start)
+                    || !datagram.on_channel
+                    || !self.matches_datagram(payload)
+                {
+                    stats.filtered += 1;
+                    return;
+                }
+
+                if let Pacing::Speed(speed) = self.pacing {
+                    let (first, started) = *origin.get_or_insert((timestamp, Instant::now()));
+                    let offset = timestamp.saturating_sub(first) as f64 / speed;
+                    let deadline = started + Duration::from_nanos(offset as u64);
+                    wait_until(deadline);
+                    stats.max_lag = stats.max_lag.max(deadline.elapsed().as_nanos() as UnixNano);
+                }
+                match socket.send(payload) {
+                    Ok(_) => {
+                        stats.sent += 1;
+                        stats.bytes += payload.len() as u64;
+                    },
+                    Err(_) => stats.send_errors += 1,
+                }
+            });
+            if let Err(reason) = delivered {
+                stats.skip_counts.record(reason);
+            }
+        }
+        stats.skip_counts.fragmented += pipeline.finish();
+        Ok(stats)
+    }
+
+    fn matches_datagram(&self, payload: &[u8]) -> bool {
+        self.messages.header_filter.is_none() || self.messages.matches_datagram(payload, None)
+    }
+
+    fn socket(&self) -> io::Result<UdpSocket> {
+        let socket = Socket::new(Domain::for_address(self.target), Type::DGRAM, Some(Protocol::UDP))?;
+        match self.target {
+            SocketAddr::V4(target) => {
+                if target.ip().is_multicast() {
+                    socket.set_multicast_ttl_v4(self.multicast_ttl)?;
+                    socket.set_multicast_loop_v4(true)?;
+                    if let Some(interface) = self.multicast_interface {
+                        socket.set_multicast_if_v4(&interface)?;
+                    }
+                }
+                socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)).into())?;
+            },
+            SocketAddr::V6(target) => {
+                if target.ip().is_multicast() {
+                    socket.set_multicast_hops_v6(self.multicast_ttl)?;
+                    socket.set_multicast_loop_v6(true)?;
+                }
+                socket.bind(&SocketAddr::from((std::net::Ipv6Addr::UNSPECIFIED, 0)).into())?;
+            },
+        }
+        socket.connect(&self.target.into())?;
+        Ok(socket.into())
+    }
+}
+
+/// Sleeps most of the way and spins the rest, `thread::sleep` alone overshoots by up to a scheduler tick
+fn wait_until(deadline: Instant) {
+    loop {
+        let now = Instant::now();
+        if now >= deadline {
+            return;
+        }
+        let left = deadline - now;
+        if left > SPIN_THRESHOLD {
+            thread::sleep(left - SPIN_THRESHOLD);
+        } else {
+            std::hint::spin_loop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::test_frames::{b6_message, udp_frame, write_pcap};
+
+    const T0: i64 = 1_727_400_000;
+
+    /// B606F distidx 0..10 every 10ms, with an A301K after each
+    fn write_capture(path: &std::path::Path) -> anyhow::Result<()> {
+        let mut frames = Vec::new();
+        for i in 0..10 {
+            frames.push((T0, i * 10_000_000, udp_frame(&b6_message(i as u32))));
+            frames.push((T0, i * 10_000_000 + 1, udp_frame(b"A301K00000002\xff")));
+        }
+        write_pcap(path, &frames)
+    }
+
+    fn receiver() -> anyhow::Result<UdpSocket> {
+        let receiver = UdpSocket::bind("127.0.0.1:0")?;
+        receiver.set_read_timeout(Some(Duration::from_millis(500)))?;
+        Ok(receiver)
+    }
+
+    fn received_distidx(receiver: &UdpSocket, count: usize) -> anyhow::Result<Vec<u32>> {
+        let mut buf = [0u8; 2048];
+        let mut distidx = Vec::new();
+        for _ in 0..count {
+            let n = receiver.recv(&mut buf)?;
+            assert_eq!(n, 324);
+            distidx.push(std::str::from_utf8(&buf[5..13])?.parse()?);
+        }
+        Ok(distidx)
+    }
+
+    #[test]
+    fn test_replay_to_loopback() -> anyhow::Result<()> {
+        let path = std::env::temp_dir().join("replayer_test.pcap");
+        write_capture(&path)?;
+        let receiver = receiver()?;
+
+        let replayer = UdpReplayer::new(
+            path.to_str().unwrap().to_string(),
+            receiver.local_addr()?,
+            Some(vec!["B606F".to_string()]),
+        );
+        let stats = replayer.clone().with_pacing(Pacing::MaxRate)?.replay()?;
+        assert_eq!((stats.sent, stats.bytes, stats.filtered), (10, 3240, 10));
+        assert_eq!(received_distidx(&receiver, 10)?, (0..10).collect::<Vec<_>>());
+
+        // 30ms..70ms of capture time at 2x speed
+        let t0 = T0 as UnixNano * 1_000_000_000;
+        let started = Instant::now();
+        let stats = replayer
+            .with_pacing(Pacing::Speed(2.0))?
+            .with_start(t0 + 30_000_000)
+            .with_stop(t0 + 70_000_000)
+            .replay()?;
+        let elapsed = started.elapsed();
+        std::fs::remove_file(&path)?;
+
+        assert_eq!(stats.sent, 4);
+        assert_eq!(received_distidx(&receiver, 4)?, vec![3, 4, 5, 6]);
+        assert!(elapsed >= Duration::from_millis(15), "{:?}", elapsed);
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_speed() {
+        let replayer = UdpReplayer::new("in.pcap".to_string(), "127.0.0.1:9".parse().unwrap(), None);
+        for speed in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            let error = replayer.clone().with_pacing(Pacing::Speed(speed)).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        }
+        assert!(replayer.with_pacing(Pacing::Speed(0.5)).is_ok());
+    }
+}
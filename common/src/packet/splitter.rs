@@ -0,0 +1,308 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use pcap::Linktype;
+use serde::{Deserialize, Serialize};
+use crate::layout::LayoutRegistry;
+use crate::mongodb_collection::krx_msg::range_helper::krx_messages_instcode_range;
+use crate::packet::capture_reader::{write_pcap_header, write_pcap_record, CaptureReader, CaptureRecord, OwnedFrame};
+use crate::packet::channel::ChannelFilter;
+use crate::packet::decoder::{SkipCounts, TransportProtocol};
+use crate::packet::framing::MessageLengths;
+use crate::packet::krx_msg_iter::{DatagramPipeline, MessageFilter, SplitMessage};
+
+pub const DEFAULT_MAX_OPEN_FILES: usize = 64;
+
+/// written for messages without an instcode (or a trcode that is not text)
+const UNKNOWN_KEY: &str = "unknown";
+
+const TRCODE_LEN: usize = 5;
+
+/// # Arguments
+/// * `records` - records read from the input
+/// * `written` - records written, a packet carrying messages of several outputs is written to each of them
+/// * `files` - outputs created
+/// * `reopened` - times an output closed to respect the open file cap was opened again
+/// * `tcp` - TCP packets, not split
+/// * `skip_counts` - frames that could not be decoded
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SplitStats {
+    pub records: u64,
+    pub written: u64,
+    pub files: u64,
+    pub reopened: u64,
+    pub tcp: u64,
+    pub skip_counts: SkipCounts,
+}
+
+/// Reads a capture once and writes each UDP packet to the outputs its messages map to.
+/// The output path comes from a template with the placeholders
+/// `{trcode}` (B606F), `{family}` (B6) and `{instcode}` (KR4165N30007, "unknown" if the message has none),
+/// e.g., "out/{trcode}_{instcode}.pcap" or "out/{family}.pcap".
+/// Outputs are nanosecond pcap with the linktype of the input. At most `max_open_files` are open at
+/// a time, the least recently used one is closed and later appended to.
+/// IP fragments are reassembled and all fragments of a datagram are written. TCP packets are not split.
+/// Instcode offsets and message lengths are built in unless given spec layouts, see `with_layouts`.
+#[derive(Debug, Clone)]
+pub struct PcapSplitter {
+    file_input: String,
+    template: String,
+    channel_filter: Option<Vec<ChannelFilter>>,
+    messages: MessageFilter,
+    max_open_files: usize,
+}
+
+impl PcapSplitter {
+    pub fn new(file_input: String, template: String, header_filter: Option<Vec<String>>) -> Self {
+        PcapSplitter {
+            file_input,
+            template,
+            channel_filter: None,
+            messages: MessageFilter { header_filter, ..MessageFilter::default() },
+            max_open_files: DEFAULT_MAX_OPEN_FILES,
+        }
+    }
+
+    pub fn with_channel_filter(mut self, channel_filter: Vec<ChannelFilter>) -> Self {
+        self.channel_filter = Some(channel_filter);
+        self
+    }
+
+    /// Overrides `KRX_MESSAGE_LENGTHS` used to split datagrams
+    pub fn with_message_lengths(mut self, message_lengths: MessageLengths) -> Self {
+        self.messages.message_lengths = message_lengths;
+        self
+    }
+
+    /// Takes the instcode offsets and message lengths of the trcodes in the registry from their specs.
+    /// Message lengths are set as `with_message_lengths(layouts.message_lengths())` would.
+    pub fn with_layouts(mut self, layouts: &LayoutRegistry) -> Self {
+        self.messages.message_lengths = layouts.message_lengths();
+        self.messages.layouts = Some(layouts.clone());
+        self
+    }
+
+    pub fn with_max_open_files(mut self, max_open_files: usize) -> Self {
+        self.max_open_files = max_open_files.max(1);
+        self
+    }
+
+    /// Output path of a message
+    pub fn output_path(&self, message: &[u8]) -> String {
+        let trcode = message
+            .get(..TRCODE_LEN)
+            .and_then(|trcode| std::str::from_utf8(trcode).ok())
+            .filter(|trcode| trcode.bytes().all(|b| b.is_ascii_alphanumeric()))
+            .unwrap_or(UNKNOWN_KEY);
+        let family = trcode.get(..2).filter(|_| trcode != UNKNOWN_KEY).unwrap_or(UNKNOWN_KEY);
+        let instcode = match self.messages.layouts {
+            Some(ref layouts) => layouts.instcode_range(message),
+            None => krx_messages_instcode_range(message),
+        };
+        let instcode = instcode
+            .and_then(|range| message.get(range))
+            .and_then(|instcode| std::str::from_utf8(instcode).ok())
+            .map(|instcode| instcode.trim())
+            .filter(|instcode| !instcode.is_empty() && instcode.bytes().all(|b| b.is_ascii_alphanumeric()))
+            .unwrap_or(UNKNOWN_KEY);
+        self.template
+            .replace("{trcode}", trcode)
+            .replace("{family}", family)
+            .replace("{instcode}", instcode)
+    }
+
+    pub fn split(&self) -> io::Result<SplitStats> {
+        let mut reader = CaptureReader::from_file(&self.file_input)?;
+        let linktype = reader.linktype();
+        let mut outputs = SplitOutputs::new(linktype, self.max_open_files);
+        let mut pipeline: DatagramPipeline<OwnedFrame> = DatagramPipeline::new(self.channel_filter.clone());
+        let mut stats = SplitStats::default();
+
+        while let Some(record) = reader.next_record()? {
+            stats.records += 1;
+            stats.skip_counts.fragmented += pipeline.expire(record.timestamp);
+            if record.linktype != linktype {
+                stats.skip_counts.unsupported_linktype += 1;
+                continue;
+            }
+            let frame = || (record.timestamp, record.orig_len, record.data.to_vec());
+            let delivered = pipeline.push(&record, frame, |datagram| -> io::Result<()> {
+                if datagram.packet.protocol == TransportProtocol::Tcp {
+                    stats.tcp += datagram.frames.len().max(1) as u64;
+                    return Ok(());
+                }
+                if !datagram.on_channel {
+                    return Ok(());
+                }
+                for path in self.output_paths(datagram.packet.payload) {
+                    if datagram.frames.is_empty() {
+                        outputs.write(&path, &record)?;
+                    }
+                    for (timestamp, orig_len, data) in datagram.frames.iter() {
+                        outputs.write(&path, &CaptureRecord {
+                            timestamp: *timestamp,
+                            linktype,
+                            interface_id: 0,
+                            orig_len: *orig_len,
+                            data,
+                        })?;
+                    }
+                }
+                Ok(())
+            });
+            match delivered {
+                Ok(Some(written)) => written?,
+                Ok(None) => {},
+                Err(reason) => stats.skip_counts.record(reason),
+            }
+        }
+        stats.skip_counts.fragmented += pipeline.finish();
+        outputs.finish(&mut stats)?;
+        Ok(stats)
+    }
+
+    /// Distinct outputs of the valid messages of a datagram passing the header filter, in message order
+    fn output_paths(&self, payload: &[u8]) -> Vec<String> {
+        let mut paths: Vec<String> = Vec::new();
+        for message in self.messages.split(payload, None) {
+            let SplitMessage::Kept(message) = message else {
+                continue;
+            };
+            let path = self.output_path(message);
+            if !paths.contains(&path) {
+                paths.push(path);
+            }
+        }
+        paths
+    }
+}
+
+/// Open outputs, closed least recently used first
+struct SplitOutputs {
+    linktype: Linktype,
+    max_open_files: usize,
+    open: HashMap<String, (BufWriter<File>, u64)>,
+    created: HashSet<String>,
+    clock: u64,
+    written: u64,
+    reopened: u64,
+}
+
+impl SplitOutputs {
+    fn new(linktype: Linktype, max_open_files: usize) -> Self {
+        SplitOutputs {
+            linktype,
+            max_open_files,
+            open: HashMap::new(),
+            created: HashSet::new(),
+            clock: 0,
+            written: 0,
+            reopened: 0,
+        }
+    }
+
+    fn write(&mut self, path: &str, record: &CaptureRecord) -> io::Result<()> {
+        self.clock += 1;
+        if !self.open.contains_key(path) {
+            if self.open.len() >= self.max_open_files {
+                let oldest = self.open.iter().min_by_key(|(_, (_, used))| *used).map(|(path, _)| path.clone()).unwrap();
+                let (mut output, _) = self.open.remove(&oldest).unwrap();
+                output.flush()?;
+            }
+            let output = self.open_output(path)?;
+            self.open.insert(path.to_string(), (output, self.clock));
+        }
+        let (output, used) = self.open.get_mut(path).unwrap();
+        *used = self.clock;
+
+        write_pcap_record(output, record.timestamp, record.orig_len, record.data)?;
+        self.written += 1;
+        Ok(())
+    }
+
+    /// A new output gets the file header (replacing a file left by an earlier run), a reopened one is appended to
+    fn open_output(&mut self, path: &str) -> io::Result<BufWriter<File>> {
+        if self.created.contains(path) {
+            self.reopened += 1;
+            return Ok(BufWriter::new(OpenOptions::new().append(true).open(path)?));
+        }
+        if let Some(parent) = Path::new(path).parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut output = BufWriter::new(File::create(path)?);
+        write_pcap_header(&mut output, self.linktype)?;
+        self.created.insert(path.to_string());
+        Ok(output)
+    }
+
+    fn finish(self, stats: &mut SplitStats) -> io::Result<()> {
+        for (_, (mut output, _)) in self.open {
+            output.flush()?;
+        }
+        stats.written = self.written;
+        stats.files = self.created.len() as u64;
+        stats.reopened = self.reopened;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::test_frames::{b6_message, udp_frame, write_pcap};
+
+    fn b6_for(instcode: &str, distidx: u32) -> Vec<u8> {
+        let mut message = b6_message(distidx);
+        message[17..29].copy_from_slice(instcode.as_bytes());
+        message
+    }
+
+    #[test]
+    fn test_split_by_trcode_and_instcode() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir().join("splitter_test");
+        let input = std::env::temp_dir().join("splitter_test_in.pcap");
+        let instcodes = ["KR4165N30007", "KR4167N30005", "KR4170N30002"];
+        let mut frames = Vec::new();
+        for i in 0..30u32 {
+            frames.push((1_727_400_000, i as i64, udp_frame(&b6_for(instcodes[i as usize % 3], i))));
+        }
+        // one packet with messages of two instruments, and one without a layout
+        frames.push((1_727_400_001, 0, udp_frame(&[b6_for(instcodes[0], 30), b6_for(instcodes[1], 31)].concat())));
+        frames.push((1_727_400_001, 1, udp_frame(b"A301K00000002\xff")));
+        write_pcap(&input, &frames)?;
+
+        let template = format!("{}/{{trcode}}_{{instcode}}.pcap", dir.display());
+        let stats = PcapSplitter::new(input.to_str().unwrap().to_string(), template, None)
+            // fewer handles than outputs, so that outputs are closed and reopened
+            .with_max_open_files(2)
+            .split()?;
+        assert_eq!(stats.records, 32);
+        assert_eq!(stats.files, 4);
+        assert_eq!(stats.written, 30 + 2 + 1);
+        assert!(stats.reopened > 0);
+
+        for (instcode, expected) in [(instcodes[0], 11), (instcodes[1], 11), (instcodes[2], 10)] {
+            let mut reader = CaptureReader::from_file(dir.join(format!("B606F_{}.pcap", instcode)))?;
+            let mut count = 0;
+            let mut last = 0;
+            while let Some(record) = reader.next_record()? {
+                assert!(record.timestamp >= last);
+                last = record.timestamp;
+                count += 1;
+            }
+            assert_eq!(count, expected, "{}", instcode);
+        }
+        assert!(dir.join("A301K_unknown.pcap").exists());
+        std::fs::remove_dir_all(&dir)?;
+        std::fs::remove_file(&input)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_output_path_by_family() {
+        let splitter = PcapSplitter::new(String::new(), "{family}/{trcode}.pcap".to_string(), None);
+        assert_eq!(splitter.output_path(&b6_message(1)), "B6/B606F.pcap");
+        assert_eq!(splitter.output_path(b"\xff\xfe"), "unknown/unknown.pcap");
+    }
+}
@@ -0,0 +1,394 @@
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+use serde::{Deserialize, Serialize};
+use crate::packet::decoder::DecodedPacket;
+use crate::UnixNano;
+
+/// How long an out-of-order segment waits for the hole before it to be retransmitted.
+/// Linux's minimum RTO is 200ms, a retransmission later than 1 second is treated as lost.
+pub const DEFAULT_GAP_TIMEOUT: UnixNano = 1_000_000_000;
+
+/// Out-of-order bytes buffered per flow before the hole is given up on
+const MAX_PENDING_BYTES: usize = 4 << 20;
+
+/// One direction of a TCP connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct FlowKey {
+    pub src: IpAddr,
+    pub src_port: u16,
+    pub dst: IpAddr,
+    pub dst_port: u16,
+}
+
+impl FlowKey {
+    pub fn from_packet(packet: &DecodedPacket) -> Self {
+        FlowKey {
+            src: packet.src,
+            src_port: packet.src_port,
+            dst: packet.dst,
+            dst_port: packet.dst_port,
+        }
+    }
+}
+
+/// # Arguments
+/// * `flows` - streams seen (a SYN on a known flow starts a new one)
+/// * `segments` - segments carrying data
+/// * `bytes` - bytes delivered in order
+/// * `retransmitted` - segments whose data was already delivered or buffered
+/// * `out_of_order` - segments that arrived ahead of a hole
+/// * `gaps` - holes given up on (timeout, buffer limit, RST or end of capture)
+/// * `gap_bytes` - bytes missing in those holes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TcpStats {
+    pub flows: u64,
+    pub segments: u64,
+    pub bytes: u64,
+    pub retransmitted: u64,
+    pub out_of_order: u64,
+    pub gaps: u64,
+    pub gap_bytes: u64,
+}
+
+/// In-order data of one flow
+/// # Arguments
+/// * `offset` - stream offset of `data[0]` (0 is the byte after the SYN, or the first byte seen mid-stream)
+/// * `gap` - bytes missing right before `data`
+/// * `data` - already-delivered bytes of a retransmission are trimmed
+/// * `tag` - the tag pushed with the segment
+/// * `fin` - the stream ended (FIN or RST) after this segment, per-flow state can be dropped
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamSegment<T> {
+    pub flow: FlowKey,
+    pub offset: u64,
+    pub gap: u64,
+    pub data: Vec<u8>,
+    pub tag: T,
+    pub fin: bool,
+}
+
+#[derive(Debug)]
+struct PendingSegment<T> {
+    timestamp: UnixNano,
+    data: Vec<u8>,
+    tag: T,
+}
+
+#[derive(Debug)]
+struct FlowState<T> {
+    // sequence number of stream offset 0
+    isn: u32,
+    // next stream offset to deliver
+    next: u64,
+    pending: BTreeMap<u64, PendingSegment<T>>,
+    pending_bytes: usize,
+    fin: Option<u64>,
+}
+
+impl<T> FlowState<T> {
+    fn new(isn: u32) -> Self {
+        FlowState {
+            isn,
+            next: 0,
+            pending: BTreeMap::new(),
+            pending_bytes: 0,
+            fin: None,
+        }
+    }
+
+    /// sequence numbers wrap at 2^32, the offset closest to `next` is taken
+    fn offset_of(&self, seq: u32) -> i64 {
+        let relative = seq.wrapping_sub(self.isn);
+        let delta = relative.wrapping_sub(self.next as u32) as i32;
+        self.next as i64 + delta as i64
+    }
+
+    fn oldest_pending(&self) -> Option<UnixNano> {
+        self.pending.values().map(|segment| segment.timestamp).min()
+    }
+}
+
+/// Puts the segments of each TCP flow back in sequence order: retransmissions are deduplicated,
+/// out-of-order segments are held until the hole before them is filled, and a hole that is not
+/// filled within the timeout is skipped and reported as `gap`.
+/// `T` is attached to every segment and handed back with its data, e.g., the capture timestamp.
+#[derive(Debug)]
+pub struct TcpReassembler<T = ()> {
+    gap_timeout: UnixNano,
+    flows: HashMap<FlowKey, FlowState<T>>,
+    stats: TcpStats,
+}
+
+impl<T> TcpReassembler<T> {
+    pub fn new(gap_timeout: UnixNano) -> Self {
+        TcpReassembler {
+            gap_timeout,
+            flows: HashMap::new(),
+            stats: TcpStats::default(),
+        }
+    }
+
+    /// Returns the data that became deliverable, in stream order. Non-TCP packets are ignored.
+    pub fn push(&mut self, timestamp: UnixNano, packet: &DecodedPacket, tag: T) -> Vec<StreamSegment<T>> {
+        let mut delivered = Vec::new();
+        let header = match packet.tcp {
+            Some(header) => header,
+            None => return delivered,
+        };
+        let key = FlowKey::from_packet(packet);
+
+        if header.is_rst() {
+            if let Some(mut flow) = self.flows.remove(&key) {
+                self.flush(key, &mut flow, &mut delivered);
+                close(key, flow.next, &mut delivered, Some(tag));
+            }
+            return delivered;
+        }
+        if header.is_syn() {
+            // a new connection on the same addresses ends the previous one, so that framing
+            // restarts at the new stream's offset 0
+            if let Some(mut flow) = self.flows.remove(&key) {
+                self.flush(key, &mut flow, &mut delivered);
+                close(key, flow.next, &mut delivered, Some(tag));
+            }
+            self.flows.insert(key, FlowState::new(header.seq.wrapping_add(1)));
+            self.stats.flows += 1;
+            return delivered;
+        }
+
+        let mut flow = match self.flows.remove(&key) {
+            Some(flow) => flow,
+            None => {
+                // picked up mid-stream
+                self.stats.flows += 1;
+                FlowState::new(header.seq)
+            }
+        };
+        let mut tag = Some(tag);
+        let offset = flow.offset_of(header.seq);
+        let end = offset + packet.payload.len() as i64;
+        if header.is_fin() {
+            flow.fin = Some(end.max(0) as u64);
+        }
+
+        if !packet.payload.is_empty() {
+            self.stats.segments += 1;
+            if end <= flow.next as i64 {
+                self.stats.retransmitted += 1;
+            } else if offset <= flow.next as i64 {
+                let skip = (flow.next as i64 - offset) as usize;
+                self.deliver(key, &mut flow, 0, packet.payload[skip..].to_vec(), tag.take().unwrap(), &mut delivered);
+                self.drain(key, &mut flow, 0, &mut delivered);
+            } else if flow.pending.contains_key(&(offset as u64)) {
+                self.stats.retransmitted += 1;
+            } else {
+                self.stats.out_of_order += 1;
+                flow.pending_bytes += packet.payload.len();
+                flow.pending.insert(offset as u64, PendingSegment {
+                    timestamp,
+                    data: packet.payload.to_vec(),
+                    tag: tag.take().unwrap(),
+                });
+                if flow.pending_bytes > MAX_PENDING_BYTES {
+                    self.skip_hole(key, &mut flow, &mut delivered);
+                }
+            }
+        }
+
+        if flow.fin.is_some_and(|fin| fin <= flow.next) && flow.pending.is_empty() {
+            close(key, flow.next, &mut delivered, tag);
+        } else {
+            self.flows.insert(key, flow);
+        }
+        delivered
+    }
+
+    /// Skips the holes that waited longer than the gap timeout and returns the data behind them
+    pub fn expire(&mut self, now: UnixNano) -> Vec<StreamSegment<T>> {
+        let mut delivered = Vec::new();
+        let timeout = self.gap_timeout;
+        let expired: Vec<FlowKey> = self.flows
+            .iter()
+            .filter(|(_, flow)| flow.oldest_pending().is_some_and(|since| now.saturating_sub(since) > timeout))
+            .map(|(key, _)| *key)
+            .collect();
+        for key in expired {
+            let mut flow = self.flows.remove(&key).unwrap();
+            while flow.oldest_pending().is_some_and(|since| now.saturating_sub(since) > timeout) {
+                self.skip_hole(key, &mut flow, &mut delivered);
+            }
+            if flow.fin.is_some_and(|fin| fin <= flow.next) && flow.pending.is_empty() {
+                close(key, flow.next, &mut delivered, None);
+            } else {
+                self.flows.insert(key, flow);
+            }
+        }
+        delivered
+    }
+
+    /// End of capture: everything still buffered is delivered with its gaps
+    pub fn finish(&mut self) -> Vec<StreamSegment<T>> {
+        let mut delivered = Vec::new();
+        let flows: Vec<(FlowKey, FlowState<T>)> = self.flows.drain().collect();
+        for (key, mut flow) in flows {
+            self.flush(key, &mut flow, &mut delivered);
+        }
+        delivered
+    }
+
+    pub fn stats(&self) -> &TcpStats {
+        &self.stats
+    }
+
+    pub fn flow_len(&self) -> usize {
+        self.flows.len()
+    }
+
+    fn deliver(&mut self, key: FlowKey, flow: &mut FlowState<T>, gap: u64, data: Vec<u8>, tag: T, delivered: &mut Vec<StreamSegment<T>>) {
+        self.stats.bytes += data.len() as u64;
+        let offset = flow.next;
+        flow.next += data.len() as u64;
+        delivered.push(StreamSegment {
+            flow: key,
+            offset,
+            gap,
+            data,
+            tag,
+            fin: false,
+        });
+    }
+
+    /// Delivers the buffered segments that became contiguous, the first one after `gap` missing bytes
+    fn drain(&mut self, key: FlowKey, flow: &mut FlowState<T>, mut gap: u64, delivered: &mut Vec<StreamSegment<T>>) {
+        while let Some(entry) = flow.pending.first_entry() {
+            let offset = *entry.key();
+            if offset > flow.next {
+                break;
+            }
+            let segment = entry.remove();
+            flow.pending_bytes -= segment.data.len();
+            let skip = (flow.next - offset) as usize;
+            if skip >= segment.data.len() {
+                self.stats.retransmitted += 1;
+                continue;
+            }
+            self.deliver(key, flow, gap, segment.data[skip..].to_vec(), segment.tag, delivered);
+            gap = 0;
+        }
+    }
+
+    /// Gives up on the first hole
+    fn skip_hole(&mut self, key: FlowKey, flow: &mut FlowState<T>, delivered: &mut Vec<StreamSegment<T>>) {
+        let offset = match flow.pending.keys().next() {
+            Some(&offset) => offset,
+            None => return,
+        };
+        let gap = offset.saturating_sub(flow.next);
+        if gap > 0 {
+            self.stats.gaps += 1;
+            self.stats.gap_bytes += gap;
+            flow.next = offset;
+        }
+        self.drain(key, flow, gap, delivered);
+    }
+
+    fn flush(&mut self, key: FlowKey, flow: &mut FlowState<T>, delivered: &mut Vec<StreamSegment<T>>) {
+        while !flow.pending.is_empty() {
+            self.skip_hole(key, flow, delivered);
+        }
+    }
+}
+
+impl<T> Default for TcpReassembler<T> {
+    fn default() -> Self {
+        TcpReassembler::new(DEFAULT_GAP_TIMEOUT)
+    }
+}
+
+/// Marks the end of a flow on its last delivered segment, or on an empty one if a tag is at hand
+fn close<T>(key: FlowKey, offset: u64, delivered: &mut Vec<StreamSegment<T>>, tag: Option<T>) {
+    if let Some(tag) = tag {
+        delivered.push(StreamSegment {
+            flow: key,
+            offset,
+            gap: 0,
+            data: Vec::new(),
+            tag,
+            fin: true,
+        });
+    } else if let Some(last) = delivered.iter_mut().rev().find(|segment| segment.flow == key) {
+        last.fin = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+    use pnet::packet::tcp::TcpFlags;
+    use crate::packet::decoder::{TcpHeader, TransportProtocol};
+
+    fn segment(seq: u32, flags: u8, payload: &[u8]) -> DecodedPacket<'_> {
+        DecodedPacket {
+            src: IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+            dst: IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)),
+            protocol: TransportProtocol::Tcp,
+            src_port: 40000,
+            dst_port: 9000,
+            tcp: Some(TcpHeader { seq, flags }),
+            payload,
+        }
+    }
+
+    fn data<T>(segments: &[StreamSegment<T>]) -> Vec<u8> {
+        segments.iter().flat_map(|segment| segment.data.clone()).collect()
+    }
+
+    #[test]
+    fn test_reorder_and_retransmission() {
+        let mut reassembler = TcpReassembler::default();
+        // sequence numbers wrap right after the SYN
+        assert!(reassembler.push(0, &segment(u32::MAX - 1, TcpFlags::SYN, b""), 0).is_empty());
+        let first = reassembler.push(1, &segment(u32::MAX, TcpFlags::ACK, b"abc"), 1);
+        assert_eq!(data(&first), b"abc");
+
+        assert!(reassembler.push(2, &segment(5, TcpFlags::ACK, b"ghi"), 2).is_empty());
+        let filled = reassembler.push(3, &segment(2, TcpFlags::ACK, b"def"), 3);
+        assert_eq!(data(&filled), b"defghi");
+        assert_eq!(filled.iter().map(|segment| segment.tag).collect::<Vec<_>>(), vec![3, 2]);
+
+        // overlapping retransmission is trimmed, an exact one dropped
+        assert_eq!(data(&reassembler.push(4, &segment(7, TcpFlags::ACK, b"ijkl"), 4)), b"jkl");
+        assert!(reassembler.push(5, &segment(2, TcpFlags::ACK, b"def"), 5).is_empty());
+
+        let closed = reassembler.push(6, &segment(11, TcpFlags::FIN | TcpFlags::ACK, b""), 6);
+        assert!(closed.last().unwrap().fin);
+        assert_eq!(reassembler.flow_len(), 0);
+        assert_eq!(reassembler.stats(), &TcpStats {
+            flows: 1,
+            segments: 5,
+            bytes: 12,
+            retransmitted: 1,
+            out_of_order: 1,
+            gaps: 0,
+            gap_bytes: 0,
+        });
+    }
+
+    #[test]
+    fn test_gap_after_timeout() {
+        let mut reassembler = TcpReassembler::new(100);
+        // no SYN, the stream starts at the first segment seen
+        assert_eq!(data(&reassembler.push(0, &segment(1000, TcpFlags::ACK, b"abc"), ())), b"abc");
+        assert!(reassembler.push(10, &segment(1006, TcpFlags::ACK, b"ghi"), ()).is_empty());
+        assert!(reassembler.expire(110).is_empty());
+
+        let skipped = reassembler.expire(111);
+        assert_eq!(skipped.len(), 1);
+        assert_eq!((skipped[0].offset, skipped[0].gap), (6, 3));
+        assert_eq!(data(&reassembler.push(120, &segment(1009, TcpFlags::ACK, b"jkl"), ())), b"jkl");
+        // the late retransmission is dropped
+        assert!(reassembler.push(130, &segment(1003, TcpFlags::ACK, b"def"), ()).is_empty());
+        assert_eq!((reassembler.stats().gaps, reassembler.stats().gap_bytes), (1, 3));
+    }
+}
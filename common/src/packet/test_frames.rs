@@ -0,0 +1,78 @@
+use pcap::{Capture, Linktype, PacketHeader, Precision};
+
+/// 324-byte B606F with the End Keyword, blank past the instcode
+pub(crate) fn b6_message(distidx: u32) -> Vec<u8> {
+    let mut message = format!("B606F{:08}G1  KR4165N30007", distidx).into_bytes();
+    message.resize(323, b' ');
+    message.push(0xff);
+    message
+}
+
+pub(crate) fn udp_frame(payload: &[u8]) -> Vec<u8> {
+    udp_frame_to([233, 37, 54, 1], payload)
+}
+
+pub(crate) fn udp_frame_to(group: [u8; 4], payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0u8; 12];
+    frame.extend_from_slice(&[0x08, 0x00]);
+    let total_len = (20 + 8 + payload.len()) as u16;
+    frame.extend_from_slice(&[0x45, 0x00]);
+    frame.extend_from_slice(&total_len.to_be_bytes());
+    frame.extend_from_slice(&[0, 0, 0, 0, 64, 17, 0, 0]);
+    frame.extend_from_slice(&[10, 0, 0, 1]);
+    frame.extend_from_slice(&group);
+    frame.extend_from_slice(&20000u16.to_be_bytes());
+    frame.extend_from_slice(&20001u16.to_be_bytes());
+    frame.extend_from_slice(&((8 + payload.len()) as u16).to_be_bytes());
+    frame.extend_from_slice(&[0, 0]);
+    frame.extend_from_slice(payload);
+    frame
+}
+
+pub(crate) fn tcp_frame(seq: u32, flags: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0u8; 12];
+    frame.extend_from_slice(&[0x08, 0x00]);
+    let total_len = (20 + 20 + payload.len()) as u16;
+    frame.extend_from_slice(&[0x45, 0x00]);
+    frame.extend_from_slice(&total_len.to_be_bytes());
+    frame.extend_from_slice(&[0, 0, 0, 0, 64, 6, 0, 0]);
+    frame.extend_from_slice(&[10, 0, 0, 1]);
+    frame.extend_from_slice(&[10, 0, 0, 2]);
+    frame.extend_from_slice(&9000u16.to_be_bytes());
+    frame.extend_from_slice(&40000u16.to_be_bytes());
+    frame.extend_from_slice(&seq.to_be_bytes());
+    frame.extend_from_slice(&[0, 0, 0, 0, 0x50, flags, 0xff, 0xff, 0, 0, 0, 0]);
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// one IPv4 fragment of a UDP datagram, `offset` in bytes (a multiple of 8)
+pub(crate) fn ipv4_fragment(id: u16, offset: usize, more_fragments: bool, data: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0u8; 12];
+    frame.extend_from_slice(&[0x08, 0x00]);
+    frame.extend_from_slice(&[0x45, 0x00]);
+    frame.extend_from_slice(&((20 + data.len()) as u16).to_be_bytes());
+    frame.extend_from_slice(&id.to_be_bytes());
+    let flags_offset = ((more_fragments as u16) << 13) | (offset / 8) as u16;
+    frame.extend_from_slice(&flags_offset.to_be_bytes());
+    frame.extend_from_slice(&[64, 17, 0, 0]);
+    frame.extend_from_slice(&[10, 0, 0, 1]);
+    frame.extend_from_slice(&[233, 37, 54, 1]);
+    frame.extend_from_slice(data);
+    frame
+}
+
+pub(crate) fn write_pcap(path: &std::path::Path, frames: &[(i64, i64, Vec<u8>)]) -> anyhow::Result<()> {
+    let cap = Capture::dead_with_precision(Linktype::ETHERNET, Precision::Nano)?;
+    let mut savefile = cap.savefile(path)?;
+    for (sec, usec, frame) in frames {
+        let header = PacketHeader {
+            ts: libc::timeval { tv_sec: *sec, tv_usec: *usec },
+            caplen: frame.len() as u32,
+            len: frame.len() as u32,
+        };
+        savefile.write(&pcap::Packet::new(&header, frame));
+    }
+    savefile.flush()?;
+    Ok(())
+}
@@ -0,0 +1,136 @@
+use serde::{Deserialize, Serialize};
+use crate::UnixNano;
+
+/// Korea Standard Time is UTC+9 all year round
+pub const KST_OFFSET_SECS: i64 = 9 * 3600;
+
+const NANOS_PER_SEC: i64 = 1_000_000_000;
+
+/// [start, end) in UnixNano, either side may be open
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimeWindow {
+    pub start: Option<UnixNano>,
+    pub end: Option<UnixNano>,
+}
+
+impl TimeWindow {
+    pub fn new(start: Option<UnixNano>, end: Option<UnixNano>) -> Self {
+        TimeWindow { start, end }
+    }
+
+    /// Wall-clock times in KST on `date`, e.g., `TimeWindow::kst(20240927, "08:45", "15:45")`
+    /// for the derivatives session
+    /// # Arguments
+    /// * `date` - yyyymmdd
+    /// * `start`, `end` - HH:MM, HH:MM:SS or HH:MM:SS.fraction
+    pub fn kst(date: i32, start: &str, end: &str) -> Result<Self, String> {
+        Ok(TimeWindow {
+            start: Some(kst_to_unix_nano(date, start)?),
+            end: Some(kst_to_unix_nano(date, end)?),
+        })
+    }
+
+    pub fn contains(&self, timestamp: UnixNano) -> bool {
+        self.start.is_none_or(|start| timestamp >= start) && self.end.is_none_or(|end| timestamp < end)
+    }
+}
+
+/// KST wall-clock time on `date` (yyyymmdd) => UnixNano.
+/// Times before the epoch or past `UnixNano::MAX` (year 2554) are errors.
+pub fn kst_to_unix_nano(date: i32, time: &str) -> Result<UnixNano, String> {
+    let (year, month, day) = (date / 10000, (date % 10000) / 100, date % 100);
+    if !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) || year < 1970 {
+        return Err(format!("Invalid date: {}", date));
+    }
+    let days = days_from_civil(year as i64, month as i64, day as i64) as i128;
+    let nanos = days * 86400 * NANOS_PER_SEC as i128 + time_of_day(time)? as i128
+        - (KST_OFFSET_SECS * NANOS_PER_SEC) as i128;
+    UnixNano::try_from(nanos).map_err(|_| format!("Out of the UnixNano range: {} {}", date, time))
+}
+
+/// HH:MM, HH:MM:SS or HH:MM:SS.fraction => ns since midnight
+pub fn time_of_day(time: &str) -> Result<UnixNano, String> {
+    let invalid = || format!("Invalid time: {}", time);
+
+    let (clock, fraction) = match time.split_once('.') {
+        Some((clock, fraction)) => (clock, Some(fraction)),
+        None => (time, None),
+    };
+    let parts: Vec<i64> = clock
+        .split(':')
+        .map(|part| part.parse::<i64>().map_err(|_| invalid()))
+        .collect::<Result<_, _>>()?;
+    let (hour, minute, second) = match parts[..] {
+        [hour, minute] => (hour, minute, 0),
+        [hour, minute, second] => (hour, minute, second),
+        _ => return Err(invalid()),
+    };
+    if !(0..24).contains(&hour) || !(0..60).contains(&minute) || !(0..60).contains(&second) {
+        return Err(invalid());
+    }
+    let nanos = match fraction {
+        Some(fraction) if fraction.len() <= 9 && fraction.bytes().all(|b| b.is_ascii_digit()) && !fraction.is_empty() => {
+            fraction.parse::<i64>().map_err(|_| invalid())? * 10i64.pow(9 - fraction.len() as u32)
+        },
+        Some(_) => return Err(invalid()),
+        None => 0,
+    };
+    Ok(((hour * 3600 + minute * 60 + second) * NANOS_PER_SEC + nanos) as UnixNano)
+}
+
+/// UnixNano => ns since midnight KST
+pub fn kst_time_of_day(timestamp: UnixNano) -> UnixNano {
+    ((timestamp as u128 + (KST_OFFSET_SECS * NANOS_PER_SEC) as u128) % (86400 * NANOS_PER_SEC) as u128) as UnixNano
+}
+
+fn days_in_month(year: i32, month: i32) -> i32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kst_to_unix_nano() {
+        // 2024-09-27 00:00:00 UTC
+        assert_eq!(kst_to_unix_nano(20240927, "09:00"), Ok(1_727_395_200_000_000_000));
+        assert_eq!(kst_to_unix_nano(20240927, "08:45:30.5"), Ok(1_727_394_330_500_000_000));
+        // before 09:00 KST is the previous UTC day
+        assert_eq!(kst_to_unix_nano(20240301, "00:00"), Ok(1_709_218_800_000_000_000));
+        assert!(kst_to_unix_nano(20240927, "24:00").is_err());
+        assert!(kst_to_unix_nano(20240927, "9").is_err());
+        assert!(kst_to_unix_nano(20241327, "09:00").is_err());
+        assert!(kst_to_unix_nano(20240231, "09:00").is_err());
+        assert!(kst_to_unix_nano(20230229, "09:00").is_err());
+        assert!(kst_to_unix_nano(20240229, "09:00").is_ok());
+        // out of the UnixNano range
+        assert_eq!(kst_to_unix_nano(19700101, "09:00"), Ok(0));
+        assert!(kst_to_unix_nano(19700101, "08:59:59.999999999").is_err());
+        assert!(kst_to_unix_nano(25540101, "09:00").is_ok());
+        assert!(kst_to_unix_nano(30000101, "09:00").is_err());
+        assert!(kst_to_unix_nano(99991231, "23:59").is_err());
+
+        let window = TimeWindow::kst(20240927, "08:45", "15:45").unwrap();
+        assert!(!window.contains(1_727_394_299_999_999_999));
+        assert!(window.contains(1_727_394_300_000_000_000));
+        assert!(!window.contains(window.end.unwrap()));
+        assert_eq!(kst_time_of_day(window.start.unwrap()), time_of_day("08:45").unwrap());
+        assert!(kst_time_of_day(UnixNano::MAX) < 86400 * NANOS_PER_SEC as UnixNano);
+    }
+}
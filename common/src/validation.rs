@@ -0,0 +1,193 @@
+use std::fmt;
+use serde::{Deserialize, Serialize};
+use crate::layout::{Layout, LayoutRegistry};
+use crate::mongodb_collection::krx_msg::trcode::TrCode;
+use crate::packet::framing::{MessageLengths, END_KEYWORD};
+
+const TRCODE_LEN: usize = 5;
+const END_KEYWORD_FIELD: &str = "End Keyword";
+
+/// One check a message failed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FailedCheck {
+    /// neither in the product table nor in the registry
+    UnknownTrcode,
+    /// not the spec length of the trcode
+    Length { expected: usize, found: usize },
+    /// the last byte is not 0xFF
+    MissingEndKeyword,
+    /// an Int or Double field holding something other than digits (with a sign and a decimal point), or blank
+    NotNumeric { field: String },
+    /// a String field holding control characters or 0xFF
+    NotText { field: String },
+}
+
+impl fmt::Display for FailedCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FailedCheck::UnknownTrcode => write!(f, "Unknown trcode"),
+            FailedCheck::Length { expected, found } => write!(f, "Message length {} (expected {})", found, expected),
+            FailedCheck::MissingEndKeyword => write!(f, "Missing End Keyword"),
+            FailedCheck::NotNumeric { field } => write!(f, "{} is not numeric", field),
+            FailedCheck::NotText { field } => write!(f, "{} is not text", field),
+        }
+    }
+}
+
+/// Every check a message failed, see `Validator::validate`
+/// # Arguments
+/// * `trcode` - the first 5 bytes of the message (lossy)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ValidationError {
+    pub trcode: String,
+    pub failures: Vec<FailedCheck>,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: ", self.trcode)?;
+        for (i, failure) in self.failures.iter().enumerate() {
+            if i > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{}", failure)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+/// Checks whole messages (End Keyword included) against the specs: the trcode is known, the message
+/// has the spec length and ends with 0xFF, and with a layout for the trcode, Int/Double fields are
+/// numeric and String fields are text (ASCII or EUC-KR).
+/// Lengths come from `KRX_MESSAGE_LENGTHS` and the registry, see `LayoutRegistry::message_lengths`.
+#[derive(Debug, Clone, Default)]
+pub struct Validator {
+    registry: LayoutRegistry,
+    lengths: MessageLengths,
+}
+
+impl Validator {
+    pub fn new(registry: LayoutRegistry) -> Self {
+        Validator { lengths: registry.message_lengths(), registry }
+    }
+
+    pub fn validate(&self, message: &[u8]) -> Result<(), ValidationError> {
+        let mut failures = Vec::new();
+        let trcode = message.get(..TRCODE_LEN);
+        let known = trcode.is_some_and(|trcode| {
+            self.registry.get(trcode).is_some()
+                || self.lengths.get(trcode).is_some()
+                || TrCode::from_bytes(trcode).is_some_and(|trcode| trcode.is_known_product())
+        });
+        if !known {
+            failures.push(FailedCheck::UnknownTrcode);
+        }
+        if let Some(expected) = trcode.and_then(|trcode| self.lengths.get(trcode)) {
+            if expected != message.len() {
+                failures.push(FailedCheck::Length { expected, found: message.len() });
+            }
+        }
+        if message.last() != Some(&END_KEYWORD) {
+            failures.push(FailedCheck::MissingEndKeyword);
+        }
+        if let Some(layout) = self.registry.layout_of(message) {
+            check_fields(layout, message, &mut failures);
+        }
+
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            let trcode = String::from_utf8_lossy(&message[..message.len().min(TRCODE_LEN)]).into_owned();
+            Err(ValidationError { trcode, failures })
+        }
+    }
+}
+
+/// Fields past the end of a short message are left to the length check
+fn check_fields(layout: &Layout, message: &[u8], failures: &mut Vec<FailedCheck>) {
+    for field in layout.fields().iter().filter(|field| field.item_name != END_KEYWORD_FIELD) {
+        let Some(bytes) = message.get(field.range()) else {
+            continue;
+        };
+        let failure = match field.data_type.as_str() {
+            "Int" | "Double" if !is_numeric(bytes, field.data_type == "Double") => {
+                FailedCheck::NotNumeric { field: field.item_name.clone() }
+            },
+            "Int" | "Double" => continue,
+            _ if !is_text(bytes) => FailedCheck::NotText { field: field.item_name.clone() },
+            _ => continue,
+        };
+        failures.push(failure);
+    }
+}
+
+/// Zero padded digits with an optional leading sign (and one decimal point for Double), or blank
+fn is_numeric(bytes: &[u8], decimal: bool) -> bool {
+    let trimmed = bytes.trim_ascii();
+    if trimmed.is_empty() {
+        return true;
+    }
+    let digits = trimmed.strip_prefix(b"-").or_else(|| trimmed.strip_prefix(b"+")).unwrap_or(trimmed);
+    let points = digits.iter().filter(|&&b| b == b'.').count();
+    !digits.is_empty()
+        && digits.iter().all(|&b| b.is_ascii_digit() || b == b'.')
+        && (points == 0 || (decimal && points == 1 && digits.len() > 1))
+}
+
+/// Printable ASCII, or EUC-KR bytes (0x80..0xFE)
+fn is_text(bytes: &[u8]) -> bool {
+    bytes.iter().all(|&b| (b' '..0x7f).contains(&b) || (0x80..END_KEYWORD).contains(&b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::PayloadField;
+
+    fn field(item_name: &str, data_type: &str, start: i32, length: i32) -> PayloadField {
+        PayloadField {
+            korean_name: String::new(),
+            item_name: item_name.to_string(),
+            sub_section: "0".to_string(),
+            data_type: data_type.to_string(),
+            length,
+            cumulative_length: start + length,
+            start_point: start,
+        }
+    }
+
+    #[test]
+    fn test_validator() {
+        let registry = LayoutRegistry::new().with_layout("B606F", vec![
+            field("Data Category", "String", 0, 2),
+            field("Information Category", "String", 2, 3),
+            field("Message sequence number", "Int", 5, 8),
+            field("Ask Level 1 price", "Double", 13, 9),
+            field("ISIN Code", "String", 22, 12),
+            field("End Keyword", "String", 34, 1),
+        ]);
+        let validator = Validator::new(registry);
+        assert_eq!(validator.validate(b"B606F00000042000105.25KR4165N30007\xff"), Ok(()));
+        // blank numbers, and A301K is known from the product table without a layout
+        assert_eq!(validator.validate(b"B606F        -00105.25KR4165N30007\xff"), Ok(()));
+        assert_eq!(validator.validate(b"A301K00000002\xff"), Ok(()));
+
+        let error = validator.validate(b"B606F0000004200010.2.5KR4165N\x013007\xff").unwrap_err();
+        assert_eq!(error.failures, vec![
+            FailedCheck::NotNumeric { field: "Ask Level 1 price".to_string() },
+            FailedCheck::NotText { field: "ISIN Code".to_string() },
+        ]);
+        assert_eq!(error.to_string(), "B606F: Ask Level 1 price is not numeric; ISIN Code is not text");
+
+        let error = validator.validate(b"B606F0000004X000105.25KR41").unwrap_err();
+        assert_eq!(error.failures, vec![
+            FailedCheck::Length { expected: 35, found: 26 },
+            FailedCheck::MissingEndKeyword,
+            FailedCheck::NotNumeric { field: "Message sequence number".to_string() },
+        ]);
+        assert_eq!(validator.validate(b"J907700000002\xff").unwrap_err().failures, vec![FailedCheck::UnknownTrcode]);
+        assert_eq!(validator.validate(b"B6").unwrap_err().failures, vec![FailedCheck::UnknownTrcode, FailedCheck::MissingEndKeyword]);
+    }
+}
@@ -0,0 +1,115 @@
+// Generates one struct per KRX spec CSV into $OUT_DIR/messages.rs, see src/messages/mod.rs.
+// The specs are `spec::KRX_SPECS` (re-exported as `common::layout::KRX_SPECS`), read with the same
+// `PayloadField` parser as the runtime layouts. Only the `spec` crate is a build-dependency.
+use std::collections::HashSet;
+use std::fmt::Write as _;
+use std::io;
+use std::path::Path;
+use spec::{PayloadField, KRX_SPECS};
+
+const END_KEYWORD: &str = "End Keyword";
+
+/// a `PayloadField` with its offsets as indices
+struct Field {
+    korean_name: String,
+    item_name: String,
+    data_type: String,
+    length: usize,
+    start: usize,
+    end: usize,
+}
+
+impl From<PayloadField> for Field {
+    fn from(field: PayloadField) -> Self {
+        Field {
+            korean_name: field.korean_name,
+            item_name: field.item_name,
+            data_type: field.data_type,
+            length: field.length.max(0) as usize,
+            start: field.start_point.max(0) as usize,
+            end: field.cumulative_length.max(0) as usize,
+        }
+    }
+}
+
+fn main() -> io::Result<()> {
+    println!("cargo:rerun-if-changed=build.rs");
+    let mut code = String::new();
+    for (trcode, spec) in KRX_SPECS {
+        let fields = load_spec(spec)?;
+        generate(&mut code, trcode, &fields).map_err(io::Error::other)?;
+    }
+    let out = Path::new(&std::env::var("OUT_DIR").expect("OUT_DIR is set by cargo")).join("messages.rs");
+    std::fs::write(out, code)
+}
+
+fn load_spec(spec: &[u8]) -> io::Result<Vec<Field>> {
+    Ok(PayloadField::from_spec_bytes(spec)?.into_iter().map(Field::from).collect())
+}
+
+/// "Ask Level 1_Order Counts" => ask_level_1_order_counts
+fn snake_case(item_name: &str) -> String {
+    let mut name = String::new();
+    for c in item_name.chars() {
+        if c.is_ascii_alphanumeric() {
+            name.push(c.to_ascii_lowercase());
+        } else if !name.is_empty() && !name.ends_with('_') {
+            name.push('_');
+        }
+    }
+    let mut name = name.trim_end_matches('_').to_string();
+    if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) {
+        name.insert_str(0, "field_");
+    }
+    if matches!(name.as_str(), "type" | "match" | "ref" | "in" | "as" | "mod" | "use" | "loop" | "move" | "self") {
+        name.push('_');
+    }
+    name
+}
+
+fn generate(code: &mut String, trcode: &str, fields: &[Field]) -> std::fmt::Result {
+    let length = fields.iter().map(|field| field.end).max().unwrap_or(0);
+    let mut names = HashSet::new();
+    let mut members = Vec::new();
+    for field in fields.iter().filter(|field| field.item_name != END_KEYWORD) {
+        let mut name = snake_case(&field.item_name);
+        if !names.insert(name.clone()) {
+            name = format!("{}_{}", name, field.start);
+            names.insert(name.clone());
+        }
+        let (ty, decoder) = match field.data_type.as_str() {
+            "Int" if field.length > 18 => ("i128", "decode_number"),
+            "Int" => ("i64", "decode_number"),
+            "Double" => ("f64", "decode_number"),
+            _ => ("String", "decode_text"),
+        };
+        members.push((name, ty, decoder, field));
+    }
+
+    writeln!(code, "/// {}, generated from its spec in `common::layout::KRX_SPECS` ({} bytes including the End Keyword)", trcode, length)?;
+    writeln!(code, "#[allow(clippy::upper_case_acronyms)]")?;
+    writeln!(code, "#[derive(Debug, Clone, PartialEq)]")?;
+    writeln!(code, "pub struct {} {{", trcode)?;
+    for (name, ty, _, field) in members.iter() {
+        writeln!(code, "    /// {} ({}, {}..{})", field.korean_name, field.item_name, field.start, field.end)?;
+        writeln!(code, "    pub {}: {},", name, ty)?;
+    }
+    writeln!(code, "}}\n")?;
+
+    writeln!(code, "impl {} {{", trcode)?;
+    writeln!(code, "    pub const TRCODE: &'static str = {:?};", trcode)?;
+    writeln!(code, "    pub const LENGTH: usize = {};\n", length)?;
+    writeln!(code, "    pub fn decode(payload: &[u8]) -> Result<Self, DecodeError> {{")?;
+    writeln!(code, "        check(payload, Self::TRCODE, Self::LENGTH)?;")?;
+    writeln!(code, "        Ok({} {{", trcode)?;
+    for (name, _, decoder, field) in members.iter() {
+        if *decoder == "decode_text" {
+            writeln!(code, "            {}: {}(&payload[{}..{}]),", name, decoder, field.start, field.end)?;
+        } else {
+            writeln!(code, "            {}: {}(&payload[{}..{}], {:?})?,", name, decoder, field.start, field.end, field.item_name)?;
+        }
+    }
+    writeln!(code, "        }})")?;
+    writeln!(code, "    }}")?;
+    writeln!(code, "}}\n")
+}
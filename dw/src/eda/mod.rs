@@ -0,0 +1,338 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use common::packet::time_window::kst_time_of_day;
+use common::layout::LayoutRegistry;
+use common::{KrxMsg, UnixNano};
+use crate::payload_field::PayloadField;
+
+const NANOS_PER_SEC: UnixNano = 1_000_000_000;
+pub const SECOND: UnixNano = NANOS_PER_SEC;
+pub const MINUTE: UnixNano = 60 * NANOS_PER_SEC;
+
+/// Messages received in [start, start + bucket width)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RateBucket {
+    pub start: UnixNano,
+    pub count: u64,
+}
+
+/// Message counts per time bucket. Only buckets with a message are listed, so that a stray timestamp far
+/// from the rest does not blow up the series; a bucket missing between two listed ones had none.
+/// # Arguments
+/// * `width` - ns, `SECOND` or `MINUTE`
+/// * `peak` - the busiest bucket, the earliest one on a tie
+/// * `mean` - messages per bucket over the span from the first to the last listed bucket, empty ones included
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RateSeries {
+    pub width: UnixNano,
+    pub buckets: Vec<RateBucket>,
+    pub peak: Option<RateBucket>,
+    pub mean: f64,
+}
+
+impl RateSeries {
+    fn from_counts(width: UnixNano, counts: &BTreeMap<UnixNano, u64>) -> Self {
+        let (first, last) = match (counts.keys().next(), counts.keys().next_back()) {
+            (Some(&first), Some(&last)) => (first, last),
+            _ => return RateSeries { width, ..RateSeries::default() },
+        };
+        let buckets: Vec<RateBucket> = counts
+            .iter()
+            .map(|(&index, &count)| RateBucket { start: index * width, count })
+            .collect();
+        let peak = buckets.iter().copied().reduce(|peak, bucket| if bucket.count > peak.count { bucket } else { peak });
+        let span = (last - first + 1) as f64;
+        let mean = buckets.iter().map(|bucket| bucket.count).sum::<u64>() as f64 / span;
+        RateSeries { width, buckets, peak, mean }
+    }
+}
+
+/// How often a field of the spec carries no data
+/// # Arguments
+/// * `messages` - messages of the trcode long enough to hold the field
+/// * `blank` - of those, the field is all spaces (or NUL)
+/// * `short` - messages of the trcode ending before the field
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct FieldFill {
+    pub item_name: String,
+    pub korean_name: String,
+    pub messages: u64,
+    pub blank: u64,
+    pub short: u64,
+}
+
+impl FieldFill {
+    /// Share of `messages` where the field is blank, 0 if there were none
+    pub fn blank_ratio(&self) -> f64 {
+        if self.messages == 0 {
+            0.0
+        } else {
+            self.blank as f64 / self.messages as f64
+        }
+    }
+}
+
+/// Counts, rates, payload lengths and field fill of a `KrxMsg` stream.
+/// Times are `packet_timestamp` (or `timestamp` if it is missing); messages without either are
+/// counted but left out of the rates.
+/// # Arguments
+/// * `trcodes`, `instcodes` - messages per code, blank instcodes left out
+/// * `payload_lengths` - per trcode, messages per payload length
+/// * `field_fill` - per trcode with a spec, its fields in spec order
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct EdaReport {
+    pub messages: u64,
+    pub without_timestamp: u64,
+    pub first_timestamp: Option<UnixNano>,
+    pub last_timestamp: Option<UnixNano>,
+    pub trcodes: BTreeMap<String, u64>,
+    pub instcodes: BTreeMap<String, u64>,
+    pub per_second: RateSeries,
+    pub per_minute: RateSeries,
+    pub payload_lengths: BTreeMap<String, BTreeMap<usize, u64>>,
+    pub field_fill: BTreeMap<String, Vec<FieldFill>>,
+}
+
+impl EdaReport {
+    pub fn write_json<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(writer, self).map_err(io::Error::other)
+    }
+
+    /// One file per table in `dir` (created if needed): trcodes.csv, instcodes.csv, per_second.csv,
+    /// per_minute.csv, payload_lengths.csv and field_fill.csv.
+    /// Rate rows carry the bucket start both as UnixNano and as KST time of day (HH:MM:SS) for charting.
+    pub fn write_csv<P: AsRef<Path>>(&self, dir: P) -> io::Result<()> {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir)?;
+
+        let mut writer = csv::Writer::from_path(dir.join("trcodes.csv"))?;
+        writer.write_record(["trcode", "messages"])?;
+        for (trcode, count) in self.trcodes.iter() {
+            writer.write_record([trcode.as_str(), &count.to_string()])?;
+        }
+        writer.flush()?;
+
+        let mut writer = csv::Writer::from_path(dir.join("instcodes.csv"))?;
+        writer.write_record(["instcode", "messages"])?;
+        for (instcode, count) in self.instcodes.iter() {
+            writer.write_record([instcode.as_str(), &count.to_string()])?;
+        }
+        writer.flush()?;
+
+        for (name, series) in [("per_second.csv", &self.per_second), ("per_minute.csv", &self.per_minute)] {
+            let mut writer = csv::Writer::from_path(dir.join(name))?;
+            writer.write_record(["start", "kst", "messages"])?;
+            for bucket in series.buckets.iter() {
+                writer.write_record([bucket.start.to_string(), kst_clock(bucket.start), bucket.count.to_string()])?;
+            }
+            writer.flush()?;
+        }
+
+        let mut writer = csv::Writer::from_path(dir.join("payload_lengths.csv"))?;
+        writer.write_record(["trcode", "length", "messages"])?;
+        for (trcode, lengths) in self.payload_lengths.iter() {
+            for (length, count) in lengths.iter() {
+                writer.write_record([trcode.as_str(), &length.to_string(), &count.to_string()])?;
+            }
+        }
+        writer.flush()?;
+
+        let mut writer = csv::Writer::from_path(dir.join("field_fill.csv"))?;
+        writer.write_record(["trcode", "item_name", "korean_name", "messages", "blank", "short", "blank_ratio"])?;
+        for (trcode, fields) in self.field_fill.iter() {
+            for fill in fields.iter() {
+                writer.write_record([
+                    trcode.as_str(),
+                    &fill.item_name,
+                    &fill.korean_name,
+                    &fill.messages.to_string(),
+                    &fill.blank.to_string(),
+                    &fill.short.to_string(),
+                    &format!("{:.6}", fill.blank_ratio()),
+                ])?;
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Builds an `EdaReport` one message at a time. Field fill is computed for the trcodes given a spec.
+#[derive(Debug, Default)]
+pub struct EdaCollector {
+    fields: HashMap<String, Vec<PayloadField>>,
+    report: EdaReport,
+    // bucket index (timestamp / width) => messages
+    seconds: BTreeMap<UnixNano, u64>,
+    minutes: BTreeMap<UnixNano, u64>,
+}
+
+impl EdaCollector {
+    pub fn new() -> Self {
+        EdaCollector::default()
+    }
+
+    /// Spec of `trcode` (e.g., from `PayloadField::load_from_csv`) for the field fill table
+    pub fn with_fields(mut self, trcode: &str, fields: Vec<PayloadField>) -> Self {
+        let fill = fields
+            .iter()
+            .map(|field| FieldFill {
+                item_name: field.item_name.clone(),
+                korean_name: field.korean_name.clone(),
+                ..FieldFill::default()
+            })
+            .collect();
+        self.report.field_fill.insert(trcode.to_string(), fill);
+        self.fields.insert(trcode.to_string(), fields);
+        self
+    }
+
+    /// `with_fields` for every trcode in the registry
+    pub fn with_layouts(self, registry: &LayoutRegistry) -> Self {
+        registry
+            .iter()
+            .fold(self, |collector, (trcode, layout)| collector.with_fields(trcode, layout.fields().to_vec()))
+    }
+
+    pub fn push(&mut self, msg: &KrxMsg) {
+        let trcode = msg.trcode().unwrap_or_default();
+        let report = &mut self.report;
+        report.messages += 1;
+        *report.trcodes.entry(trcode.to_string()).or_default() += 1;
+        // a blank ISIN Code is no instcode, as in `Filter`
+        if let Some(instcode) = msg.instcode.as_deref().map(str::trim).filter(|instcode| !instcode.is_empty()) {
+            *report.instcodes.entry(instcode.to_string()).or_default() += 1;
+        }
+        *report
+            .payload_lengths
+            .entry(trcode.to_string())
+            .or_default()
+            .entry(msg.payload.len())
+            .or_default() += 1;
+
+        match msg.packet_timestamp.or(msg.timestamp) {
+            Some(timestamp) => {
+                report.first_timestamp = Some(report.first_timestamp.map_or(timestamp, |first| first.min(timestamp)));
+                report.last_timestamp = Some(report.last_timestamp.map_or(timestamp, |last| last.max(timestamp)));
+                *self.seconds.entry(timestamp / SECOND).or_default() += 1;
+                *self.minutes.entry(timestamp / MINUTE).or_default() += 1;
+            },
+            None => report.without_timestamp += 1,
+        }
+
+        if let (Some(fields), Some(fill)) = (self.fields.get(trcode), report.field_fill.get_mut(trcode)) {
+            for (field, fill) in fields.iter().zip(fill.iter_mut()) {
+                match msg.payload.get(field.start_point as usize..field.cumulative_length as usize) {
+                    Some(data) => {
+                        fill.messages += 1;
+                        if data.iter().all(|&b| b == b' ' || b == 0) {
+                            fill.blank += 1;
+                        }
+                    },
+                    None => fill.short += 1,
+                }
+            }
+        }
+    }
+
+    pub fn report(&self) -> EdaReport {
+        let mut report = self.report.clone();
+        report.per_second = RateSeries::from_counts(SECOND, &self.seconds);
+        report.per_minute = RateSeries::from_counts(MINUTE, &self.minutes);
+        report
+    }
+
+    /// Runs a whole stream (e.g., a `KrxMsgIter` or `KrxMsgJsonReader`) through the collector
+    pub fn collect<I>(mut self, krx_msgs: I) -> io::Result<EdaReport>
+    where
+        I: IntoIterator<Item = io::Result<KrxMsg>>,
+    {
+        for msg in krx_msgs {
+            self.push(&msg?);
+        }
+        Ok(self.report())
+    }
+}
+
+/// UnixNano => HH:MM:SS in KST
+fn kst_clock(timestamp: UnixNano) -> String {
+    let secs = kst_time_of_day(timestamp) / NANOS_PER_SEC;
+    format!("{:02}:{:02}:{:02}", secs / 3600, secs % 3600 / 60, secs % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::payload_field::LoadFromCsv;
+    use crate::feed_generator::b606f_payload;
+
+    // 2024-09-27 10:20:00 KST
+    const T0: UnixNano = 1_727_400_000_000_000_000;
+
+    #[test]
+    fn test_eda_report() -> anyhow::Result<()> {
+        let fields = PayloadField::load_from_csv("../spec/data/BF606F_new.7z")?;
+        let mut msgs = Vec::new();
+        // 3 messages in the first second, none in the second, 1 in the third, then 2 a minute later
+        for (i, offset) in [0, 300_000_000, 900_000_000, 2 * SECOND, 61 * SECOND, 61 * SECOND + 5].into_iter().enumerate() {
+            // the ISIN Code is left blank in every other message
+            let isin = if i % 2 == 0 { format!("KR416{}N30007", i % 4) } else { String::new() };
+            let payload = b606f_payload(&fields, i as i64, &isin, 100.0)?.build();
+            msgs.push(Ok(KrxMsg::new_from_payload(20240927, &payload, Some(T0 + offset), None).unwrap()));
+        }
+        msgs.push(Ok(KrxMsg::new_from_payload(20240927, b"A301K00000002\xff", None, None).unwrap()));
+
+        let report = EdaCollector::new().with_fields("B606F", fields).collect(msgs)?;
+        assert_eq!((report.messages, report.without_timestamp), (7, 1));
+        assert_eq!(report.trcodes, BTreeMap::from([("A301K".to_string(), 1), ("B606F".to_string(), 6)]));
+        // blank ISIN Codes are left out
+        assert_eq!(
+            report.instcodes,
+            BTreeMap::from([("KR4160N30007".to_string(), 2), ("KR4162N30007".to_string(), 1)])
+        );
+        assert_eq!(report.payload_lengths["B606F"], BTreeMap::from([(324, 6)]));
+        assert_eq!((report.first_timestamp, report.last_timestamp), (Some(T0), Some(T0 + 61 * SECOND + 5)));
+
+        let buckets: Vec<(UnixNano, u64)> =
+            report.per_second.buckets.iter().map(|bucket| (bucket.start - T0, bucket.count)).collect();
+        assert_eq!(buckets, vec![(0, 3), (2 * SECOND, 1), (61 * SECOND, 2)]);
+        assert_eq!(report.per_second.peak, Some(RateBucket { start: T0, count: 3 }));
+        assert!((report.per_second.mean - 6.0 / 62.0).abs() < 1e-12);
+        assert_eq!(report.per_minute.buckets.len(), 2);
+        assert_eq!(report.per_minute.peak.map(|peak| peak.count), Some(4));
+        assert!((report.per_minute.mean - 3.0).abs() < 1e-12);
+
+        let isin = report.field_fill["B606F"].iter().find(|fill| fill.item_name == "ISIN Code").unwrap();
+        assert_eq!((isin.messages, isin.blank), (6, 3));
+        assert!((isin.blank_ratio() - 0.5).abs() < 1e-12);
+        // numeric fields are zero filled, not blank
+        let price = report.field_fill["B606F"].iter().find(|fill| fill.item_name == "Ask Level 1 price").unwrap();
+        assert_eq!(price.blank, 0);
+
+        let dir = std::env::temp_dir().join("eda_report_test");
+        report.write_csv(&dir)?;
+        report.write_json(dir.join("report.json"))?;
+        let per_second = std::fs::read_to_string(dir.join("per_second.csv"))?;
+        assert_eq!(per_second.lines().nth(1), Some(format!("{},10:20:00,3", T0).as_str()));
+        let read: EdaReport = serde_json::from_reader(File::open(dir.join("report.json"))?)?;
+        assert_eq!(read, report);
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_stray_timestamp() {
+        // a zero timestamp next to 2024 data spans ~1.7e9 seconds, only the two buckets are kept
+        let mut collector = EdaCollector::new();
+        for timestamp in [0, T0] {
+            collector.push(&KrxMsg::new_from_payload(20240927, b"A301K00000002\xff", Some(timestamp), None).unwrap());
+        }
+        let report = collector.report();
+        assert_eq!(report.per_second.buckets, vec![RateBucket { start: 0, count: 1 }, RateBucket { start: T0, count: 1 }]);
+        assert!((report.per_second.mean - 2.0 / (T0 / SECOND + 1) as f64).abs() < 1e-18);
+    }
+}
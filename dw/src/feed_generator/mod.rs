@@ -0,0 +1,42 @@
+// The synthetic feed generator lives in common (so that common's tests can build their fixtures), kept here under its old path
+pub use common::feed_generator::*;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::payload_field::{LoadFromCsv, PayloadField};
+    use crate::payload_parser::{parse_data, ParsedValue};
+
+    #[test]
+    fn test_payload_round_trip() -> anyhow::Result<()> {
+        let fields = PayloadField::load_from_csv("../spec/data/BF606F_new.7z")?;
+        let payload = b606f_payload(&fields, 42, "KR4165N30007", 102.5)?
+            .set("Bid Level 1 price", FieldValue::Double(-3.25))?
+            .set("Ask Level 1 volume", FieldValue::Int(150))?
+            .build();
+        assert_eq!(payload.len(), 324);
+        assert_eq!(payload[323], 0xff);
+        assert_eq!(&payload[..29], b"B606F00000042    KR4165N30007");
+
+        let value = |item_name: &str| {
+            let field = fields.iter().find(|field| field.item_name == item_name).unwrap();
+            parse_data(&payload[field.start_point as usize..field.cumulative_length as usize], &field.data_type)
+        };
+        assert!(matches!(value("Ask Level 1 price"), ParsedValue::Double(v) if v == 102.5));
+        assert!(matches!(value("Bid Level 1 price"), ParsedValue::Double(v) if v == -3.25));
+        assert!(matches!(value("Ask Level 1 volume"), ParsedValue::Integer(150)));
+        assert!(matches!(value("Bid Level 1 volume"), ParsedValue::Integer(0)));
+
+        assert_eq!(
+            PayloadBuilder::new(&fields).set("Ask Level 1 volume", FieldValue::Int(1_000_000_000)).err(),
+            Some(GeneratorError::TooLong {
+                field: "Ask Level 1 volume".to_string(),
+                length: 9,
+                value: "Int(1000000000)".to_string(),
+            })
+        );
+        assert!(PayloadBuilder::new(&fields).set("ISIN Code", FieldValue::Int(1)).is_err());
+        assert!(PayloadBuilder::new(&fields).set("No such field", FieldValue::Int(1)).is_err());
+        Ok(())
+    }
+}
@@ -1,6 +1,9 @@
 pub mod payload_field;
 pub mod payload_parser;
 pub mod unique_json;
+pub mod feed_generator;
+pub mod eda;
+pub mod messages;
 
 // common 크레이트에서 data_types를 가져옵니다
 pub use common::types::{UnixNano, Real};
@@ -0,0 +1,93 @@
+// Typed KRX messages generated by build.rs from the specs in `spec::KRX_SPECS` (one struct per trcode, e.g., `B606F`),
+// so that fields are read by name instead of by their index in the spec.
+// A new spec is added to `KRX_SPECS` in the spec crate (spec/src/lib.rs, re-exported as `common::layout::KRX_SPECS`).
+use std::str::FromStr;
+use encoding_rs::EUC_KR;
+
+include!(concat!(env!("OUT_DIR"), "/messages.rs"));
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecodeError {
+    /// shorter than the spec
+    TooShort { trcode: &'static str, length: usize, expected: usize },
+    /// the payload starts with another trcode
+    WrongTrcode { expected: &'static str, found: String },
+    InvalidNumber { field: &'static str, text: String },
+}
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DecodeError::TooShort { trcode, length, expected } => {
+                write!(f, "{} needs {} bytes, got {}", trcode, expected, length)
+            },
+            DecodeError::WrongTrcode { expected, found } => write!(f, "Expected {}, found {}", expected, found),
+            DecodeError::InvalidNumber { field, text } => write!(f, "{} is not a number: {:?}", field, text),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+fn check(payload: &[u8], trcode: &'static str, length: usize) -> Result<(), DecodeError> {
+    if payload.len() < length {
+        return Err(DecodeError::TooShort { trcode, length: payload.len(), expected: length });
+    }
+    if !payload.starts_with(trcode.as_bytes()) {
+        return Err(DecodeError::WrongTrcode {
+            expected: trcode,
+            found: String::from_utf8_lossy(&payload[..trcode.len()]).into_owned(),
+        });
+    }
+    Ok(())
+}
+
+/// Int and Double fields. Blank is zero, as `payload_parser::parse_data` reads it
+fn decode_number<T: FromStr + Default>(bytes: &[u8], field: &'static str) -> Result<T, DecodeError> {
+    let invalid = || DecodeError::InvalidNumber { field, text: String::from_utf8_lossy(bytes).into_owned() };
+    let text = std::str::from_utf8(bytes).map_err(|_| invalid())?.trim();
+    if text.is_empty() {
+        return Ok(T::default());
+    }
+    text.parse().map_err(|_| invalid())
+}
+
+/// EUC-KR, trailing spaces trimmed
+fn decode_text(bytes: &[u8]) -> String {
+    let (text, _, _) = EUC_KR.decode(bytes);
+    text.trim_end().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::feed_generator::{b606f_payload, FieldValue};
+    use crate::payload_field::{LoadFromCsv, PayloadField};
+
+    #[test]
+    fn test_decode_b606f() -> anyhow::Result<()> {
+        let fields = PayloadField::load_from_csv("../spec/data/BF606F_new.7z")?;
+        let payload = b606f_payload(&fields, 42, "KR4165N30007", 105.25)?
+            .set("Bid Level 1 volume", FieldValue::Int(-12))?
+            .build();
+        assert_eq!(B606F::LENGTH, payload.len());
+
+        let msg = B606F::decode(&payload)?;
+        assert_eq!((msg.data_category.as_str(), msg.information_category.as_str()), ("B6", "06F"));
+        assert_eq!((msg.message_sequence_number, msg.isin_code.as_str()), (42, "KR4165N30007"));
+        assert_eq!((msg.ask_level_1_price, msg.bid_level_1_volume), (105.25, -12));
+        assert_eq!((msg.ask_level_1_order_counts, msg.estimated_trading_price), (0, 0.0));
+
+        assert!(matches!(B606F::decode(&payload[..100]), Err(DecodeError::TooShort { length: 100, .. })));
+        let mut other = payload.clone();
+        other[..5].copy_from_slice(b"A301S");
+        assert!(matches!(B606F::decode(&other), Err(DecodeError::WrongTrcode { .. })));
+        other[..5].copy_from_slice(b"B606F");
+        other[47..56].copy_from_slice(b"00010x.25");
+        assert_eq!(
+            B606F::decode(&other),
+            Err(DecodeError::InvalidNumber { field: "Ask Level 1 price", text: "00010x.25".to_string() })
+        );
+        Ok(())
+    }
+}
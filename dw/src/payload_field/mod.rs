@@ -1,93 +1,14 @@
 // Jay 
 // unused import -> run 'cargo check' and 'cargo clippy' before push
 //use std::error::Error;
-use std::fs::File;
-use std::io::Read;
-use encoding_rs::EUC_KR;
-use csv::ReaderBuilder;
+use common::layout::LayoutRegistry;
 
-#[derive(Debug)]
-pub struct PayloadField {
-   pub korean_name: String,
-   pub item_name: String,
-   pub sub_section: String,
-   pub data_type: String,
-   pub length: i32,
-   pub cumulative_length: i32,
-   pub start_point: i32,
-}
+/// The spec row type and its CSV loader live in `common::layout`, next to the `LayoutRegistry`
+pub use common::layout::{LoadFromCsv, PayloadField};
 
-// Jay: why not implment std::fmt::Display for PayloadField
-impl std::fmt::Display for PayloadField {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        writeln!(f, "PayloadFiled")?;
-        writeln!(f, "  Korean name: {}", self.korean_name)?;
-        writeln!(f, "  Item name: {}", self.item_name)?;
-        writeln!(f, "  Sub section: {}", self.sub_section)?;
-        writeln!(f, "  Data type: {}", self.data_type)?;
-        writeln!(f, "  Length: {}", self.length)?;
-        writeln!(f, "  Cumulative length: {}", self.cumulative_length)?;
-        writeln!(f, "  Start point: {}", self.start_point)
-    }
-}
-
-impl PayloadField {
-    // Jay
-    // Box<dyn Error> is not thread safe.
-    // Moreover, Box<dyn Trait> is slow. General practice is specify the error type in lib and use anyhow in the application
-    pub fn load_from_csv(file_path: &str) -> Result<Vec<PayloadField>, std::io::Error> {
-        // 파일을 바이트로 읽기
-        let mut file = File::open(file_path)?;
-        let mut bytes = Vec::new();
-        file.read_to_end(&mut bytes)?;
-
-        // EUC-KR에서 UTF-8로 변환
-        let (cow, _, _) = EUC_KR.decode(&bytes);
-
-        // CSV 파서 설정
-        let mut rdr = ReaderBuilder::new()
-            .has_headers(true)  // 헤더 처리 추가
-            .from_reader(cow.as_bytes());
-
-        let mut payload_fields = Vec::new();
-
-        for result in rdr.records() {
-            let record = result?;
-            // Jay
-            // run 'cargo clippy' before push
-            let length = record.get(4)
-                .map(|s| if s.trim().is_empty() { "0" } else { s.trim() })
-                .unwrap_or("0")
-                .parse::<i32>()
-                .unwrap_or(0);
-
-            let cumulative_length = record.get(5)
-                .map(|s| if s.trim().is_empty() { "0" } else { s.trim() })
-                .unwrap_or("0")
-                .parse::<i32>()
-                .unwrap_or(0);
-
-            let start_point = record.get(6)
-                .map(|s| if s.trim().is_empty() { "0" } else { s.trim() })
-                .unwrap_or("0")
-                .parse::<i32>()
-                .unwrap_or(0);
-
-            let field = PayloadField {
-                korean_name: record.get(0).unwrap_or("").to_string(),
-                item_name: record.get(1).unwrap_or("").to_string(),
-                sub_section: record.get(2).unwrap_or("").to_string(),
-                data_type: record.get(3).unwrap_or("").to_string(),
-                length,
-                cumulative_length,
-                start_point,
-            };
-
-            payload_fields.push(field);
-        }
-
-        Ok(payload_fields)
-    }
+/// Specs shipped with `common`, see `common::layout::KRX_SPECS`
+pub fn embedded_layouts() -> std::io::Result<LayoutRegistry> {
+    LayoutRegistry::krx()
 }
 
 #[cfg(test)]
@@ -123,4 +44,26 @@ mod tests {
         */
         Ok(())
    }
+
+   #[test]
+   fn test_load_from_7z() -> anyhow::Result<()> {
+        let fields = PayloadField::load_from_csv("../spec/data/BF606F_new.7z")?;
+        assert!(!fields.is_empty());
+        assert_eq!(fields[0].length, 2);
+
+        // End Keyword closes the B606F layout
+        let last = fields.iter().rev().find(|field| field.length > 0).unwrap();
+        assert_eq!(last.start_point + last.length, 324);
+        Ok(())
+   }
+
+   #[test]
+   fn test_embedded_layouts() -> anyhow::Result<()> {
+        let registry = embedded_layouts()?;
+        let layout = registry.get(b"B606F").unwrap();
+        assert_eq!(layout.fields().len(), PayloadField::load_from_csv("../spec/data/BF606F_new.7z")?.len());
+        assert_eq!((layout.distidx_range(), layout.instcode_range()), (Some(5..13), Some(17..29)));
+        assert_eq!(layout.message_length(), 324);
+        Ok(())
+   }
 }
\ No newline at end of file
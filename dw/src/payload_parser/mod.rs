@@ -3,6 +3,8 @@ use crate::payload_field::PayloadField;
 
 // common 크레이트에서 직접 가져옵니다
 use common::KrxMsg;
+use common::filter::{FieldSource, Value};
+use common::layout::{Layout, LayoutRegistry};
 
 
 
@@ -134,21 +136,75 @@ pub fn parse_json_db(krx_msg: &KrxMsg, fields: &[PayloadField], field_idx: usize
     Some(parse_data(data, &field.data_type))
 }
 
+/// Field `item_name` of the payload, parsed as the spec says
+pub fn parse_field(layout: &Layout, payload: &[u8], item_name: &str) -> Option<ParsedValue> {
+    let field = layout.field(item_name)?;
+    Some(parse_data(payload.get(field.range())?, &field.data_type))
+}
+
+/// Spec layouts decoding `field("...")` of a `common::filter::Filter` by item name,
+/// in the layout of the trcode the message starts with
+#[derive(Debug)]
+pub struct FieldLayout {
+    registry: LayoutRegistry,
+}
+
+impl FieldLayout {
+    /// A single layout
+    pub fn new(trcode: &str, fields: Vec<PayloadField>) -> Self {
+        FieldLayout { registry: LayoutRegistry::new().with_layout(trcode, fields) }
+    }
+
+    pub fn from_registry(registry: LayoutRegistry) -> Self {
+        FieldLayout { registry }
+    }
+}
+
+impl FieldSource for FieldLayout {
+    fn has_field(&self, name: &str) -> bool {
+        self.registry.iter().any(|(_, layout)| layout.field(name).is_some())
+    }
+
+    fn field(&self, payload: &[u8], name: &str) -> Option<Value> {
+        let layout = self.registry.layout_of(payload)?;
+        Some(match parse_field(layout, payload, name)? {
+            ParsedValue::Double(v) => Value::Double(v),
+            ParsedValue::Integer(v) => Value::Int(v as i64),
+            ParsedValue::Text(v) => Value::Text(v.trim().to_string()),
+        })
+    }
+}
+
 
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::payload_field::LoadFromCsv;
     use struson::reader::{JsonStreamReader, JsonReader};
     use common::KrxMsg;
     use pcap::Capture;
+    use crate::feed_generator::{b606f_payload, FeedGenerator};
     //use approx::assert_relative_eq;
 
+    /// B606F quotes with a rising Ask Level 1 price, one per millisecond
+    fn write_fixture(csv_path: &str, pcap_path: &std::path::Path, count: i64) -> anyhow::Result<()> {
+        let fields = PayloadField::load_from_csv(csv_path)?;
+        let mut generator = FeedGenerator::create(pcap_path)?;
+        for i in 0..count {
+            let payload = b606f_payload(&fields, i, "KR4165N30007", 100.0 + i as f64 * 0.05)?.build();
+            generator.write(1_727_400_000_000_000_000 + i as u64 * 1_000_000, &payload);
+        }
+        generator.finish()?;
+        Ok(())
+    }
+
     #[test]
     fn test_payload_parser() -> anyhow::Result<()> {
         let current_dir = std::env::current_dir()?;
-        let csv_path = "data/BF606F_new.csv";
-        let pcap_path = "data/USD_Fwd_data.pcap";
+        let csv_path = "../spec/data/BF606F_new.7z";
+        let pcap_path = std::env::temp_dir().join("payload_parser_test.pcap");
+        write_fixture(csv_path, &pcap_path, 600)?;
 
         // Jay
         // In this case, if the csv file or the pcap file does not exist, the program will print an error message and return Ok(()).
@@ -156,12 +212,12 @@ mod tests {
         if !std::path::Path::new(csv_path).exists() {
             anyhow::bail!("CSV file not found: {}, current_path: {}", csv_path, current_dir.display());
         }
-        if !std::path::Path::new(pcap_path).exists() {
-            anyhow::bail!("PCAP file not found: {}, current_path: {}", pcap_path, current_dir.display());
+        if !pcap_path.exists() {
+            anyhow::bail!("PCAP file not found: {}, current_path: {}", pcap_path.display(), current_dir.display());
         }
 
         let fields = PayloadField::load_from_csv(csv_path)?;
-        let mut capture = Capture::from_file(pcap_path)?;
+        let mut capture = Capture::from_file(&pcap_path)?;
 
         let mut results = Vec::new();
         let mut processed_count = 0;
@@ -181,6 +237,9 @@ mod tests {
                 // Are you sure you would remenber where you have to change in your code?
                 // Every line of code should be written under consideration that you have to maintain, fix, modify, or extend it in the future.
                 if let Some(parsed_value) = parse_packet(&packet, &fields, 8) {
+                    // the same field, by name (bytes_to_f64 is off in the last digits)
+                    let decoded = crate::messages::B606F::decode(&packet.data[42..])?;
+                    assert!(matches!(parsed_value, ParsedValue::Double(v) if approx::relative_eq!(v, decoded.ask_level_1_price)));
                     results.push(parsed_value);
                     processed_count += 1;
 
@@ -191,6 +250,7 @@ mod tests {
             }
         }
 
+        std::fs::remove_file(&pcap_path)?;
         assert!(!results.is_empty());
         println!("\nParsed Values:");
         for (i, value) in results.iter().enumerate() {
@@ -208,6 +268,24 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_filter_on_fields() -> anyhow::Result<()> {
+        let fields = PayloadField::load_from_csv("../spec/data/BF606F_new.7z")?;
+        let msgs = (0..10)
+            .map(|i| {
+                let payload = b606f_payload(&fields, i, "KR4165N30007", 100.0 + i as f64 * 0.25)?.build();
+                Ok(KrxMsg::new_from_payload(20240927, &payload, None, None).unwrap())
+            })
+            .collect::<anyhow::Result<Vec<KrxMsg>>>()?;
+
+        let layout = std::sync::Arc::new(FieldLayout::new("B606F", fields));
+        let filter = common::filter::Filter::parse(r#"field("Ask Level 1 price") in 100.5..101.5 and field("ISIN Code") ^= "KR4165""#)?
+            .with_fields(layout.clone())?;
+        assert_eq!(msgs.iter().filter(|msg| filter.matches_msg(msg)).count(), 4);
+        assert!(common::filter::Filter::parse(r#"field("Ask price") > 1"#)?.with_fields(layout).is_err());
+        Ok(())
+    }
+
     // json 파일을 STREAM 으로 읽어서 Payload FIELD 파싱
 
     #[test]
@@ -216,25 +294,23 @@ mod tests {
         const FIELD_INDEX: usize = 8;
         const MAX_SAMPLES: usize = 10;
 
-        // 테스트 데이터 준비
-        let json_path = "data/multiasset_db.krx_msg.json";
-        let csv_path = "data/BF606F_new.csv";
-
-        // 파일 존재 확인
-        if !std::path::Path::new(json_path).exists() {
-            anyhow::bail!("JSON file not found: {}", json_path);
-        }
-        if !std::path::Path::new(csv_path).exists() {
-            anyhow::bail!("CSV file not found: {}", csv_path);
-        }
+        // 테스트 데이터 준비: mongoexport 형식의 B606F, KR4165 와 KR4175 번갈아
+        let json_path = std::env::temp_dir().join("payload_parser_stream_test.json");
+        let csv_path = "../spec/data/BF606F_new.7z";
+        let fields = PayloadField::load_from_csv(csv_path)?;
+        let dump = (0..40)
+            .map(|i| {
+                let instcode = if i % 2 == 0 { "KR4165N30007" } else { "KR4175N30002" };
+                let payload = b606f_payload(&fields, i, instcode, 100.0 + i as f64 * 0.05)?.build();
+                Ok(KrxMsg::new_from_payload(20240927, &payload, None, None)?.to_extended_json()?)
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        std::fs::write(&json_path, serde_json::to_vec(&dump)?)?;
 
         // 파일 및 필드 로드
-        let file = std::fs::File::open(json_path)?;
+        let file = std::fs::File::open(&json_path)?;
         let reader = std::io::BufReader::new(file);
         let mut stream_reader = JsonStreamReader::new(reader);
-        
-        let fields = PayloadField::load_from_csv(csv_path)
-            .map_err(|e| anyhow::anyhow!("Failed to load CSV: {}", e.to_string()))?;
 
         // 결과 저장용 벡터
         let mut parsed_values = Vec::new();
@@ -246,7 +322,7 @@ mod tests {
 
             if krx_msg.instcode
                 .as_ref()
-                .map_or(false, |code| code.starts_with(INST_CODE_PREFIX))
+                .is_some_and(|code| code.starts_with(INST_CODE_PREFIX))
             {
                 if let Some(parsed_value) = parse_json_db(&krx_msg, &fields, FIELD_INDEX) {
                     parsed_values.push((krx_msg.instcode.clone(), parsed_value));
@@ -254,8 +330,13 @@ mod tests {
             }
         }
 
-        // 결과 검증
-        assert!(!parsed_values.is_empty(), "No values were parsed");
+        // 결과 검증: 홀수 번째 메시지의 Ask Level 1 price
+        std::fs::remove_file(&json_path)?;
+        assert_eq!(parsed_values.len(), MAX_SAMPLES);
+        for (i, (_, value)) in parsed_values.iter().enumerate() {
+            let expected = 100.0 + (2 * i + 1) as f64 * 0.05;
+            assert!(matches!(value, ParsedValue::Double(v) if approx::relative_eq!(*v, expected)), "{}: {}", i, value);
+        }
         
         // 결과 출력 (디버깅용)
         for (instcode, value) in parsed_values {
@@ -0,0 +1,146 @@
+// KRX field specs: the rows of a spec CSV and the specs shipped with the repo.
+// Kept free of the capture and database stack so that build scripts (dw/build.rs) can read specs too.
+use std::io;
+use std::ops::Range;
+use csv::ReaderBuilder;
+use encoding_rs::EUC_KR;
+use sevenz_rust::{Password, SevenZReader};
+
+/// KRX specs shipped in data/, compiled into the crate
+/// * B606F - BF606F, derivatives quote (5 levels)
+pub const KRX_SPECS: &[(&str, &[u8])] = &[("B606F", include_bytes!("../data/BF606F_new.7z").as_slice())];
+
+pub const SEVENZ_MAGIC: &[u8] = &[b'7', b'z', 0xbc, 0xaf, 0x27, 0x1c];
+
+/// One row of a KRX spec CSV
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PayloadField {
+   pub korean_name: String,
+   pub item_name: String,
+   pub sub_section: String,
+   pub data_type: String,
+   pub length: i32,
+   pub cumulative_length: i32,
+   pub start_point: i32,
+}
+
+// Jay: why not implment std::fmt::Display for PayloadField
+impl std::fmt::Display for PayloadField {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "PayloadFiled")?;
+        writeln!(f, "  Korean name: {}", self.korean_name)?;
+        writeln!(f, "  Item name: {}", self.item_name)?;
+        writeln!(f, "  Sub section: {}", self.sub_section)?;
+        writeln!(f, "  Data type: {}", self.data_type)?;
+        writeln!(f, "  Length: {}", self.length)?;
+        writeln!(f, "  Cumulative length: {}", self.cumulative_length)?;
+        writeln!(f, "  Start point: {}", self.start_point)
+    }
+}
+
+impl PayloadField {
+    /// A spec CSV (EUC-KR, with a header row) already in memory and decompressed
+    pub fn from_csv_bytes(bytes: &[u8]) -> Result<Vec<PayloadField>, std::io::Error> {
+        // EUC-KR에서 UTF-8로 변환
+        let (cow, _, _) = EUC_KR.decode(bytes);
+
+        // CSV 파서 설정
+        let mut rdr = ReaderBuilder::new()
+            .has_headers(true)  // 헤더 처리 추가
+            .from_reader(cow.as_bytes());
+
+        let mut payload_fields = Vec::new();
+
+        for result in rdr.records() {
+            let record = result?;
+            // Jay
+            // run 'cargo clippy' before push
+            let length = record.get(4)
+                .map(|s| if s.trim().is_empty() { "0" } else { s.trim() })
+                .unwrap_or("0")
+                .parse::<i32>()
+                .unwrap_or(0);
+
+            let cumulative_length = record.get(5)
+                .map(|s| if s.trim().is_empty() { "0" } else { s.trim() })
+                .unwrap_or("0")
+                .parse::<i32>()
+                .unwrap_or(0);
+
+            let start_point = record.get(6)
+                .map(|s| if s.trim().is_empty() { "0" } else { s.trim() })
+                .unwrap_or("0")
+                .parse::<i32>()
+                .unwrap_or(0);
+
+            let field = PayloadField {
+                korean_name: record.get(0).unwrap_or("").trim().to_string(),
+                item_name: record.get(1).unwrap_or("").trim().to_string(),
+                sub_section: record.get(2).unwrap_or("").trim().to_string(),
+                data_type: record.get(3).unwrap_or("").trim().to_string(),
+                length,
+                cumulative_length,
+                start_point,
+            };
+
+            payload_fields.push(field);
+        }
+
+        Ok(payload_fields)
+    }
+
+    /// A spec in memory, plain or 7z like the ones in `KRX_SPECS`
+    pub fn from_spec_bytes(bytes: &[u8]) -> Result<Vec<PayloadField>, std::io::Error> {
+        if bytes.starts_with(SEVENZ_MAGIC) {
+            PayloadField::from_csv_bytes(&un7z(bytes)?)
+        } else {
+            PayloadField::from_csv_bytes(bytes)
+        }
+    }
+
+    /// Byte range of the field in the payload
+    pub fn range(&self) -> Range<usize> {
+        self.start_point as usize..self.cumulative_length as usize
+    }
+}
+
+/// The first file of a 7z archive in memory
+pub fn un7z(bytes: &[u8]) -> io::Result<Vec<u8>> {
+    let error = |e: sevenz_rust::Error| match e {
+        sevenz_rust::Error::Io(e, _) => e,
+        e => io::Error::new(io::ErrorKind::InvalidData, e.to_string()),
+    };
+    let mut archive = SevenZReader::new(io::Cursor::new(bytes), bytes.len() as u64, Password::empty()).map_err(error)?;
+    let mut decompressed = Vec::new();
+    let mut found = false;
+    archive
+        .for_each_entries(|entry, reader| {
+            if entry.is_directory() {
+                return Ok(true);
+            }
+            found = true;
+            reader.read_to_end(&mut decompressed)?;
+            Ok(false)
+        })
+        .map_err(error)?;
+    if !found {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "7z archive has no file"));
+    }
+    Ok(decompressed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_krx_specs() -> io::Result<()> {
+        for (trcode, spec) in KRX_SPECS {
+            let fields = PayloadField::from_spec_bytes(spec)?;
+            assert_eq!(fields.last().map(|field| field.item_name.as_str()), Some("End Keyword"), "{}", trcode);
+        }
+        let b606f = PayloadField::from_spec_bytes(KRX_SPECS[0].1)?;
+        assert_eq!(b606f.iter().map(|field| field.cumulative_length).max(), Some(324));
+        Ok(())
+    }
+}